@@ -0,0 +1,375 @@
+//! Camera animation: keyframed camera parameters, interpolated between keyframes,
+//! and rendering the frames.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::camera::{Builder, Camera};
+use crate::geometry::hittable::HittableList;
+use crate::point::Point3;
+use crate::quaternion::Quaternion;
+use crate::vec3::{cross, Vector3};
+
+/// How the camera moves between keyframes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// straight between keyframes, turning at constant speed
+    Linear,
+    /// a smooth curve through every keyframe, without sudden changes of speed at them
+    CatmullRom,
+}
+
+/// The camera parameters at one point in time, in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    time: f64,
+    lookfrom: Point3,
+    lookat: Point3,
+    vup: Vector3,
+    vfov: f64,
+    focus_dist: f64,
+    defocus_angle: f64,
+}
+
+impl Keyframe {
+    /// looking from `lookfrom` at `lookat` with y up, a 90 degree field of view and
+    /// everything in focus, focused at `lookat`
+    pub fn new(time: f64, lookfrom: &Point3, lookat: &Point3) -> Self {
+        Self {
+            time,
+            lookfrom: *lookfrom,
+            lookat: *lookat,
+            vup: Vector3::new(0.0, 1.0, 0.0),
+            vfov: 90.0,
+            focus_dist: (*lookat - *lookfrom).length(),
+            defocus_angle: 0.0,
+        }
+    }
+
+    /// which way is up for the camera
+    pub fn set_vup(&mut self, vup: &Vector3) -> &mut Self {
+        self.vup = *vup;
+        self
+    }
+
+    /// vertical field of view, in degrees
+    pub fn set_vfov(&mut self, vfov: f64) -> &mut Self {
+        self.vfov = vfov;
+        self
+    }
+
+    /// the distance from the camera to the plane in perfect focus
+    pub fn set_focus_dist(&mut self, focus_dist: f64) -> &mut Self {
+        self.focus_dist = focus_dist;
+        self
+    }
+
+    /// the angle, in degrees, the lens aperture subtends from a pixel; 0 keeps everything
+    /// in focus
+    pub fn set_defocus_angle(&mut self, defocus_angle: f64) -> &mut Self {
+        self.defocus_angle = defocus_angle;
+        self
+    }
+
+    /// the camera's orientation, turning the x, y and z axes to its right, up and back
+    fn orientation(&self) -> Quaternion {
+        let back = (self.lookfrom - self.lookat).normalize().unwrap();
+        let right = cross(&self.vup, &back).normalize().unwrap();
+        let up = cross(&back, &right);
+
+        Quaternion::from_basis(&right, &up, &back)
+    }
+}
+
+/// A camera moving through keyframes. The position, field of view and focus are
+/// interpolated on their own; the orientation is interpolated as a quaternion, so the
+/// camera turns evenly and never rolls unless the keyframes do, with lookat kept at the
+/// interpolated distance in front of it.
+pub struct Animation {
+    keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
+}
+
+impl Animation {
+    /// an animation without keyframes, moving between them as `interpolation` says
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            keyframes: vec![],
+            interpolation,
+        }
+    }
+
+    /// keyframes can be added in any order
+    pub fn add_keyframe(&mut self, keyframe: &Keyframe) -> &mut Self {
+        let index = self
+            .keyframes
+            .partition_point(|other| other.time <= keyframe.time);
+
+        self.keyframes.insert(index, *keyframe);
+        self
+    }
+
+    /// The camera parameters at `time`, held at the first and last keyframes outside their
+    /// range.
+    ///
+    /// # Panics
+    ///
+    /// If no keyframe has been added.
+    pub fn keyframe_at(&self, time: f64) -> Keyframe {
+        let keyframes = &self.keyframes;
+        assert!(!keyframes.is_empty(), "an animation needs a keyframe");
+        let last = keyframes.len() - 1;
+
+        if time <= keyframes[0].time {
+            return Keyframe {
+                time,
+                ..keyframes[0]
+            };
+        }
+
+        if time >= keyframes[last].time {
+            return Keyframe {
+                time,
+                ..keyframes[last]
+            };
+        }
+
+        // the segment from keyframe k to k + 1, with its neighbours repeated at the ends
+        let k = keyframes.partition_point(|keyframe| keyframe.time <= time) - 1;
+        let around = [k.saturating_sub(1), k, k + 1, usize::min(k + 2, last)];
+        let [k0, k1, k2, k3] = around.map(|index| &keyframes[index]);
+        let t = (time - k1.time) / (k2.time - k1.time);
+
+        let blend = |value: fn(&Keyframe) -> f64| match self.interpolation {
+            Interpolation::Linear => (1.0 - t) * value(k1) + t * value(k2),
+            Interpolation::CatmullRom => {
+                catmull_rom([value(k0), value(k1), value(k2), value(k3)], t)
+            }
+        };
+
+        let lookfrom = Point3::new(
+            blend(|keyframe| keyframe.lookfrom.x()),
+            blend(|keyframe| keyframe.lookfrom.y()),
+            blend(|keyframe| keyframe.lookfrom.z()),
+        );
+        let distance = blend(|keyframe| (keyframe.lookat - keyframe.lookfrom).length());
+
+        // keep neighbouring orientations in one hemisphere, so they turn the short way
+        let mut orientations = around.map(|index| keyframes[index].orientation());
+        for index in 1..4 {
+            if orientations[index].dot(&orientations[index - 1]) < 0.0 {
+                orientations[index] = -orientations[index];
+            }
+        }
+
+        let [q0, q1, q2, q3] = orientations;
+        let orientation = match self.interpolation {
+            Interpolation::Linear => Quaternion::slerp(&q1, &q2, t),
+            Interpolation::CatmullRom => Quaternion::squad(&q0, &q1, &q2, &q3, t),
+        };
+        let back = orientation.rotate(&Vector3::new(0.0, 0.0, 1.0));
+
+        Keyframe {
+            time,
+            lookfrom,
+            lookat: lookfrom - distance * back,
+            vup: orientation.rotate(&Vector3::new(0.0, 1.0, 0.0)),
+            vfov: blend(|keyframe| keyframe.vfov),
+            focus_dist: blend(|keyframe| keyframe.focus_dist),
+            defocus_angle: f64::max(blend(|keyframe| keyframe.defocus_angle), 0.0),
+        }
+    }
+
+    /// the camera `builder` describes, posed as at `time`; panics like `keyframe_at`
+    pub fn camera_at(&self, builder: &Builder, time: f64) -> Camera {
+        let keyframe = self.keyframe_at(time);
+
+        builder
+            .clone()
+            .set_lookfrom(&keyframe.lookfrom)
+            .set_lookat(&keyframe.lookat)
+            .set_vup(&keyframe.vup)
+            .set_vfov(keyframe.vfov)
+            .set_focus_dist(keyframe.focus_dist)
+            .set_defocus_angle(keyframe.defocus_angle)
+            .build()
+    }
+
+    /// Renders `frames` frames evenly spread from the first keyframe to the last, both
+    /// included, into `directory` as frame_0001.ppm, frame_0002.ppm and so on, calling
+    /// `frame_done` with the number of every frame and its file once it's saved. A
+    /// checkpoint set on `builder` is kept per frame, next to its path as for stereo
+    /// renders, so an interrupted animation resumes from the frame it was on. Fails
+    /// without keyframes.
+    pub fn render_frames(
+        &self,
+        builder: &Builder,
+        world: Arc<HittableList>,
+        frames: u32,
+        directory: &str,
+        mut frame_done: impl FnMut(u32, &Path),
+    ) -> io::Result<()> {
+        let (Some(first), Some(last)) = (self.keyframes.first(), self.keyframes.last()) else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the animation has no keyframes",
+            ));
+        };
+        let (start, end) = (first.time, last.time);
+
+        fs::create_dir_all(directory)?;
+
+        let mut builder = builder.clone();
+        let checkpoint = builder.checkpoint().cloned();
+
+        for frame in 0..frames {
+            let time = if frames > 1 {
+                start + (end - start) * frame as f64 / (frames - 1) as f64
+            } else {
+                start
+            };
+
+            if let Some(checkpoint) = &checkpoint {
+                builder.set_checkpoint(checkpoint.for_frame(frame + 1));
+            }
+
+            let path = Path::new(directory).join(format!("frame_{:04}.ppm", frame + 1));
+
+            self.camera_at(&builder, time)
+                .render(Arc::clone(&world))?
+                .save(&path)?;
+
+            frame_done(frame + 1, &path);
+        }
+
+        Ok(())
+    }
+}
+
+/// uniform Catmull-Rom spline through p1 (t = 0) and p2 (t = 1)
+fn catmull_rom(p: [f64; 4], t: f64) -> f64 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    0.5 * (2.0 * p[1]
+        + (p[2] - p[0]) * t
+        + (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]) * t2
+        + (3.0 * p[1] - p[0] - 3.0 * p[2] + p[3]) * t3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::Checkpoint;
+
+    fn turntable(interpolation: Interpolation) -> Animation {
+        let mut animation = Animation::new(interpolation);
+        let center = Point3::new(0.0, 0.0, 0.0);
+
+        for (time, (x, z)) in [(0.0, 4.0), (4.0, 0.0), (0.0, -4.0), (-4.0, 0.0)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut keyframe = Keyframe::new(time as f64, &Point3::new(x, 0.0, z), &center);
+            keyframe.set_vfov(30.0 + 10.0 * time as f64);
+            animation.add_keyframe(&keyframe);
+        }
+
+        animation
+    }
+
+    #[test]
+    fn interpolation_passes_through_the_keyframes() {
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let animation = turntable(interpolation);
+
+            for keyframe in &animation.keyframes {
+                let at = animation.keyframe_at(keyframe.time);
+
+                assert!((at.lookfrom - keyframe.lookfrom).length() < 1e-9);
+                assert!((at.lookat - keyframe.lookat).length() < 1e-9);
+                let up = keyframe.orientation().rotate(&Vector3::new(0.0, 1.0, 0.0));
+                assert!((at.vup - up).length() < 1e-9);
+                assert!(f64::abs(at.vfov - keyframe.vfov) < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn the_camera_keeps_looking_at_the_target_in_between() {
+        let animation = turntable(Interpolation::Linear);
+        let at = animation.keyframe_at(0.5);
+
+        // halfway between two keyframes looking at the origin, so does the camera
+        let to_target = (at.lookat - at.lookfrom).normalize().unwrap();
+        let to_origin = (-at.lookfrom).normalize().unwrap();
+        assert!((to_target - to_origin).length() < 1e-9);
+        assert!(f64::abs(at.vfov - 35.0) < 1e-9);
+
+        // the smooth curve bulges out toward the circle the keyframes lie on
+        let smooth = turntable(Interpolation::CatmullRom).keyframe_at(1.5);
+        let straight = animation.keyframe_at(1.5);
+        let radius = |keyframe: &Keyframe| f64::hypot(keyframe.lookfrom.x(), keyframe.lookfrom.z());
+        assert!(radius(&smooth) > radius(&straight));
+    }
+
+    #[test]
+    fn animations_without_keyframes_render_nothing() {
+        let directory =
+            std::env::temp_dir().join(format!("empty-animation-{}", std::process::id()));
+        let error = Animation::new(Interpolation::Linear)
+            .render_frames(
+                &Builder::new(),
+                Arc::new(HittableList::new()),
+                10,
+                directory.to_str().unwrap(),
+                |_, _| {},
+            )
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(!directory.exists());
+    }
+
+    #[test]
+    fn every_frame_resumes_from_its_own_checkpoint() {
+        let directory = std::env::temp_dir().join(format!("animation-{}", std::process::id()));
+        let mut checkpoint = Checkpoint::new(directory.join("render.ckpt").to_str().unwrap());
+        let mut builder = Builder::new();
+        builder
+            .set_image_width(4)
+            .set_image_aspect_ratio(1.0)
+            .set_samples_per_pixel(1)
+            .set_seed(3)
+            .set_checkpoint(checkpoint.clone());
+        let world = Arc::new(HittableList::new());
+        let animation = turntable(Interpolation::Linear);
+
+        let render = |builder: &Builder| {
+            let mut done = vec![];
+            animation
+                .render_frames(
+                    builder,
+                    world.clone(),
+                    3,
+                    directory.to_str().unwrap(),
+                    |frame, path| {
+                        done.push(frame);
+                        assert!(path.exists());
+                    },
+                )
+                .map(|_| done)
+        };
+
+        assert_eq!(render(&builder).unwrap(), [1, 2, 3]);
+        assert!(directory.join("render.ckpt.frame_0002").exists());
+
+        // every frame finds a checkpoint made for its own camera
+        builder.set_checkpoint(checkpoint.set_resume(true).clone());
+        assert_eq!(render(&builder).unwrap(), [1, 2, 3]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,462 @@
+//! Arbitrary output variables: what the camera sees at the first hit besides light, for
+//! denoising and compositing.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::color::Color;
+use crate::image::exr::{Exr, PixelType};
+use crate::image::{Format, Image};
+use crate::point::Point3;
+use crate::vec3::Vector3;
+
+/// An auxiliary image rendered alongside the beauty pass. All but alpha are averaged over
+/// the samples of a pixel that hit something; IDs take the one most samples saw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// the color of the first surface hit, without lighting; the background where none is
+    Albedo,
+    /// the shading normal in world space, facing the camera
+    Normal,
+    /// distance from the camera: along its axis for planar projections, along the ray
+    /// for fisheye and equirectangular ones
+    Depth,
+    /// the world position of the first hit
+    Position,
+    /// surface coordinates, u in red and v in green
+    Uv,
+    /// number of the material in the scene, from 1, 0 for the background
+    MaterialId,
+    /// number of the object in the scene, from 1, 0 for the background
+    ObjectId,
+    /// coverage: the fraction of samples that hit something
+    Alpha,
+}
+
+impl Aov {
+    /// every AOV, in the order they are listed in
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::Uv,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::Alpha,
+    ];
+
+    /// the name of the EXR layer or file suffix
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Alpha => "alpha",
+        }
+    }
+
+    /// the AOV called `name`, as `name` gives it
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    /// the image as colors to look at, for formats that only hold [0, 1]: normals and
+    /// positions mapped into the unit cube, depth scaled by the farthest, IDs in colors
+    /// of their own
+    fn visualize(&self, image: &Image) -> Image {
+        let pixels = image.pixels();
+
+        match self {
+            Aov::Albedo | Aov::Uv | Aov::Alpha => image.clone(),
+            Aov::Normal => map(image, |n| 0.5 * (n + Color::new(1.0, 1.0, 1.0))),
+            Aov::Depth | Aov::Position => {
+                let (low, high) =
+                    pixels
+                        .iter()
+                        .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), pixel| {
+                            let values = [pixel.x(), pixel.y(), pixel.z()];
+                            let least = values.iter().copied().fold(f64::INFINITY, f64::min);
+                            let most = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+                            (f64::min(low, least), f64::max(high, most))
+                        });
+                let low = if *self == Aov::Depth { 0.0 } else { low };
+                let scale = if high > low { 1.0 / (high - low) } else { 0.0 };
+
+                map(image, |p| (p - Color::new(low, low, low)) * scale)
+            }
+            Aov::MaterialId | Aov::ObjectId => map(image, |id| id_color(id.x() as u32)),
+        }
+    }
+}
+
+fn map(image: &Image, f: impl Fn(Color) -> Color) -> Image {
+    Image::from_fn(image.width(), image.height(), |i, j| f(image.pixel(i, j)))
+}
+
+/// a color of its own for every ID, black for none
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::new_default();
+    }
+
+    // the golden ratio spreads neighbouring IDs far apart around the hue circle
+    let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+    let channel = |offset: f64| {
+        let distance = f64::abs((hue - offset).rem_euclid(6.0) - 3.0);
+        f64::clamp(distance - 1.0, 0.0, 1.0)
+    };
+
+    Color::new(channel(0.0), channel(4.0), channel(2.0))
+}
+
+/// What one camera sample saw: the albedo, and the rest of the first hit if there was one.
+pub(crate) struct AovSample {
+    pub albedo: Color,
+    pub hit: Option<AovHit>,
+}
+
+pub(crate) struct AovHit {
+    pub normal: Vector3,
+    pub depth: f64,
+    pub position: Point3,
+    pub uv: (f64, f64),
+    pub material_id: u32,
+    pub object_id: u32,
+}
+
+/// Adds up AOV samples pixel by pixel. Unlike the film there is no reconstruction filter,
+/// which would blur IDs and edges into each other.
+pub(crate) struct AovFilm {
+    width: u32,
+    height: u32,
+    samples: Vec<u32>,
+    hits: Vec<u32>,
+    albedo: Vec<Color>,
+    normal: Vec<Vector3>,
+    depth: Vec<f64>,
+    position: Vec<Point3>,
+    uv: Vec<(f64, f64)>,
+    material_ids: Vec<Vec<(u32, u32)>>, // every ID seen with the samples that saw it
+    object_ids: Vec<Vec<(u32, u32)>>,
+}
+
+impl AovFilm {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+
+        Self {
+            width,
+            height,
+            samples: vec![0; size],
+            hits: vec![0; size],
+            albedo: vec![Color::new_default(); size],
+            normal: vec![Vector3::new_default(); size],
+            depth: vec![0.0; size],
+            position: vec![Point3::new_default(); size],
+            uv: vec![(0.0, 0.0); size],
+            material_ids: vec![vec![]; size],
+            object_ids: vec![vec![]; size],
+        }
+    }
+
+    pub(crate) fn add(&mut self, i: u32, j: u32, sample: &AovSample) {
+        let index = (j * self.width + i) as usize;
+
+        self.samples[index] += 1;
+        self.albedo[index] += sample.albedo;
+
+        if let Some(hit) = &sample.hit {
+            self.hits[index] += 1;
+            self.normal[index] += hit.normal;
+            self.depth[index] += hit.depth;
+            self.position[index] += hit.position;
+            self.uv[index].0 += hit.uv.0;
+            self.uv[index].1 += hit.uv.1;
+            count(&mut self.material_ids[index], hit.material_id);
+            count(&mut self.object_ids[index], hit.object_id);
+        }
+    }
+
+    pub(crate) fn image(&self, aov: Aov) -> Image {
+        Image::from_fn(self.width, self.height, |i, j| {
+            let index = (j * self.width + i) as usize;
+            let per_sample = 1.0 / f64::max(self.samples[index] as f64, 1.0);
+            let per_hit = 1.0 / f64::max(self.hits[index] as f64, 1.0);
+            let gray = |value: f64| Color::new(value, value, value);
+
+            match aov {
+                Aov::Albedo => self.albedo[index] * per_sample,
+                Aov::Normal => self.normal[index] * per_hit,
+                Aov::Depth => gray(self.depth[index] * per_hit),
+                Aov::Position => self.position[index] * per_hit,
+                Aov::Uv => {
+                    let (u, v) = self.uv[index];
+                    Color::new(u * per_hit, v * per_hit, 0.0)
+                }
+                Aov::MaterialId => gray(most_common(&self.material_ids[index]) as f64),
+                Aov::ObjectId => gray(most_common(&self.object_ids[index]) as f64),
+                Aov::Alpha => gray(self.hits[index] as f64 * per_sample),
+            }
+        })
+    }
+
+    /// the sums of every pixel, for render checkpoints
+    pub(crate) fn write_to(&self, output: &mut impl Write) -> io::Result<()> {
+        for index in 0..self.samples.len() {
+            let (albedo, normal, position) =
+                (self.albedo[index], self.normal[index], self.position[index]);
+            let (u, v) = self.uv[index];
+
+            output.write_all(&self.samples[index].to_le_bytes())?;
+            output.write_all(&self.hits[index].to_le_bytes())?;
+
+            for value in [
+                albedo.x(),
+                albedo.y(),
+                albedo.z(),
+                normal.x(),
+                normal.y(),
+                normal.z(),
+                self.depth[index],
+                position.x(),
+                position.y(),
+                position.z(),
+                u,
+                v,
+            ] {
+                output.write_all(&value.to_le_bytes())?;
+            }
+
+            for ids in [&self.material_ids[index], &self.object_ids[index]] {
+                output.write_all(&(ids.len() as u32).to_le_bytes())?;
+
+                for (id, times) in ids {
+                    output.write_all(&id.to_le_bytes())?;
+                    output.write_all(&times.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read_from(&mut self, input: &mut impl Read) -> io::Result<()> {
+        let mut bytes = [0; 2 * 4 + 12 * 8];
+
+        for index in 0..self.samples.len() {
+            input.read_exact(&mut bytes)?;
+
+            let value = |k: usize| {
+                let start = 8 + 8 * k;
+                f64::from_le_bytes(bytes[start..start + 8].try_into().unwrap())
+            };
+
+            self.samples[index] = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+            self.hits[index] = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+            self.albedo[index] = Color::new(value(0), value(1), value(2));
+            self.normal[index] = Vector3::new(value(3), value(4), value(5));
+            self.depth[index] = value(6);
+            self.position[index] = Point3::new(value(7), value(8), value(9));
+            self.uv[index] = (value(10), value(11));
+
+            for ids in [&mut self.material_ids[index], &mut self.object_ids[index]] {
+                *ids = (0..read_u32(input)?)
+                    .map(|_| Ok((read_u32(input)?, read_u32(input)?)))
+                    .collect::<io::Result<_>>()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn count(counts: &mut Vec<(u32, u32)>, id: u32) {
+    match counts.iter_mut().find(|(seen, _)| *seen == id) {
+        Some((_, times)) => *times += 1,
+        None => counts.push((id, 1)),
+    }
+}
+
+/// the ID seen most, the first seen of those tied; 0 for none
+fn most_common(counts: &[(u32, u32)]) -> u32 {
+    counts
+        .iter()
+        .fold(
+            (0, 0),
+            |best, &(id, times)| {
+                if times > best.1 {
+                    (id, times)
+                } else {
+                    best
+                }
+            },
+        )
+        .0
+}
+
+/// A rendered image with the AOVs asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layers {
+    /// the rendered light
+    pub beauty: Image,
+    /// every AOV asked for, in the order asked
+    pub aovs: Vec<(Aov, Image)>,
+}
+
+impl Layers {
+    /// the image of `aov`, if it was asked for
+    pub fn aov(&self, aov: Aov) -> Option<&Image> {
+        self.aovs
+            .iter()
+            .find(|(kind, _)| *kind == aov)
+            .map(|(_, image)| image)
+    }
+
+    /// Writes an EXR as one file with a layer per AOV, in single precision floats since
+    /// IDs and positions need them. Other formats get the beauty image at `path` and every
+    /// AOV beside it, `image.png` having `image.albedo.png` and so on; formats that only
+    /// hold [0, 1] show the AOVs as colors rather than values.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let format = Format::from_path(path)?;
+
+        if format == Format::Exr {
+            let mut exr = Exr::new(self.beauty.width(), self.beauty.height());
+            exr.add_layer("", &self.beauty)?;
+
+            if !self.aovs.is_empty() {
+                exr.set_pixel_type(PixelType::Float);
+            }
+
+            for (aov, image) in &self.aovs {
+                exr.add_layer(aov.name(), image)?;
+            }
+
+            return exr.save(path);
+        }
+
+        self.beauty.save(path)?;
+
+        for (aov, image) in &self.aovs {
+            let path = aov_path(path, *aov);
+
+            if format.holds_any_float() {
+                image.save(&path)?;
+            } else {
+                aov.visualize(image).save(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `path` with the AOV's name before the extension
+fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!(
+            "{}.{}.{}",
+            stem,
+            aov.name(),
+            extension.to_string_lossy()
+        )),
+        None => path.with_file_name(format!("{}.{}", stem, aov.name())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_average_hits_and_keep_the_most_common_id() {
+        let mut film = AovFilm::new(1, 1);
+        let hit = |object_id: u32, depth: f64| AovSample {
+            albedo: Color::new(0.5, 0.5, 0.5),
+            hit: Some(AovHit {
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                depth,
+                position: Point3::new(depth, 0.0, 0.0),
+                uv: (0.25, 0.75),
+                material_id: 1,
+                object_id,
+            }),
+        };
+
+        film.add(0, 0, &hit(2, 1.0));
+        film.add(0, 0, &hit(3, 2.0));
+        film.add(0, 0, &hit(3, 3.0));
+        film.add(
+            0,
+            0,
+            &AovSample {
+                albedo: Color::new(1.0, 1.0, 1.0),
+                hit: None,
+            },
+        );
+
+        let pixel = |aov| film.image(aov).pixel(0, 0);
+
+        assert_eq!(pixel(Aov::Alpha), Color::new(0.75, 0.75, 0.75));
+        assert_eq!(pixel(Aov::Albedo), Color::new(0.625, 0.625, 0.625));
+        assert_eq!(pixel(Aov::Depth), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(pixel(Aov::Normal), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(pixel(Aov::Uv), Color::new(0.25, 0.75, 0.0));
+        assert_eq!(pixel(Aov::ObjectId), Color::new(3.0, 3.0, 3.0));
+        assert_eq!(pixel(Aov::MaterialId), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn films_read_back_what_they_wrote() {
+        let mut film = AovFilm::new(2, 1);
+        film.add(
+            1,
+            0,
+            &AovSample {
+                albedo: Color::new(0.5, 0.25, 1.0),
+                hit: Some(AovHit {
+                    normal: Vector3::new(0.0, 0.0, 1.0),
+                    depth: 2.0,
+                    position: Point3::new(1.0, 2.0, 3.0),
+                    uv: (0.5, 0.5),
+                    material_id: 4,
+                    object_id: 7,
+                }),
+            },
+        );
+
+        let mut bytes = vec![];
+        film.write_to(&mut bytes).unwrap();
+
+        let mut loaded = AovFilm::new(2, 1);
+        loaded.read_from(&mut bytes.as_slice()).unwrap();
+
+        for aov in Aov::ALL {
+            assert_eq!(loaded.image(aov).pixels(), film.image(aov).pixels());
+        }
+    }
+
+    #[test]
+    fn aovs_go_beside_the_image() {
+        assert_eq!(
+            aov_path(Path::new("out/render.png"), Aov::ObjectId),
+            Path::new("out/render.object_id.png")
+        );
+        assert_eq!(Aov::from_name("uv"), Some(Aov::Uv));
+        assert_eq!(Aov::from_name("beauty"), None);
+    }
+}
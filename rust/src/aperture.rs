@@ -0,0 +1,239 @@
+//! The shape of the lens opening, which out of focus highlights take.
+
+use std::hash::Hasher;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::image::Image;
+use crate::util::{degrees_to_radians, hash_f64s, random_double_2d, PI};
+use crate::vec3::random_in_unit_disk;
+
+enum Shape {
+    Circle,
+    Polygon(u32),
+    /// transmission of each texel, row by row, and its running sum for sampling
+    Image {
+        width: u32,
+        height: u32,
+        cdf: Vec<f64>,
+    },
+}
+
+/// Shape of the lens opening, which is what out of focus highlights (bokeh) take the
+/// shape of. Points are sampled within [-1, 1]², the camera scaling them to the aperture
+/// radius given by the defocus angle.
+pub struct Aperture {
+    shape: Shape,
+    rotation: f64,
+    squeeze: f64,
+}
+
+impl Aperture {
+    /// a round opening, as formed by a lens wide open
+    pub fn circular() -> Self {
+        Self {
+            shape: Shape::Circle,
+            rotation: 0.0,
+            squeeze: 1.0,
+        }
+    }
+
+    /// a regular polygon, as formed by the straight blades of an iris diaphragm
+    pub fn polygonal(blades: u32) -> Self {
+        Self {
+            shape: Shape::Polygon(u32::max(blades, 3)),
+            rotation: 0.0,
+            squeeze: 1.0,
+        }
+    }
+
+    /// an arbitrary mask, brighter texels letting more light through; `weights` holds
+    /// `width` x `height` values row by row, top row first
+    pub fn from_weights(width: u32, height: u32, weights: &[f64]) -> io::Result<Self> {
+        if width == 0 || height == 0 || weights.len() != (width * height) as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "aperture weights don't match the size",
+            ));
+        }
+
+        let mut total = 0.0;
+        let cdf: Vec<f64> = weights
+            .iter()
+            .map(|weight| {
+                total += f64::max(*weight, 0.0);
+                total
+            })
+            .collect();
+
+        if total <= 0.0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "aperture lets no light through",
+            ));
+        }
+
+        Ok(Self {
+            shape: Shape::Image { width, height, cdf },
+            rotation: 0.0,
+            squeeze: 1.0,
+        })
+    }
+
+    /// a mask read from an image in any format [`Image::load`] reads, using the linear
+    /// brightness of every pixel
+    pub fn from_image(path: &str) -> io::Result<Self> {
+        let image = Image::load(Path::new(path))?;
+        let weights: Vec<f64> = image
+            .pixels()
+            .iter()
+            .map(|pixel| (pixel.x() + pixel.y() + pixel.z()) / 3.0)
+            .collect();
+
+        Self::from_weights(image.width(), image.height(), &weights)
+    }
+
+    /// turns the shape counterclockwise, in degrees
+    pub fn set_rotation(&mut self, rotation: f64) -> &mut Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// narrows the aperture horizontally by `squeeze`, which gives the tall oval bokeh of
+    /// anamorphic lenses
+    pub fn set_squeeze(&mut self, squeeze: f64) -> &mut Self {
+        self.squeeze = f64::max(squeeze, 1e-3);
+        self
+    }
+
+    /// a point uniformly distributed over the opening, or in proportion to the
+    /// transmission for image masks
+    pub(crate) fn sample(&self) -> (f64, f64) {
+        let (x, y) = match &self.shape {
+            Shape::Circle => {
+                let p = random_in_unit_disk();
+                (p.x(), p.y())
+            }
+            Shape::Polygon(blades) => {
+                // one of the equal triangles between the center and an edge, then a
+                // point in it, both from the same two numbers
+                let (u, v) = random_double_2d();
+                let scaled = u * *blades as f64;
+                let edge = f64::min(f64::floor(scaled), (*blades - 1) as f64);
+                let along = f64::sqrt(scaled - edge);
+
+                let angle = 2.0 * PI / *blades as f64;
+                let (x0, y0) = (f64::cos(edge * angle), f64::sin(edge * angle));
+                let (x1, y1) = (
+                    f64::cos((edge + 1.0) * angle),
+                    f64::sin((edge + 1.0) * angle),
+                );
+
+                (
+                    along * ((1.0 - v) * x0 + v * x1),
+                    along * ((1.0 - v) * y0 + v * y1),
+                )
+            }
+            Shape::Image { width, height, cdf } => {
+                let (u, v) = random_double_2d();
+                let total = cdf[cdf.len() - 1];
+                let target = u * total;
+                let texel = usize::min(cdf.partition_point(|&sum| sum <= target), cdf.len() - 1);
+
+                // where the number fell within the texel places the point across it
+                let low = if texel == 0 { 0.0 } else { cdf[texel - 1] };
+                let across = ((target - low) / (cdf[texel] - low)).clamp(0.0, 1.0);
+
+                let column = (texel as u32 % width) as f64 + across;
+                let row = (texel as u32 / width) as f64 + v;
+                let size = u32::max(*width, *height) as f64;
+
+                // centered, keeping the aspect ratio, with the top row at the top
+                (
+                    (2.0 * column - *width as f64) / size,
+                    (*height as f64 - 2.0 * row) / size,
+                )
+            }
+        };
+
+        let (sin, cos) = f64::sin_cos(degrees_to_radians(self.rotation));
+
+        ((x * cos - y * sin) / self.squeeze, x * sin + y * cos)
+    }
+
+    /// feeds the shape, rotation and squeeze into `state`, for the camera's fingerprint
+    pub(crate) fn fingerprint(&self, state: &mut dyn Hasher) {
+        match &self.shape {
+            Shape::Circle => state.write(b"circle"),
+            Shape::Polygon(blades) => {
+                state.write(b"polygon");
+                state.write_u32(*blades);
+            }
+            Shape::Image { width, height, cdf } => {
+                state.write(b"image");
+                state.write_u32(*width);
+                state.write_u32(*height);
+                hash_f64s(state, cdf);
+            }
+        }
+
+        hash_f64s(state, &[self.rotation, self.squeeze]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn polygonal_samples_stay_inside_the_polygon() {
+        let mut aperture = Aperture::polygonal(5);
+        aperture.set_rotation(90.0);
+
+        // with the rotation, a corner points straight up and an edge lies flat below
+        let apothem = f64::cos(PI / 5.0);
+
+        for _ in 0..1000 {
+            let (x, y) = aperture.sample();
+
+            assert!(x * x + y * y <= 1.0 + 1e-9);
+            assert!(y >= -apothem - 1e-9);
+        }
+    }
+
+    #[test]
+    fn image_apertures_only_sample_where_light_gets_through() {
+        #[rustfmt::skip]
+        let aperture = Aperture::from_weights(2, 2, &[
+            0.0, 1.0,
+            0.0, 0.0,
+        ]).unwrap();
+
+        for _ in 0..1000 {
+            let (x, y) = aperture.sample();
+            assert!((0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y));
+        }
+    }
+
+    #[test]
+    fn image_apertures_load_like_images() {
+        let path = std::env::temp_dir().join(format!("aperture-{}.png", std::process::id()));
+        let image = Image::from_fn(2, 2, |i, j| match (i, j) {
+            (0, 1) => Color::new(1.0, 1.0, 1.0),
+            _ => Color::new_default(),
+        });
+        image.save(&path).unwrap();
+
+        let aperture = Aperture::from_image(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the bottom left pixel is the only one open
+        for _ in 0..1000 {
+            let (x, y) = aperture.sample();
+            assert!((-1.0..=0.0).contains(&x) && (-1.0..=0.0).contains(&y));
+        }
+
+        assert!(Aperture::from_image("aperture.bmp").is_err());
+    }
+}
@@ -1,17 +1,112 @@
+//! Cameras: where the image is taken from and how it is sampled and rendered.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::Hasher;
 use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::color::{write_color, Color};
+use crate::aov::{Aov, AovFilm, AovHit, AovSample, Layers};
+use crate::aperture::Aperture;
+use crate::checkpoint::{self, Checkpoint, Progress};
+use crate::color::{luminance, Color};
+use crate::film::Film;
+use crate::filter::{BoxFilter, Filter};
+use crate::firefly::RadianceClamp;
 use crate::geometry::hittable::{HitRecord, Hittable, HittableList};
+use crate::image::{Format, Image};
+use crate::integrator::{path::PathTracer, Integrator};
+use crate::lens::{LensSystem, RealisticLens};
 use crate::point::Point3;
+use crate::progressive::Progressive;
+use crate::projection::Projection;
 use crate::ray::Ray;
-use crate::util::{degrees_to_radians, random_double};
-use crate::util::{interval::Interval, INFINITY};
-use crate::vec3::{cross, random_in_unit_disk, Vector3};
+use crate::sampler::{Independent, PixelSample, PixelSampleSource, Sampler};
+use crate::scheduler::{Tile, WorkStealingQueue};
+use crate::stereo::Stereo;
+use crate::util::interval::Interval;
+use crate::util::{
+    degrees_to_radians, mix_seed, random_double, random_double_2d, with_sample_source, Fnv1a,
+    Pcg32, SampleSource, INFINITY,
+};
+use crate::vec3::{cross, dot, Vector3};
+
+/// samples taken at a time once adaptive sampling is past its minimum
+const ADAPTIVE_BATCH: u32 = 16;
+
+/// tiles per render thread between the points a checkpoint may be saved at
+const CHECKPOINT_BATCH: usize = 4;
+
+/// Takes samples for a pixel until the estimate of its mean luminance is within
+/// `relative_error` of the mean (one standard error), but never fewer than `min_samples`
+/// nor more than `max_samples` per pass.
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling {
+    min_samples: u32,
+    max_samples: u32,
+    relative_error: f64,
+}
+
+impl AdaptiveSampling {
+    /// at least two samples, so there is a variance to estimate
+    pub fn new(min_samples: u32, max_samples: u32, relative_error: f64) -> Self {
+        let min_samples = u32::max(min_samples, 2);
+
+        Self {
+            min_samples,
+            max_samples: u32::max(max_samples, min_samples),
+            relative_error,
+        }
+    }
+
+    /// `take(first, count)` traces `count` samples starting with sample `first`; returns
+    /// how many were taken
+    fn sample_pixel(&self, take: &mut impl FnMut(u32, u32) -> Vec<Color>) -> u32 {
+        let mut taken = 0;
+
+        // running mean and sum of squared deviations of the luminance (Welford)
+        let mut mean = 0.0;
+        let mut squared_deviations = 0.0;
+        let mut batch = self.min_samples;
+
+        loop {
+            for sample in take(taken, batch) {
+                taken += 1;
+
+                let value = luminance(&sample);
+                let delta = value - mean;
+                mean += delta / taken as f64;
+                squared_deviations += delta * (value - mean);
+            }
+
+            if taken >= self.max_samples {
+                break;
+            }
+
+            let variance = squared_deviations / (taken - 1) as f64;
+            let standard_error = f64::sqrt(variance / taken as f64);
+
+            // the floor keeps nearly black pixels from soaking up the whole budget
+            if standard_error <= self.relative_error * f64::max(mean, 1e-3) {
+                break;
+            }
+
+            batch = u32::min(ADAPTIVE_BATCH, self.max_samples - taken);
+        }
 
-const THREAED_NUMBER: usize = 4;
+        taken
+    }
+}
 
+/// Collects the settings of a [`Camera`]. The image size, the samples and depth and the
+/// view (`lookfrom`, `lookat`, `vup`, `vfov` and `focus_dist`) need setting; everything
+/// else has a working default.
+#[derive(Clone)]
 pub struct Builder {
     // image
     image_width: u32,
@@ -24,13 +119,51 @@ pub struct Builder {
     vfov: f64,
     defocus_angle: f64,
     focus_dist: f64,
+    aperture: Arc<Aperture>,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    lens_system: Option<LensSystem>,
 
     // samples
     samples_per_pixel: u32,
     max_depth: u32,
+
+    // shading
+    background: Option<Color>,
+    integrator: Arc<dyn Integrator>,
+    radiance_clamp: RadianceClamp,
+
+    // randomness
+    seed: Option<u64>,
+    sampler: Arc<dyn Sampler>,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    progressive: Option<Progressive>,
+    checkpoint: Option<Checkpoint>,
+
+    // reconstruction
+    filter: Arc<dyn Filter>,
+
+    // outputs
+    aovs: Vec<Aov>,
+
+    // diagnostics
+    heat_map: Option<PathBuf>,
+
+    // parallelism
+    tile_size: u32,
+    threads: Option<usize>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Builder {
+    /// Settings for a camera at the origin that renders nothing, to be filled in with the
+    /// setters: at least the image size, samples per pixel, depth, field of view, view and
+    /// focus distance.
     pub fn new() -> Self {
         Self {
             image_width: 0,
@@ -41,60 +174,204 @@ impl Builder {
             vup: Vector3::new_default(),
             defocus_angle: 0.0,
             focus_dist: 0.0,
+            aperture: Arc::new(Aperture::circular()),
+            projection: Projection::Perspective,
+            stereo: None,
+            lens_system: None,
             samples_per_pixel: 0,
             max_depth: 0,
+            background: None,
+            integrator: Arc::new(PathTracer),
+            radiance_clamp: RadianceClamp::new(),
+            seed: None,
+            sampler: Arc::new(Independent),
+            adaptive_sampling: None,
+            progressive: None,
+            checkpoint: None,
+            filter: Arc::new(BoxFilter::new(0.5)),
+            aovs: vec![],
+            heat_map: None,
+            tile_size: 16,
+            threads: None,
         }
     }
+    /// in pixels; the height follows from the aspect ratio
     pub fn set_image_width(&mut self, width: u32) -> &mut Self {
         self.image_width = width;
         self
     }
 
+    /// width over height
     pub fn set_image_aspect_ratio(&mut self, ratio: f64) -> &mut Self {
         self.image_aspect_ratio = ratio;
         self
     }
 
+    /// vertical field of view, in degrees
     pub fn set_vfov(&mut self, vfov: f64) -> &mut Self {
         self.vfov = vfov;
         self
     }
 
+    /// camera samples taken per pixel in every pass, so integrators that render several
+    /// passes (e.g. progressive photon mapping) take this many times their passes in all
     pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) -> &mut Self {
         self.samples_per_pixel = samples_per_pixel;
         self
     }
 
+    /// the most bounces a path takes before it's cut off
     pub fn set_max_depth(&mut self, max_depth: u32) -> &mut Self {
         self.max_depth = max_depth;
         self
     }
 
+    /// where the camera stands
     pub fn set_lookfrom(&mut self, lookfrom: &Point3) -> &mut Self {
-        self.lookfrom = lookfrom.clone();
+        self.lookfrom = *lookfrom;
         self
     }
 
+    /// the point the camera looks at
     pub fn set_lookat(&mut self, lookat: &Point3) -> &mut Self {
-        self.lookat = lookat.clone();
+        self.lookat = *lookat;
         self
     }
 
+    /// which way is up for the camera
     pub fn set_vup(&mut self, vup: &Vector3) -> &mut Self {
-        self.vup = vup.clone();
+        self.vup = *vup;
         self
     }
 
+    /// the angle, in degrees, the lens aperture subtends from a pixel; 0 keeps everything
+    /// in focus
     pub fn set_defocus_angle(&mut self, defocus_angle: f64) -> &mut Self {
         self.defocus_angle = defocus_angle;
         self
     }
 
+    /// the distance from the camera to the plane in perfect focus
     pub fn set_focus_dist(&mut self, focus_dist: f64) -> &mut Self {
         self.focus_dist = focus_dist;
         self
     }
 
+    /// perspective by default
+    pub fn set_projection(&mut self, projection: Projection) -> &mut Self {
+        self.projection = projection;
+        self
+    }
+
+    /// renders an image per eye, packed into one
+    pub fn set_stereo(&mut self, stereo: Stereo) -> &mut Self {
+        self.stereo = Some(stereo);
+        self
+    }
+
+    /// shape of the lens opening, sized by the defocus angle; circular by default
+    pub fn set_aperture(&mut self, aperture: Aperture) -> &mut Self {
+        self.aperture = Arc::new(aperture);
+        self
+    }
+
+    /// traces rays through the elements of a real lens instead of the projection, focused
+    /// at the focus distance (if set) from lookfrom, where the film sits; the field of
+    /// view and the defocus come from the lens
+    pub fn set_lens_system(&mut self, lens_system: LensSystem) -> &mut Self {
+        self.lens_system = Some(lens_system);
+        self
+    }
+
+    /// radiance of rays leaving the scene; the sky gradient is used if this is never set
+    pub fn set_background(&mut self, background: &Color) -> &mut Self {
+        self.background = Some(*background);
+        self
+    }
+
+    /// how light is found along camera rays, path tracing by default
+    pub fn set_integrator(&mut self, integrator: Arc<dyn Integrator>) -> &mut Self {
+        self.integrator = integrator;
+        self
+    }
+
+    /// limits on the radiance single samples bring back, against fireflies; none by default
+    pub fn set_radiance_clamp(&mut self, radiance_clamp: RadianceClamp) -> &mut Self {
+        self.radiance_clamp = radiance_clamp;
+        self
+    }
+
+    /// makes every render with the same seed produce the same image, whatever the number of
+    /// threads; without a seed each render draws fresh random numbers. Integrators that
+    /// splat from many threads at once (BDPT, MLT) only match up to float rounding, since
+    /// the order in which splats are added up is left to the scheduler
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// where the numbers of each pixel sample come from; independent uniform ones by default
+    pub fn set_sampler(&mut self, sampler: Arc<dyn Sampler>) -> &mut Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// lets every pixel stop sampling once it's converged, in place of the fixed
+    /// `samples_per_pixel`
+    pub fn set_adaptive_sampling(&mut self, adaptive_sampling: AdaptiveSampling) -> &mut Self {
+        self.adaptive_sampling = Some(adaptive_sampling);
+        self
+    }
+
+    /// renders a sample per pixel at a time, writing snapshots along the way
+    pub fn set_progressive(&mut self, progressive: Progressive) -> &mut Self {
+        self.progressive = Some(progressive);
+        self
+    }
+
+    /// saves the render every so often, or resumes it from the save
+    pub fn set_checkpoint(&mut self, checkpoint: Checkpoint) -> &mut Self {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    pub(crate) fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    /// how samples are weighted into the pixels around them; by default each pixel is the
+    /// plain average of its own samples
+    pub fn set_filter(&mut self, filter: Arc<dyn Filter>) -> &mut Self {
+        self.filter = filter;
+        self
+    }
+
+    /// auxiliary images to render alongside the beauty pass, which `render_layers` returns
+    pub fn set_aovs(&mut self, aovs: &[Aov]) -> &mut Self {
+        self.aovs = aovs.to_vec();
+        self
+    }
+
+    /// writes an image of how many samples every pixel took to `path`, brighter meaning more,
+    /// in the format the extension names
+    pub fn set_heat_map(&mut self, path: &str) -> &mut Self {
+        self.heat_map = Some(PathBuf::from(path));
+        self
+    }
+
+    /// side of the square tiles the image is rendered in, 16 pixels by default
+    pub fn set_tile_size(&mut self, tile_size: u32) -> &mut Self {
+        self.tile_size = u32::max(tile_size, 1);
+        self
+    }
+
+    /// render threads, one per core by default
+    pub fn set_threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = Some(usize::max(threads, 1));
+        self
+    }
+
+    /// the camera the settings describe, with its viewport and defocus disk worked out
     pub fn build(&self) -> Camera {
         // image
         let mut image_height = ((self.image_width as f64) / self.image_aspect_ratio) as u32;
@@ -141,10 +418,42 @@ impl Builder {
             defocus_disk_u,
             defocus_disk_v,
             defocus_angle: self.defocus_angle,
+            aperture: Arc::clone(&self.aperture),
+            focus_dist: self.focus_dist,
+            projection: self.projection,
+            stereo: self.stereo,
+            eye_offset: 0.0,
+            lens: self.lens_system.as_ref().map(|lens_system| {
+                Arc::new(lens_system.focus(
+                    self.focus_dist,
+                    self.image_width as f64 / image_height as f64,
+                ))
+            }),
+            right: u,
+            up: v,
+            forward: -w,
+            film_area: (viewport_width * viewport_height) / (self.focus_dist * self.focus_dist),
+            background: self.background,
+            integrator: Arc::clone(&self.integrator),
+            radiance_clamp: self.radiance_clamp,
+            seed: self.seed,
+            sampler: Arc::clone(&self.sampler),
+            adaptive_sampling: self.adaptive_sampling,
+            progressive: self.progressive.clone(),
+            checkpoint: self.checkpoint.clone(),
+            filter: Arc::clone(&self.filter),
+            aovs: self.aovs.clone(),
+            heat_map: self.heat_map.clone(),
+            tile_size: self.tile_size,
+            threads: self
+                .threads
+                .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())),
         }
     }
 }
 
+/// Renders a scene into an [`Image`], as configured by a [`Builder`].
+#[derive(Clone)]
 pub struct Camera {
     // image
     image_width: u32,
@@ -157,120 +466,768 @@ pub struct Camera {
     defocus_disk_u: Vector3,
     defocus_disk_v: Vector3,
     defocus_angle: f64,
+    aperture: Arc<Aperture>,
+    focus_dist: f64,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    eye_offset: f64, // along the right axis, set on the cameras of the two eyes
+    lens: Option<Arc<RealisticLens>>,
 
     // center
     center: Point3,
+    right: Vector3,
+    up: Vector3,
+    forward: Vector3,
+    film_area: f64, // viewport area at unit distance from the center
 
     // sampling
     samples_per_pixel: u32,
     max_depth: u32,
+
+    // shading
+    background: Option<Color>,
+    integrator: Arc<dyn Integrator>,
+    radiance_clamp: RadianceClamp,
+
+    // randomness
+    seed: Option<u64>,
+    sampler: Arc<dyn Sampler>,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    progressive: Option<Progressive>,
+    checkpoint: Option<Checkpoint>,
+
+    // reconstruction
+    filter: Arc<dyn Filter>,
+
+    // outputs
+    aovs: Vec<Aov>,
+
+    // diagnostics
+    heat_map: Option<PathBuf>,
+
+    // parallelism
+    tile_size: u32,
+    threads: usize,
 }
 
 impl Camera {
     // builder pattern
-    pub fn render(&self, world: Arc<HittableList>) -> std::io::Result<()> {
-        let mut stdout = BufWriter::new(io::stdout().lock());
+    /// renders `world` into an image, for [`Image::save`] or a writer to encode
+    pub fn render(&self, world: Arc<HittableList>) -> io::Result<Image> {
+        Ok(self.render_layers(world)?.beauty)
+    }
+
+    /// renders `world` into an image along with the AOVs the builder asked for
+    pub fn render_layers(&self, world: Arc<HittableList>) -> io::Result<Layers> {
         let mut stderr = BufWriter::new(io::stderr().lock());
-        stdout.write(b"P3\n")?;
-        stdout.write((format!("{} {}\n255\n", self.image_width, self.image_height)).as_bytes())?;
-
-        for j in 0..self.image_height {
-            for i in 0..self.image_width {
-                stderr.write(
-                    format!("\rScanlines remaining: {} ", self.image_height - j).as_bytes(),
-                )?;
-                stderr.flush()?;
-
-                let (tx, rx) = mpsc::channel();
-                let samples_per_thread = if self.samples_per_pixel > THREAED_NUMBER as u32 {
-                    self.samples_per_pixel / THREAED_NUMBER as u32
-                } else {
-                    1
+
+        // an output that can't be written fails now rather than after the render
+        if let Some(path) = &self.heat_map {
+            Format::from_path(path)?;
+        }
+        if let Some(progressive) = &self.progressive {
+            Format::from_path(progressive.snapshot_path())?;
+        }
+
+        let cameras = match self.stereo {
+            None => vec![(self.clone(), None)],
+            Some(stereo) => {
+                let [left, right] = stereo.eye_offsets();
+
+                vec![
+                    (self.eye(left), Some("left")),
+                    (self.eye(right), Some("right")),
+                ]
+            }
+        };
+
+        // every film with the samples per pixel its splats are averaged over, and its AOVs
+        let films = cameras
+            .iter()
+            .map(|(camera, name)| camera.render_film(&world, *name, &mut stderr))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let (width, height) = match self.stereo {
+            None => (self.image_width, self.image_height),
+            Some(stereo) => stereo.packed_size(self.image_width, self.image_height),
+        };
+        let locate = |i: u32, j: u32| match self.stereo {
+            None => (0, i, j),
+            Some(stereo) => stereo.locate(i, j, self.image_width, self.image_height),
+        };
+
+        // splats may land on any pixel, so the image can only be made once every pixel is done
+        let beauty = Image::from_fn(width, height, |i, j| {
+            let (eye, i, j) = locate(i, j);
+            let (film, splat_samples_per_pixel, _) = &films[eye];
+
+            film.pixel(i, j, *splat_samples_per_pixel)
+        });
+        let aovs = self
+            .aovs
+            .iter()
+            .map(|&aov| {
+                let eyes: Vec<Image> = films
+                    .iter()
+                    .filter_map(|(_, _, aovs)| aovs.as_ref())
+                    .map(|aovs| aovs.image(aov))
+                    .collect();
+                let image = Image::from_fn(width, height, |i, j| {
+                    let (eye, i, j) = locate(i, j);
+
+                    eyes[eye].pixel(i, j)
+                });
+
+                (aov, image)
+            })
+            .collect();
+
+        if let Some(path) = &self.heat_map {
+            write_heat_map(path, width, height, |i, j| {
+                let (eye, i, j) = locate(i, j);
+
+                films[eye].0.sample_count(i, j)
+            })?;
+        }
+
+        stderr.write_all("\rDone.                 \n".as_bytes())?;
+        stderr.flush()?;
+
+        Ok(Layers { beauty, aovs })
+    }
+
+    /// the camera of the eye `offset` to the right of the center
+    fn eye(&self, offset: f64) -> Camera {
+        let mut eye = self.clone();
+        let shift = offset * self.right;
+
+        match self.projection {
+            // ODS moves the eyes around with the longitude, see `ray_through`
+            Projection::Equirectangular => eye.eye_offset = offset,
+            _ => {
+                let convergence = self
+                    .stereo
+                    .map_or(INFINITY, |stereo| stereo.convergence_distance());
+
+                // the image plane at the focus distance moves by less than the eye, so
+                // that both eyes' planes meet at the convergence distance
+                eye.center = self.center + shift;
+                eye.pixel00_loc = self.pixel00_loc + shift * (1.0 - self.focus_dist / convergence);
+            }
+        }
+
+        eye
+    }
+
+    /// renders every pass into a new film, returned with the samples per pixel its splats
+    /// are averaged over and, if the builder asked for AOVs, the first hits of the same
+    /// camera samples; `name` tells the films of stereo eyes apart in progress messages and
+    /// checkpoints
+    fn render_film(
+        &self,
+        world: &Arc<HittableList>,
+        name: Option<&str>,
+        stderr: &mut impl Write,
+    ) -> io::Result<(Film, f64, Option<AovFilm>)> {
+        let film = Film::new(
+            self.image_width,
+            self.image_height,
+            Arc::clone(&self.filter),
+        );
+        let mut aovs = match self.aovs.is_empty() {
+            true => None,
+            false => Some(AovFilm::new(self.image_width, self.image_height)),
+        };
+        let label = label(name);
+        let passes = self.integrator.passes();
+        let samples_per_pass = match (self.adaptive_sampling, &self.progressive) {
+            (Some(adaptive), None) => adaptive.max_samples,
+            _ => self.samples_per_pass(),
+        };
+        // a progressive render goes over the image once for every sample of a pass
+        let (rounds, samples_per_round) = match (&self.progressive, self.adaptive_sampling) {
+            (Some(_), _) => (samples_per_pass, Some(1)),
+            (None, Some(_)) => (1, None),
+            (None, None) => (1, Some(samples_per_pass)),
+        };
+        let pixels = self.image_width as f64 * self.image_height as f64;
+        let tiles = Tile::split(self.image_width, self.image_height, self.tile_size);
+        // with checkpoints, every so many tiles all threads finish, so the film can be
+        // saved with no tile half rendered (nor its splats half added)
+        let batch = match self.checkpoint {
+            Some(_) => usize::max(CHECKPOINT_BATCH * self.threads, 1),
+            None => tiles.len(),
+        };
+
+        let checkpoint_path = self
+            .checkpoint
+            .as_ref()
+            .map(|checkpoint| checkpoint.path(name));
+        let mut progress = Progress {
+            scene_hash: self.fingerprint(world),
+            seed: self.seed.unwrap_or_else(rand::random),
+            pass: 0,
+            round: 0,
+            tile: 0,
+            // the samples per pixel splats are averaged over, added up pass by pass
+            splat_samples_per_pixel: 0.0,
+            elapsed: Duration::ZERO,
+        };
+
+        if let (Some(checkpoint), Some(path)) = (&self.checkpoint, &checkpoint_path) {
+            if checkpoint.resume() {
+                if let Some(saved) =
+                    checkpoint::load(path, progress.scene_hash, &film, aovs.as_mut())?
+                {
+                    progress = saved;
+                    stderr
+                        .write_all(format!("{}Resuming {}\n", label, path.display()).as_bytes())?;
+                }
+            }
+        }
+
+        let seed = progress.seed;
+        let start_time = Instant::now() - progress.elapsed;
+        let mut last_snapshot = Instant::now();
+        let mut last_save = Instant::now();
+
+        while progress.pass < passes {
+            let pass = progress.pass;
+
+            self.seeded(&[pass as u64, u64::MAX], || {
+                self.integrator.begin_pass(pass, self, world)
+            });
+
+            let pixel_sample = |round: u32| {
+                move |i: u32, j: u32, first: u32| PixelSample {
+                    i,
+                    j,
+                    index: pass * samples_per_pass + round + first,
+                    count: samples_per_pass * passes,
+                    seed,
+                }
+            };
+
+            if self.integrator.render_pass(self, world, &film) {
+                // its samples aren't the camera's to see, so the AOVs take their own
+                if let Some(aovs) = &mut aovs {
+                    self.render_aov_tiles(world, aovs, &tiles, &pixel_sample(0), label, stderr)?;
+                }
+
+                progress.splat_samples_per_pixel += self.samples_per_pixel as f64;
+                progress.pass += 1;
+            }
+
+            while progress.pass == pass {
+                let round = progress.round;
+                let pixel_sample = pixel_sample(round);
+                let (current, total) = (pass * rounds + round + 1, passes * rounds);
+
+                while (progress.tile as usize) < tiles.len() {
+                    let start = progress.tile as usize;
+                    let end = usize::min(start + batch, tiles.len());
+                    let pass_samples = self.render_tiles(
+                        world,
+                        &film,
+                        aovs.as_mut(),
+                        &tiles[start..end],
+                        &pixel_sample,
+                        samples_per_round,
+                        |remaining| {
+                            let remaining = remaining + tiles.len() - end;
+                            let progress = if total > 1 {
+                                format!(
+                                    "\r{}Pass {}/{}, tiles remaining: {} ",
+                                    label, current, total, remaining
+                                )
+                            } else {
+                                format!("\r{}Tiles remaining: {} ", label, remaining)
+                            };
+                            stderr.write_all(progress.as_bytes())?;
+                            stderr.flush()
+                        },
+                    )?;
+
+                    progress.splat_samples_per_pixel += pass_samples as f64 / pixels;
+                    progress.tile = end as u32;
+
+                    if let (Some(checkpoint), Some(path)) = (&self.checkpoint, &checkpoint_path) {
+                        if end < tiles.len() && last_save.elapsed() >= checkpoint.interval() {
+                            progress.elapsed = start_time.elapsed();
+                            checkpoint::save(path, &progress, &film, aovs.as_ref())?;
+                            last_save = Instant::now();
+                        }
+                    }
+                }
+
+                progress.tile = 0;
+                progress.round += 1;
+
+                if progress.round == rounds {
+                    (progress.pass, progress.round) = (pass + 1, 0);
+                }
+
+                if let Some(progressive) = &self.progressive {
+                    if progressive.should_stop(start_time.elapsed(), || film.noise()) {
+                        progress.pass = passes;
+                        break;
+                    }
+
+                    if current < total && progressive.snapshot_due(current, last_snapshot.elapsed())
+                    {
+                        write_snapshot(
+                            progressive.snapshot_path(),
+                            &film,
+                            progress.splat_samples_per_pixel,
+                        )?;
+                        last_snapshot = Instant::now();
+                    }
+                }
+
+                if let (Some(checkpoint), Some(path)) = (&self.checkpoint, &checkpoint_path) {
+                    if progress.pass < passes && last_save.elapsed() >= checkpoint.interval() {
+                        progress.elapsed = start_time.elapsed();
+                        checkpoint::save(path, &progress, &film, aovs.as_ref())?;
+                        last_save = Instant::now();
+                    }
+                }
+            }
+        }
+
+        // a finished checkpoint resumes straight to the image
+        if let Some(path) = &checkpoint_path {
+            progress.elapsed = start_time.elapsed();
+            checkpoint::save(path, &progress, &film, aovs.as_ref())?;
+        }
+
+        Ok((film, progress.splat_samples_per_pixel, aovs))
+    }
+
+    /// the AOVs of every pixel, for integrators that render passes themselves: as many
+    /// camera rays as a pass takes samples, through the image positions `pixel_sample` gives
+    fn render_aov_tiles(
+        &self,
+        world: &HittableList,
+        aovs: &mut AovFilm,
+        tiles: &[Tile],
+        pixel_sample: &(impl Fn(u32, u32, u32) -> PixelSample + Sync),
+        label: &str,
+        stderr: &mut impl Write,
+    ) -> io::Result<()> {
+        let samples = self.samples_per_pass();
+
+        self.for_each_tile(
+            tiles,
+            |tile| {
+                tile.pixels()
+                    .flat_map(|(i, j)| {
+                        let first = pixel_sample(i, j, 0);
+
+                        (0..samples).map(move |offset| PixelSample {
+                            index: first.index + offset,
+                            ..first
+                        })
+                    })
+                    .map(|sample| (sample.i, sample.j, self.aov_sample(world, sample)))
+                    .collect::<Vec<_>>()
+            },
+            |taken| {
+                for (i, j, sample) in &taken {
+                    aovs.add(*i, *j, sample);
+                }
+            },
+            |remaining| {
+                let progress = format!("\r{}AOVs, tiles remaining: {} ", label, remaining);
+                stderr.write_all(progress.as_bytes())?;
+                stderr.flush()
+            },
+        )
+    }
+
+    /// what the camera ray of `sample` hits first
+    fn aov_sample(&self, world: &HittableList, sample: PixelSample) -> AovSample {
+        let source: Rc<RefCell<dyn SampleSource>> = Rc::new(RefCell::new(PixelSampleSource::new(
+            Arc::clone(&self.sampler),
+            sample,
+        )));
+
+        with_sample_source(source, || {
+            let black = Color::new_default();
+            let Some(ray) = self.get_ray(sample.i, sample.j) else {
+                return AovSample {
+                    albedo: black,
+                    hit: None,
+                };
+            };
+            let mut rec = HitRecord::new();
+
+            if !world.hit(&ray, Interval::new(0.001, INFINITY), &mut rec) {
+                let background = self.background(&ray);
+
+                return AovSample {
+                    albedo: Color::new(
+                        f64::clamp(background.x(), 0.0, 1.0),
+                        f64::clamp(background.y(), 0.0, 1.0),
+                        f64::clamp(background.z(), 0.0, 1.0),
+                    ),
+                    hit: None,
                 };
+            }
+
+            let depth = match self.projection {
+                Projection::Perspective | Projection::Orthographic { .. } => {
+                    dot(&(rec.p - self.center), &self.forward)
+                }
+                _ => (rec.p - *ray.origin()).length(),
+            };
+
+            AovSample {
+                albedo: rec.mat.as_ref().map_or(black, |mat| mat.albedo(&rec)),
+                hit: Some(AovHit {
+                    normal: rec.normal,
+                    depth,
+                    position: rec.p,
+                    uv: (rec.u, rec.v),
+                    material_id: rec.material_id,
+                    object_id: rec.object_id,
+                }),
+            }
+        })
+    }
 
-                // four threads
-                thread::scope(|scope| {
-                    let mut joins = vec![];
+    /// hash of the scene and of everything about the camera that changes the image, which
+    /// a checkpoint must match to be resumed
+    fn fingerprint(&self, world: &HittableList) -> u64 {
+        let mut state = Fnv1a::new();
 
-                    for _ in 0..THREAED_NUMBER {
-                        let join = scope.spawn(|| {
-                            let mut pixel_color = Color::new_default();
+        world.fingerprint(&mut state);
 
-                            for _ in 0..samples_per_thread {
-                                let ray = self.get_ray(i, j);
-                                pixel_color +=
-                                    Self::ray_color(&ray, self.max_depth, Arc::clone(&world));
-                            }
-                            let tx_clone = tx.clone();
+        let view = format!(
+            "{:?}",
+            (
+                self.image_width,
+                self.image_height,
+                self.pixel00_loc,
+                self.pixel_delta_u,
+                self.pixel_delta_v,
+                self.center,
+                self.defocus_disk_u,
+                self.defocus_disk_v,
+                self.projection,
+                self.stereo,
+                self.eye_offset,
+                // checkpoints count the tiles done
+                self.tile_size,
+            )
+        );
+        let sampling = format!(
+            "{:?}",
+            (
+                self.samples_per_pixel,
+                self.max_depth,
+                self.background,
+                self.radiance_clamp,
+                self.seed,
+                self.adaptive_sampling,
+                self.integrator.passes(),
+                self.progressive.is_some(),
+                self.defocus_angle,
+                self.focus_dist,
+                // checkpoints hold the AOVs too
+                &self.aovs,
+            )
+        );
 
-                            let _ = tx_clone.send(pixel_color);
-                        });
+        state.write(view.as_bytes());
+        state.write(sampling.as_bytes());
+        self.integrator.fingerprint(&mut state);
+        self.sampler.fingerprint(&mut state);
+        self.filter.fingerprint(&mut state);
+        self.aperture.fingerprint(&mut state);
 
-                        joins.push(join);
+        match &self.lens {
+            None => state.write_u8(0),
+            Some(lens) => {
+                state.write_u8(1);
+                lens.fingerprint(&mut state);
+            }
+        }
+
+        state.finish()
+    }
+
+    /// how many samples each pixel gets per pass without adaptive sampling
+    fn samples_per_pass(&self) -> u32 {
+        u32::max(self.samples_per_pixel, 1)
+    }
+
+    /// Renders `tiles` of a pass, with `samples` per pixel (see `render_tile`), adding the
+    /// first hits of the camera samples to `aovs` if given, and returns the number of
+    /// camera samples taken.
+    #[allow(clippy::too_many_arguments)]
+    fn render_tiles(
+        &self,
+        world: &HittableList,
+        film: &Film,
+        mut aovs: Option<&mut AovFilm>,
+        tiles: &[Tile],
+        pixel_sample: &(impl Fn(u32, u32, u32) -> PixelSample + Sync),
+        samples: Option<u32>,
+        progress: impl FnMut(usize) -> io::Result<()>,
+    ) -> io::Result<u64> {
+        let mut taken = 0;
+        let with_aovs = aovs.is_some();
+
+        self.for_each_tile(
+            tiles,
+            |tile| self.render_tile(world, film, tile, pixel_sample, samples, with_aovs),
+            |(samples, aov_samples)| {
+                for (x, y, sample) in &samples {
+                    film.add_sample(*x, *y, sample);
+                }
+                if let Some(aovs) = aovs.as_deref_mut() {
+                    for (i, j, sample) in &aov_samples {
+                        aovs.add(*i, *j, sample);
                     }
+                }
+
+                taken += samples.len() as u64;
+            },
+            progress,
+        )?;
 
-                    for j in joins {
-                        let _ = j.join();
+        Ok(taken)
+    }
+
+    /// Renders every one of `tiles`, consecutive in scanline order, with `render`, the
+    /// render threads taking them from a work-stealing queue, and hands the results to
+    /// `finish` on this thread in scanline order whatever order they finish in, so a seeded
+    /// image doesn't depend on the number of threads. `progress` hears how many tiles remain.
+    fn for_each_tile<T: Send>(
+        &self,
+        tiles: &[Tile],
+        render: impl Fn(&Tile) -> T + Sync,
+        mut finish: impl FnMut(T),
+        mut progress: impl FnMut(usize) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let tile_count = tiles.len();
+        let first = tiles.first().map_or(0, |tile| tile.index);
+        let queue = WorkStealingQueue::new(tiles.to_vec(), self.threads);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for worker in 0..self.threads {
+                let (queue, sender, render) = (&queue, sender.clone(), &render);
+
+                scope.spawn(move || {
+                    while let Some(tile) = queue.next(worker) {
+                        let result = render(&tile);
+
+                        // the receiver only goes away on an error
+                        if sender.send((tile.index, result)).is_err() {
+                            break;
+                        }
                     }
                 });
+            }
 
-                let mut recved_color = Color::new_default();
+            drop(sender);
+            progress(tile_count)?;
 
-                for _ in 0..THREAED_NUMBER {
-                    recved_color += rx.recv().unwrap();
-                }
+            let mut finished = BTreeMap::new();
+            let mut next = first;
+
+            for (index, result) in receiver {
+                finished.insert(index, result);
 
-                write_color(
-                    &mut stdout,
-                    &(recved_color * (1.0 / self.samples_per_pixel as f64)),
-                )?;
+                while let Some(result) = finished.remove(&next) {
+                    finish(result);
+                    next += 1;
+                    progress(tile_count - (next - first))?;
+                }
             }
+
+            Ok(())
+        })
+    }
+
+    /// the samples of every pixel of `tile`, pixel after pixel: `count` each, or as many as
+    /// adaptive sampling takes if None; with `aovs`, also what the camera ray of every
+    /// sample hit first
+    #[allow(clippy::type_complexity)]
+    fn render_tile(
+        &self,
+        world: &HittableList,
+        film: &Film,
+        tile: &Tile,
+        pixel_sample: &impl Fn(u32, u32, u32) -> PixelSample,
+        count: Option<u32>,
+        aovs: bool,
+    ) -> (Vec<(f64, f64, Color)>, Vec<(u32, u32, AovSample)>) {
+        let mut samples = vec![];
+        let mut aov_samples = vec![];
+
+        for (i, j) in tile.pixels() {
+            let mut take = |first: u32, count: u32| {
+                let first = pixel_sample(i, j, first);
+                let taken = self.take_samples(world, film, first, count);
+                let colors: Vec<Color> = taken.iter().map(|(_, _, color)| *color).collect();
+
+                if aovs {
+                    // the same sample numbers replay the same camera rays
+                    aov_samples.extend((0..count).map(|offset| {
+                        let sample = PixelSample {
+                            index: first.index + offset,
+                            ..first
+                        };
+
+                        (i, j, self.aov_sample(world, sample))
+                    }));
+                }
+
+                samples.extend(taken);
+                colors
+            };
+
+            match (count, self.adaptive_sampling) {
+                (Some(count), _) => take(0, count).len() as u32,
+                (None, Some(adaptive)) => adaptive.sample_pixel(&mut take),
+                (None, None) => take(0, self.samples_per_pass()).len() as u32,
+            };
         }
 
-        stderr.write("\rDone.                 \n".as_bytes())?;
-        stderr.flush()?;
-        stdout.flush()?;
+        (samples, aov_samples)
+    }
+
+    /// `count` samples of one pixel, starting at `first`; every sample draws from the
+    /// sampler on its own, so a seeded image doesn't depend on how the work is split. Each
+    /// comes with the raster position `get_ray` placed it at, which the sampler's first two
+    /// dimensions set
+    fn take_samples(
+        &self,
+        world: &HittableList,
+        film: &Film,
+        first: PixelSample,
+        count: u32,
+    ) -> Vec<(f64, f64, Color)> {
+        (0..count)
+            .map(|offset| {
+                let sample = PixelSample {
+                    index: first.index + offset,
+                    ..first
+                };
+                let source: Rc<RefCell<dyn SampleSource>> = Rc::new(RefCell::new(
+                    PixelSampleSource::new(Arc::clone(&self.sampler), sample),
+                ));
 
-        Ok(())
+                let (x, y) = self.sampler.get_2d(&sample, 0);
+                let color = with_sample_source(source, || {
+                    self.integrator.sample(self, world, first.i, first.j, film)
+                });
+
+                (first.i as f64 + x, first.j as f64 + y, color)
+            })
+            .collect()
     }
 
-    fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let offset = Point3::new(random_double() - 0.5, random_double() - 0.5, 0.0);
-        let pixel_sample = self.pixel00_loc
-            + ((i as f64) + offset.x()) * self.pixel_delta_u
-            + ((j as f64) + offset.y()) * self.pixel_delta_v;
+    /// render threads, for integrators that run passes of their own
+    pub(crate) fn threads(&self) -> usize {
+        self.threads
+    }
 
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center
-        } else {
-            self.defocus_disk_sample()
-        };
-        let ray_direction = pixel_sample - ray_origin;
+    /// a camera ray through a random point of pixel (i, j), if the image shows anything there
+    pub(crate) fn get_ray(&self, i: u32, j: u32) -> Option<Ray> {
+        let (x, y) = random_double_2d();
 
-        Ray::new(&self.center, &ray_direction)
+        self.ray_through(i as f64 + x, j as f64 + y)
     }
 
-    fn ray_color(ray: &Ray, depth: u32, world: Arc<HittableList>) -> Color {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
+    /// the ray through continuous raster position (x, y)
+    fn ray_through(&self, x: f64, y: f64) -> Option<Ray> {
+        let (width, height) = (self.image_width as f64, self.image_height as f64);
+
+        if let Some(lens) = &self.lens {
+            let (origin, direction) = lens.ray(x / width, y / height, random_double())?;
+            let to_world = |v: Vector3| v.x() * self.right + v.y() * self.up + v.z() * self.forward;
+
+            return Some(Ray::new(
+                &(self.center + lens.scale() * to_world(origin)),
+                &to_world(direction),
+            ));
         }
 
-        let mut rec = HitRecord::new();
+        match self.projection {
+            Projection::Perspective => {
+                let pixel_sample = self.pixel00_loc
+                    + (x - 0.5) * self.pixel_delta_u
+                    + (y - 0.5) * self.pixel_delta_v;
+                let ray_origin = self.sample_lens();
+                let ray_direction = pixel_sample - ray_origin;
+
+                Some(Ray::new(&ray_origin, &ray_direction))
+            }
+            Projection::Orthographic { view_width } => {
+                let view_height = view_width * height / width;
+                let origin = self.center + ((x / width - 0.5) * view_width) * self.right
+                    - ((y / height - 0.5) * view_height) * self.up;
+
+                if self.defocus_angle <= 0.0 {
+                    return Some(Ray::new(&origin, &self.forward));
+                }
+
+                // the lens moves along with the ray, still focusing at the focus distance
+                let in_focus = origin + self.focus_dist * self.forward;
+                let ray_origin = origin + (self.sample_lens() - self.center);
 
-        if world.hit(ray, Interval::new(0.001, INFINITY), &mut rec) {
-            let mut scattered = Ray::new_default();
-            let mut attenuation = Color::new_default();
-            let material = rec.mat.clone();
+                Some(Ray::new(&ray_origin, &(in_focus - ray_origin)))
+            }
+            // angular projections see everything from the center, always in focus
+            projection => {
+                let [right, up, forward] = projection.direction(x, y, width, height)?;
+                let mut origin = self.center;
+
+                // ODS: the eye sits on a circle, sideways to the horizontal view direction
+                if self.eye_offset != 0.0 {
+                    let (longitude, _) = Projection::longitude_latitude(x, y, width, height);
+
+                    origin += self.eye_offset
+                        * (f64::cos(longitude) * self.right - f64::sin(longitude) * self.forward);
+                }
 
-            if material
-                .unwrap()
-                .scatter(ray, &rec, &mut attenuation, &mut scattered)
-            {
-                return attenuation * Camera::ray_color(&scattered, depth - 1, world);
-            } else {
-                return Color::new(0.0, 0.0, 0.0);
+                Some(Ray::new(
+                    &origin,
+                    &(right * self.right + up * self.up + forward * self.forward),
+                ))
             }
         }
+    }
+
+    /// width of the image in pixels
+    pub fn image_width(&self) -> u32 {
+        self.image_width
+    }
+
+    /// height of the image in pixels, from the width and the aspect ratio
+    pub fn image_height(&self) -> u32 {
+        self.image_height
+    }
+
+    pub(crate) fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    pub(crate) fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    pub(crate) fn radiance_clamp(&self) -> &RadianceClamp {
+        &self.radiance_clamp
+    }
+
+    pub(crate) fn background(&self, ray: &Ray) -> Color {
+        if let Some(background) = self.background {
+            return background;
+        }
 
         let unit_direction = ray.direction().normalize().unwrap();
         let alpha = 0.5 * (unit_direction.y() + 1.0);
@@ -278,9 +1235,391 @@ impl Camera {
         (1.0 - alpha) * Color::new(1.0, 1.0, 1.0) + alpha * Color::new(0.5, 0.7, 1.0)
     }
 
-    fn defocus_disk_sample(&self) -> Vector3 {
-        let p = random_in_unit_disk();
+    /// importance We emitted from lens point `origin` along `direction` and the solid
+    /// angle density with which `get_ray` picks that direction, both zero outside the
+    /// image; the density of the lens point cancels the lens area in We, so neither appears
+    pub(crate) fn importance(&self, origin: &Point3, direction: &Vector3) -> (f64, f64) {
+        let cos_theta = match direction.normalize() {
+            Ok(unit_direction) => dot(&unit_direction, &self.forward),
+            Err(_) => return (0.0, 0.0),
+        };
+
+        if !self.is_perspective()
+            || cos_theta <= 0.0
+            || self
+                .raster_position(origin, &(*origin + *direction))
+                .is_none()
+        {
+            return (0.0, 0.0);
+        }
+
+        let cos2_theta = cos_theta * cos_theta;
+
+        (
+            1.0 / (self.film_area * cos2_theta * cos2_theta),
+            1.0 / (self.film_area * cos2_theta * cos_theta),
+        )
+    }
+
+    /// continuous raster coordinates of the pixel `point` is seen through from lens point
+    /// `origin`
+    pub(crate) fn raster_position(&self, origin: &Point3, point: &Point3) -> Option<(f64, f64)> {
+        let direction = *point - *origin;
+        let distance = dot(&direction, &self.forward);
+
+        if !self.is_perspective() || distance <= 0.0 {
+            return None;
+        }
+
+        // scale onto the viewport plane, which lies in focus where pixel00_loc does; lens
+        // points all lie in the plane of the center
+        let focus_distance = dot(&(self.pixel00_loc - self.center), &self.forward);
+        let on_viewport = *origin + direction * (focus_distance / distance);
+        let offset = on_viewport - self.pixel00_loc;
+
+        let x = dot(&offset, &self.pixel_delta_u) / self.pixel_delta_u.length_squared() + 0.5;
+        let y = dot(&offset, &self.pixel_delta_v) / self.pixel_delta_v.length_squared() + 0.5;
+
+        if x < 0.0 || y < 0.0 || x >= self.image_width as f64 || y >= self.image_height as f64 {
+            return None;
+        }
+
+        Some((x, y))
+    }
+
+    pub(crate) fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// runs `f` with random numbers derived from the seed and `values`, or with fresh
+    /// ones if no seed is set
+    pub(crate) fn seeded<R>(&self, values: &[u64], f: impl FnOnce() -> R) -> R {
+        let Some(seed) = self.seed else {
+            return f();
+        };
+
+        let mut values = values.to_vec();
+        values.push(seed);
+
+        let source: Rc<RefCell<dyn SampleSource>> =
+            Rc::new(RefCell::new(Pcg32::new(mix_seed(&values), 0)));
+
+        with_sample_source(source, f)
+    }
+
+    /// whether light paths can be connected to the lens, which needs a one to one map from
+    /// directions to the image
+    pub(crate) fn is_perspective(&self) -> bool {
+        self.projection == Projection::Perspective && self.lens.is_none()
+    }
+
+    /// where the camera is, the center of its lens
+    pub fn center(&self) -> Point3 {
+        self.center
+    }
+
+    /// unit vector in the direction the camera looks
+    pub fn forward(&self) -> Vector3 {
+        self.forward
+    }
+
+    /// a point on the lens, which is the center itself for a pinhole camera
+    pub(crate) fn sample_lens(&self) -> Point3 {
+        if self.defocus_angle <= 0.0 {
+            return self.center;
+        }
+
+        let (x, y) = self.aperture.sample();
+
+        self.center + (x * self.defocus_disk_u) + (y * self.defocus_disk_v)
+    }
+}
+
+/// what goes in front of the progress messages of the film called `name`
+fn label(name: Option<&str>) -> &'static str {
+    match name {
+        Some("left") => "Left eye: ",
+        Some("right") => "Right eye: ",
+        _ => "",
+    }
+}
+
+/// the film as rendered so far, written beside `path` and then moved over it, so the
+/// snapshot is never seen half written
+fn write_snapshot(path: &Path, film: &Film, splat_samples_per_pixel: f64) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    let mut file = BufWriter::new(File::create(&partial)?);
+
+    Format::from_path(path)?.write(&film.image(splat_samples_per_pixel), &mut file)?;
+    drop(file);
+
+    fs::rename(partial, path)
+}
+
+/// sample counts as an image in the format `path` names, running from black through red
+/// and yellow to white
+fn write_heat_map(
+    path: &Path,
+    width: u32,
+    height: u32,
+    sample_count: impl Fn(u32, u32) -> u32,
+) -> io::Result<()> {
+    let format = Format::from_path(path)?;
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut most = 1;
+
+    for j in 0..height {
+        for i in 0..width {
+            most = u32::max(most, sample_count(i, j));
+        }
+    }
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+
+    for j in 0..height {
+        for i in 0..width {
+            let t = sample_count(i, j) as f64 / most as f64;
+            let channel = |offset: f64| (255.999 * (3.0 * t - offset).clamp(0.0, 1.0)) as u8;
+
+            pixels.push([channel(0.0), channel(1.0), channel(2.0)]);
+        }
+    }
+
+    format.write_rgb8(width, height, &pixels, &mut file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::GaussianFilter;
+    use crate::geometry::sphere::Sphere;
+    use crate::integrator::bdpt::Bdpt;
+    use crate::material::Lambertian;
+    use crate::sampler::Sobol;
+    use crate::stereo::StereoPacking;
+
+    #[test]
+    fn adaptive_sampling_stops_early_only_on_converged_pixels() {
+        let adaptive = AdaptiveSampling::new(8, 256, 0.05);
+
+        let taken =
+            adaptive.sample_pixel(&mut |_, count| vec![Color::new(0.5, 0.5, 0.5); count as usize]);
+        assert_eq!(taken, 8);
+
+        // alternating black and white never gets within 5% with this budget
+        let taken = adaptive.sample_pixel(&mut |first, count| {
+            (first..first + count)
+                .map(|k| Color::new(1.0, 1.0, 1.0) * (k % 2) as f64)
+                .collect()
+        });
+        assert_eq!(taken, 256);
+    }
+
+    fn test_camera(defocus_angle: f64) -> Camera {
+        Builder::new()
+            .set_image_width(16)
+            .set_image_aspect_ratio(1.0)
+            .set_vfov(40.0)
+            .set_lookfrom(&Point3::new(0.0, 0.0, 0.0))
+            .set_lookat(&Point3::new(0.0, 0.0, -1.0))
+            .set_vup(&Vector3::new(0.0, 1.0, 0.0))
+            .set_defocus_angle(defocus_angle)
+            .set_focus_dist(2.0)
+            .build()
+    }
+
+    #[test]
+    fn fingerprints_change_with_every_setting_that_changes_the_image() {
+        let world = HittableList::new();
+        let builder = || {
+            let mut builder = Builder::new();
+            builder
+                .set_image_width(16)
+                .set_image_aspect_ratio(1.0)
+                .set_lookat(&Point3::new(0.0, 0.0, -1.0))
+                .set_vup(&Vector3::new(0.0, 1.0, 0.0))
+                .set_defocus_angle(2.0);
+            builder
+        };
+        let fingerprint = |builder: &mut Builder| builder.build().fingerprint(&world);
+        let lens = "0 20 0 12\n50 6 1.517 20\n-50 45 1 20";
+
+        let fingerprints = [
+            fingerprint(&mut builder()),
+            fingerprint(
+                builder().set_integrator(Arc::new(Bdpt::new(Arc::new(HittableList::new())))),
+            ),
+            fingerprint(builder().set_sampler(Arc::new(Sobol))),
+            fingerprint(builder().set_filter(Arc::new(GaussianFilter::new(1.5, 0.5)))),
+            fingerprint(builder().set_filter(Arc::new(GaussianFilter::new(1.5, 0.4)))),
+            fingerprint(builder().set_aperture(Aperture::polygonal(6))),
+            fingerprint(builder().set_aperture(Aperture::polygonal(7))),
+            fingerprint(builder().set_lens_system(LensSystem::parse(lens).unwrap())),
+            fingerprint(
+                builder().set_lens_system(LensSystem::parse(lens).unwrap().set_scale(0.01).clone()),
+            ),
+        ];
+
+        for (k, a) in fingerprints.iter().enumerate() {
+            assert!(fingerprints[k + 1..].iter().all(|b| a != b), "setting {k}");
+        }
+        assert_eq!(fingerprints[0], fingerprint(&mut builder()));
+    }
+
+    #[test]
+    fn defocused_rays_leave_from_distinct_origins() {
+        let camera = test_camera(10.0);
+        let rays: Vec<Ray> = (0..16).map(|_| camera.get_ray(8, 8).unwrap()).collect();
+
+        for (k, ray) in rays.iter().enumerate() {
+            assert_ne!(*ray.origin(), camera.center());
+
+            for other in &rays[k + 1..] {
+                assert_ne!(ray.origin(), other.origin());
+            }
+
+            // every ray still passes through the pixel on the plane in focus
+            let t = 2.0 / dot(ray.direction(), &camera.forward());
+            let (x, y) = camera
+                .raster_position(&camera.center(), &ray.at(t))
+                .unwrap();
+            assert!((8.0..9.0).contains(&x) && (8.0..9.0).contains(&y));
+        }
+
+        let pinhole = test_camera(0.0);
+
+        for _ in 0..16 {
+            assert_eq!(*pinhole.get_ray(8, 8).unwrap().origin(), pinhole.center());
+        }
+    }
+
+    #[test]
+    fn stereo_eyes_converge_at_the_convergence_distance() {
+        let camera = test_camera(0.0);
+        let stereo = Stereo::new(0.2, 4.0, StereoPacking::SideBySide);
+        let camera = Camera {
+            stereo: Some(stereo),
+            ..camera
+        };
+        let [left, right] = stereo.eye_offsets();
+        let (left, right) = (camera.eye(left), camera.eye(right));
+
+        assert_eq!(left.center(), Point3::new(-0.1, 0.0, 0.0));
+        assert_eq!(right.center(), Point3::new(0.1, 0.0, 0.0));
+
+        for (x, y) in [(8.0, 8.0), (2.5, 13.0)] {
+            let (left, right) = (
+                left.ray_through(x, y).unwrap(),
+                right.ray_through(x, y).unwrap(),
+            );
+            let at_convergence = |ray: &Ray| ray.at(4.0 / dot(ray.direction(), &camera.forward()));
+
+            assert!((at_convergence(&left) - at_convergence(&right)).length() < 1e-9);
+        }
+
+        // ODS eyes straight ahead sit beside the center, looking the same way
+        let panorama = Camera {
+            projection: Projection::Equirectangular,
+            ..camera
+        };
+        let ray = panorama.eye(0.1).ray_through(8.0, 8.0).unwrap();
+
+        assert!((*ray.origin() - Point3::new(0.1, 0.0, 0.0)).length() < 1e-9);
+        assert!((ray.direction().normalize().unwrap() - panorama.forward()).length() < 1e-9);
+    }
+
+    // the scheme tiles replaced, as the baseline of the benchmark below: four threads
+    // spawned for every pixel, each taking a quarter of its samples
+    fn render_with_threads_per_pixel(camera: &Camera, world: &HittableList, film: &Film) {
+        let count = camera.samples_per_pass();
+        let per_thread = count.div_ceil(4);
+
+        for j in 0..camera.image_height {
+            for i in 0..camera.image_width {
+                let samples: Vec<_> = thread::scope(|scope| {
+                    let joins: Vec<_> = (0..4)
+                        .map(|t| {
+                            scope.spawn(move || {
+                                let start = u32::min(t * per_thread, count);
+                                let end = u32::min(start + per_thread, count);
+                                let first = PixelSample {
+                                    i,
+                                    j,
+                                    index: start,
+                                    count,
+                                    seed: 0,
+                                };
+
+                                camera.take_samples(world, film, first, end - start)
+                            })
+                        })
+                        .collect();
+
+                    joins
+                        .into_iter()
+                        .flat_map(|join| join.join().unwrap())
+                        .collect()
+                });
+
+                for (x, y, sample) in &samples {
+                    film.add_sample(*x, *y, sample);
+                }
+            }
+        }
+    }
+
+    // cargo test --release -- --ignored --nocapture tiles_render_faster
+    #[test]
+    #[ignore]
+    fn tiles_render_faster_than_threads_per_pixel() {
+        let mut world = HittableList::new();
+        let material = Arc::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, -100.5, -1.0),
+            100.0,
+            material.clone(),
+        )));
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            material,
+        )));
+
+        let world = Arc::new(world);
+        let camera = Builder::new()
+            .set_image_width(160)
+            .set_image_aspect_ratio(16.0 / 9.0)
+            .set_samples_per_pixel(32)
+            .set_max_depth(10)
+            .set_vfov(90.0)
+            .set_lookfrom(&Point3::new(0.0, 0.0, 0.0))
+            .set_lookat(&Point3::new(0.0, 0.0, -1.0))
+            .set_vup(&Vector3::new(0.0, 1.0, 0.0))
+            .set_focus_dist(1.0)
+            .set_seed(1)
+            .build();
+
+        let start = Instant::now();
+        let film = Film::new(
+            camera.image_width,
+            camera.image_height,
+            camera.filter.clone(),
+        );
+        render_with_threads_per_pixel(&camera, &world, &film);
+        let per_pixel = start.elapsed();
+
+        let start = Instant::now();
+        camera.render_film(&world, None, &mut io::sink()).unwrap();
+        let tiled = start.elapsed();
 
-        self.center + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v)
+        eprintln!(
+            "threads per pixel: {:?}, tiles on {} threads: {:?} ({:.1}x faster)",
+            per_pixel,
+            camera.threads,
+            tiled,
+            per_pixel.as_secs_f64() / tiled.as_secs_f64()
+        );
+        assert!(tiled < per_pixel);
     }
 }
@@ -0,0 +1,240 @@
+//! Saving renders in progress and resuming them.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::aov::AovFilm;
+use crate::film::Film;
+
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+/// Saves the render in progress to `path` every so often, between batches of finished
+/// tiles or at the end of a pass (or of a round of a progressive render), so that it can
+/// carry on from there after being interrupted. Stereo renders keep one file per eye next
+/// to `path`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    path: PathBuf,
+    interval: Duration,
+    resume: bool,
+}
+
+impl Checkpoint {
+    /// saving every five minutes, starting over rather than resuming
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            interval: Duration::from_secs(300),
+            resume: false,
+        }
+    }
+
+    /// the least time between saves
+    pub fn set_interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Continues the render saved at the path if there is one. A checkpoint made for
+    /// another scene or camera fails the render rather than mixing the two.
+    pub fn set_resume(&mut self, resume: bool) -> &mut Self {
+        self.resume = resume;
+        self
+    }
+
+    /// the same checkpoint for frame `frame` of an animation, in a file of its own
+    pub(crate) fn for_frame(&self, frame: u32) -> Self {
+        Self {
+            path: self.path(Some(&format!("frame_{:04}", frame))),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub(crate) fn resume(&self) -> bool {
+        self.resume
+    }
+
+    /// the file for the film called `name` ("left" or "right" for the eyes), or the path
+    /// itself for the only one
+    pub(crate) fn path(&self, name: Option<&str>) -> PathBuf {
+        match name {
+            None => self.path.clone(),
+            Some(name) => {
+                let mut path = self.path.clone().into_os_string();
+                path.push(".");
+                path.push(name);
+                PathBuf::from(path)
+            }
+        }
+    }
+}
+
+/// Where a render stands, besides what its film holds: the hash of the scene and camera it
+/// renders, the seed its samples derive from (which is all the random state there is, as
+/// every sample draws its numbers from the seed and its index), the next pass, round and
+/// tile to render and the time spent so far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Progress {
+    pub scene_hash: u64,
+    pub seed: u64,
+    pub pass: u32,
+    pub round: u32,
+    /// the tiles of the round before this one are in the film already
+    pub tile: u32,
+    pub splat_samples_per_pixel: f64,
+    pub elapsed: Duration,
+}
+
+/// writes beside `path` and then moves the file over it, so an interruption while saving
+/// leaves the previous checkpoint intact
+pub(crate) fn save(
+    path: &Path,
+    progress: &Progress,
+    film: &Film,
+    aovs: Option<&AovFilm>,
+) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    let mut file = BufWriter::new(File::create(&partial)?);
+
+    file.write_all(MAGIC)?;
+    file.write_all(&progress.scene_hash.to_le_bytes())?;
+    file.write_all(&film.width().to_le_bytes())?;
+    file.write_all(&film.height().to_le_bytes())?;
+    file.write_all(&progress.seed.to_le_bytes())?;
+    file.write_all(&progress.pass.to_le_bytes())?;
+    file.write_all(&progress.round.to_le_bytes())?;
+    file.write_all(&progress.tile.to_le_bytes())?;
+    file.write_all(&progress.splat_samples_per_pixel.to_le_bytes())?;
+    file.write_all(&progress.elapsed.as_secs_f64().to_le_bytes())?;
+    film.write_to(&mut file)?;
+    if let Some(aovs) = aovs {
+        aovs.write_to(&mut file)?;
+    }
+    file.flush()?;
+    drop(file);
+
+    fs::rename(partial, path)
+}
+
+/// Reads the checkpoint at `path` into `film` and `aovs`, None if there is no file. Fails
+/// if the checkpoint was made for another scene or camera than the one hashing to
+/// `scene_hash` (which tells whether it has AOVs).
+pub(crate) fn load(
+    path: &Path,
+    scene_hash: u64,
+    film: &Film,
+    aovs: Option<&mut AovFilm>,
+) -> io::Result<Option<Progress>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    let mut input = BufReader::new(file);
+    let mut magic = [0; 8];
+
+    input.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is not a render checkpoint", path.display()),
+        ));
+    }
+
+    let saved_hash = read_u64(&mut input)?;
+    let width = read_u32(&mut input)?;
+    let height = read_u32(&mut input)?;
+
+    if saved_hash != scene_hash || width != film.width() || height != film.height() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} was saved for a different scene or camera; delete it or render without resuming",
+                path.display()
+            ),
+        ));
+    }
+
+    let seed = read_u64(&mut input)?;
+    let pass = read_u32(&mut input)?;
+    let round = read_u32(&mut input)?;
+    let tile = read_u32(&mut input)?;
+    let splat_samples_per_pixel = f64::from_bits(read_u64(&mut input)?);
+    let elapsed = Duration::from_secs_f64(f64::from_bits(read_u64(&mut input)?));
+
+    film.read_from(&mut input)?;
+    if let Some(aovs) = aovs {
+        aovs.read_from(&mut input)?;
+    }
+
+    Ok(Some(Progress {
+        scene_hash,
+        seed,
+        pass,
+        round,
+        tile,
+        splat_samples_per_pixel,
+        elapsed,
+    }))
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::filter::BoxFilter;
+    use std::env;
+    use std::sync::Arc;
+
+    #[test]
+    fn checkpoints_only_load_into_the_same_scene() {
+        let path = env::temp_dir().join(format!("checkpoint-{}.ckpt", std::process::id()));
+        let film = Film::new(3, 2, Arc::new(BoxFilter::new(0.5)));
+        film.add_sample(1.5, 0.5, &Color::new(0.25, 0.5, 1.0));
+        film.add_splat(2.5, 1.5, &Color::new(1.0, 0.0, 0.0));
+
+        let progress = Progress {
+            scene_hash: 42,
+            seed: 7,
+            pass: 1,
+            round: 3,
+            tile: 5,
+            splat_samples_per_pixel: 2.5,
+            elapsed: Duration::from_secs(90),
+        };
+        save(&path, &progress, &film, None).unwrap();
+
+        let loaded = Film::new(3, 2, Arc::new(BoxFilter::new(0.5)));
+        assert_eq!(load(&path, 42, &loaded, None).unwrap(), Some(progress));
+        assert_eq!(loaded.pixel(1, 0, 1.0), film.pixel(1, 0, 1.0));
+        assert_eq!(loaded.pixel(2, 1, 1.0), film.pixel(2, 1, 1.0));
+        assert_eq!(loaded.sample_count(1, 0), 1);
+
+        let error = load(&path, 43, &loaded, None).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load(&path, 42, &loaded, None).unwrap(), None);
+    }
+}
@@ -1,37 +1,81 @@
+//! Colors and writing them to images.
+
 use crate::{util, vec3::Vector3};
 use std::io::Write;
 use util::interval::Interval;
 
+/// Linear RGB.
 pub type Color = Vector3;
 
+/// the sRGB transfer function: linear near black, then a 1/2.4 power curve
 #[inline]
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    if linear_component > 0.0 {
-        f64::sqrt(linear_component)
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    if linear_component <= 0.0 {
+        0.0
+    } else if linear_component <= 0.003_130_8 {
+        12.92 * linear_component
     } else {
+        1.055 * f64::powf(linear_component, 1.0 / 2.4) - 0.055
+    }
+}
+
+/// the inverse of `linear_to_srgb`
+#[inline]
+pub fn srgb_to_linear(encoded_component: f64) -> f64 {
+    if encoded_component <= 0.0 {
         0.0
+    } else if encoded_component <= 0.040_45 {
+        encoded_component / 12.92
+    } else {
+        f64::powf((encoded_component + 0.055) / 1.055, 2.4)
     }
 }
 
+/// relative luminance of linear sRGB
+#[inline]
+pub fn luminance(color: &Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
 const INTENSITY: Interval = Interval::new(0.000, 0.999);
 
+/// the color as 8-bit values for display, sRGB encoded and clamped; anything brighter than
+/// 1 wants tone mapping first
+pub fn to_bytes(pixel_color: &Color) -> [u8; 3] {
+    let byte = |linear: f64| (256.0 * INTENSITY.clamps(linear_to_srgb(linear))) as u8;
+
+    [
+        byte(pixel_color.x()),
+        byte(pixel_color.y()),
+        byte(pixel_color.z()),
+    ]
+}
+
+/// writes the color as a line of a plain PPM, sRGB encoded and clamped to bytes
 pub fn write_color<W>(out: &mut W, pixel_color: &Color) -> std::io::Result<()>
 where
     W: Write,
 {
-    let mut r = pixel_color.x();
-    let mut g = pixel_color.y();
-    let mut b = pixel_color.z();
+    let [rbyte, gbyte, bbyte] = to_bytes(pixel_color);
 
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+    out.write_all(format!("{rbyte} {gbyte} {bbyte}\n").as_bytes())?;
 
-    let rbyte: i32 = (256.0 * INTENSITY.clamps(r)) as i32;
-    let gbyte: i32 = (256.0 * INTENSITY.clamps(g)) as i32;
-    let bbyte: i32 = (256.0 * INTENSITY.clamps(b)) as i32;
+    Ok(())
+}
 
-    out.write(format!("{rbyte} {gbyte} {bbyte}\n").as_bytes())?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(())
+    #[test]
+    fn the_srgb_curve_is_continuous_and_inverts() {
+        let knee = 0.003_130_8;
+        assert!(f64::abs(12.92 * knee - (1.055 * f64::powf(knee, 1.0 / 2.4) - 0.055)) < 1e-6);
+
+        for linear in [0.0, 0.001, 0.01, 0.18, 0.5, 1.0] {
+            assert!(f64::abs(srgb_to_linear(linear_to_srgb(linear)) - linear) < 1e-12);
+        }
+
+        assert_eq!(to_bytes(&Color::new(0.0, 0.18, 1.0)), [0, 118, 255]);
+    }
 }
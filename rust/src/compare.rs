@@ -0,0 +1,525 @@
+//! Comparing a render against a reference: the mean squared error and PSNR of the linear
+//! values, the structural similarity of the luminance as displayed, and an error close to
+//! how different the two look, after LDR-FLIP (Andersson et al. 2020). FLIP judges the
+//! images as a display shows them, so values past 1 count as 1.
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+use crate::color::{linear_to_srgb, luminance, srgb_to_linear, Color};
+use crate::image::Image;
+
+/// How many pixels one degree of the viewer's field of view spans: a 0.7 m wide 4K monitor
+/// seen from 0.7 m, as FLIP assumes by default.
+const PIXELS_PER_DEGREE: f64 = 67.0;
+
+/// How two images differ, and where.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    /// mean squared error over the channels of every pixel
+    pub mse: f64,
+    /// root mean squared error
+    pub rmse: f64,
+    /// in decibels, for a peak of 1; infinite for identical images
+    pub psnr: f64,
+    /// 1 for identical images
+    pub ssim: f64,
+    /// the mean of the FLIP error map, between 0 for no visible difference and 1
+    pub flip: f64,
+    /// the FLIP error of every pixel, as gray
+    pub flip_map: Image,
+}
+
+impl Comparison {
+    /// the FLIP error map through the magma colormap, black where nothing differs
+    pub fn false_color(&self) -> Image {
+        Image::from_fn(self.flip_map.width(), self.flip_map.height(), |i, j| {
+            magma(self.flip_map.pixel(i, j).x())
+        })
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "MSE   {:.6}", self.mse)?;
+        writeln!(f, "RMSE  {:.6}", self.rmse)?;
+        writeln!(f, "PSNR  {:.2} dB", self.psnr)?;
+        writeln!(f, "SSIM  {:.4}", self.ssim)?;
+        write!(f, "FLIP  {:.4}", self.flip)
+    }
+}
+
+/// `test` measured against `reference`, which must be the same size
+pub fn compare(reference: &Image, test: &Image) -> io::Result<Comparison> {
+    if (reference.width(), reference.height()) != (test.width(), test.height()) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "can't compare a {} x {} image with a {} x {} one",
+                test.width(),
+                test.height(),
+                reference.width(),
+                reference.height()
+            ),
+        ));
+    }
+
+    let mse = reference
+        .pixels()
+        .iter()
+        .zip(test.pixels())
+        .map(|(a, b)| (*a - *b).length_squared() / 3.0)
+        .sum::<f64>()
+        / reference.pixels().len().max(1) as f64;
+    let flip_map = flip(reference, test);
+    let flip = flip_map.pixels().iter().map(|error| error.x()).sum::<f64>()
+        / flip_map.pixels().len().max(1) as f64;
+
+    Ok(Comparison {
+        mse,
+        rmse: f64::sqrt(mse),
+        psnr: -10.0 * f64::log10(mse),
+        ssim: ssim(reference, test),
+        flip,
+        flip_map,
+    })
+}
+
+/// the mean structural similarity of the sRGB encoded luminances, over 11 x 11 Gaussian
+/// windows with a standard deviation of 1.5 pixels
+fn ssim(reference: &Image, test: &Image) -> f64 {
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let (width, height) = (reference.width(), reference.height());
+    let gray = |image: &Image| -> Vec<f64> {
+        image
+            .pixels()
+            .iter()
+            .map(|color| linear_to_srgb(f64::clamp(luminance(color), 0.0, 1.0)))
+            .collect()
+    };
+    let (x, y) = (gray(reference), gray(test));
+    let window = gaussian(1.5, 5);
+    let blur = |values: &[f64]| convolve(width, height, values, &window, &window);
+    let product =
+        |a: &[f64], b: &[f64]| -> Vec<f64> { a.iter().zip(b).map(|(a, b)| a * b).collect() };
+
+    let (mean_x, mean_y) = (blur(&x), blur(&y));
+    let (square_x, square_y) = (blur(&product(&x, &x)), blur(&product(&y, &y)));
+    let cross = blur(&product(&x, &y));
+
+    let total: f64 = (0..x.len())
+        .map(|k| {
+            let (mx, my) = (mean_x[k], mean_y[k]);
+            let variance_x = square_x[k] - mx * mx;
+            let variance_y = square_y[k] - my * my;
+            let covariance = cross[k] - mx * my;
+
+            (2.0 * mx * my + C1) * (2.0 * covariance + C2)
+                / ((mx * mx + my * my + C1) * (variance_x + variance_y + C2))
+        })
+        .sum();
+
+    total / x.len().max(1) as f64
+}
+
+/// The contrast sensitivity of the eye to the opponent channels of YCxCz, as the weights and
+/// spreads of up to two Gaussians in degrees of the field of view.
+const CONTRAST_SENSITIVITY: [[(f64, f64); 2]; 3] = [
+    [(1.0, 0.0047), (0.0, 1e-5)],
+    [(1.0, 0.0053), (0.0, 1e-5)],
+    [(34.1, 0.04), (13.5, 0.025)],
+];
+
+/// the FLIP error of every pixel: the color difference after filtering both images as the
+/// eye would, raised toward 1 where edges or points differ
+fn flip(reference: &Image, test: &Image) -> Image {
+    let (width, height) = (reference.width(), reference.height());
+    let clamped = |image: &Image| -> Vec<Color> {
+        image
+            .pixels()
+            .iter()
+            .map(|color| per_channel(color, |x| f64::clamp(x, 0.0, 1.0)))
+            .collect()
+    };
+    let (reference, test) = (clamped(reference), clamped(test));
+
+    let color_errors = {
+        let lab_reference = perceived(width, height, &reference);
+        let lab_test = perceived(width, height, &test);
+        let max = f64::powf(
+            hyab(
+                &hunt(&lab(&Color::new(0.0, 1.0, 0.0))),
+                &hunt(&lab(&Color::new(0.0, 0.0, 1.0))),
+            ),
+            0.7,
+        );
+
+        lab_reference
+            .iter()
+            .zip(&lab_test)
+            .map(|(a, b)| {
+                // errors past a fraction of the largest are squeezed into the top of [0, 1]
+                let (p_c, p_t) = (0.4, 0.95);
+                let error = f64::powf(hyab(a, b), 0.7);
+
+                if error < p_c * max {
+                    p_t / (p_c * max) * error
+                } else {
+                    p_t + (error - p_c * max) / (max - p_c * max) * (1.0 - p_t)
+                }
+            })
+            .collect::<Vec<f64>>()
+    };
+
+    let features_reference = features(width, height, &reference);
+    let features_test = features(width, height, &test);
+
+    Image::from_fn(width, height, |i, j| {
+        let k = (j * width + i) as usize;
+        let (edge_a, point_a) = features_reference[k];
+        let (edge_b, point_b) = features_test[k];
+        let feature_error = f64::sqrt(
+            f64::max(f64::abs(edge_a - edge_b), f64::abs(point_a - point_b)) / f64::sqrt(2.0),
+        );
+        let error = f64::powf(color_errors[k], 1.0 - feature_error);
+
+        Color::new(error, error, error)
+    })
+}
+
+/// the colors as the eye resolves them, in Hunt adjusted L*a*b*: blurred in YCxCz by the
+/// contrast sensitivity of each channel
+fn perceived(width: u32, height: u32, colors: &[Color]) -> Vec<Color> {
+    let opponent: Vec<Color> = colors.iter().map(ycxcz).collect();
+    let radius = CONTRAST_SENSITIVITY
+        .iter()
+        .flatten()
+        .map(|&(_, b)| f64::ceil(3.0 * f64::sqrt(b / (2.0 * PI_SQUARED)) * PIXELS_PER_DEGREE))
+        .fold(0.0, f64::max) as usize;
+
+    let filtered: Vec<Vec<f64>> = CONTRAST_SENSITIVITY
+        .iter()
+        .enumerate()
+        .map(|(channel, gaussians)| {
+            let values: Vec<f64> = opponent
+                .iter()
+                .map(|color| [color.x(), color.y(), color.z()][channel])
+                .collect();
+            // each Gaussian is separable; the pair is weighted so the whole sums to 1
+            let terms: Vec<(f64, Vec<f64>)> = gaussians
+                .iter()
+                .filter(|(a, _)| *a > 0.0)
+                .map(|&(a, b)| {
+                    let kernel: Vec<f64> = (-(radius as i64)..=radius as i64)
+                        .map(|x| {
+                            let x = x as f64 / PIXELS_PER_DEGREE;
+                            f64::exp(-PI_SQUARED * x * x / b)
+                        })
+                        .collect();
+                    let sum: f64 = kernel.iter().sum();
+
+                    (a * f64::sqrt(std::f64::consts::PI / b) * sum * sum, kernel)
+                })
+                .collect();
+            let total: f64 = terms.iter().map(|(weight, _)| weight).sum();
+            let mut result = vec![0.0; values.len()];
+
+            for (weight, kernel) in &terms {
+                let sum: f64 = kernel.iter().sum();
+                let normalized: Vec<f64> = kernel.iter().map(|k| k / sum).collect();
+                let blurred = convolve(width, height, &values, &normalized, &normalized);
+
+                for (result, value) in result.iter_mut().zip(blurred) {
+                    *result += weight / total * value;
+                }
+            }
+
+            result
+        })
+        .collect();
+
+    (0..colors.len())
+        .map(|k| {
+            let filtered = Color::new(filtered[0][k], filtered[1][k], filtered[2][k]);
+            let rgb = per_channel(&xyz_to_rgb(&ycxcz_to_xyz(&filtered)), |x| {
+                f64::clamp(x, 0.0, 1.0)
+            });
+
+            hunt(&lab(&rgb))
+        })
+        .collect()
+}
+
+/// the edge and point strengths of the luminance around every pixel: the lengths of its
+/// first and second derivatives over Gaussians as wide as the eye picks them out
+fn features(width: u32, height: u32, colors: &[Color]) -> Vec<(f64, f64)> {
+    let sigma = 0.5 * 0.082 * PIXELS_PER_DEGREE;
+    let radius = f64::ceil(3.0 * sigma) as usize;
+    let smooth = gaussian(sigma, radius);
+    let offsets = || (-(radius as i64)..=radius as i64).map(|x| x as f64);
+    let edge = balanced(offsets().zip(&smooth).map(|(x, g)| -x * g).collect());
+    let point = balanced(
+        offsets()
+            .zip(&smooth)
+            .map(|(x, g)| (x * x / (sigma * sigma) - 1.0) * g)
+            .collect(),
+    );
+
+    let values: Vec<f64> = colors.iter().map(luminance).collect();
+    let length = |a: Vec<f64>, b: Vec<f64>| -> Vec<f64> {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| f64::sqrt(a * a + b * b))
+            .collect()
+    };
+    let edges = length(
+        convolve(width, height, &values, &edge, &smooth),
+        convolve(width, height, &values, &smooth, &edge),
+    );
+    let points = length(
+        convolve(width, height, &values, &point, &smooth),
+        convolve(width, height, &values, &smooth, &point),
+    );
+
+    edges.into_iter().zip(points).collect()
+}
+
+const PI_SQUARED: f64 = std::f64::consts::PI * std::f64::consts::PI;
+
+/// a Gaussian of standard deviation `sigma` reaching `radius` each way, summing to 1
+fn gaussian(sigma: f64, radius: usize) -> Vec<f64> {
+    let kernel: Vec<f64> = (-(radius as i64)..=radius as i64)
+        .map(|x| f64::exp(-((x * x) as f64) / (2.0 * sigma * sigma)))
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+
+    kernel.into_iter().map(|k| k / sum).collect()
+}
+
+/// the kernel scaled so its positive weights sum to 1 and its negative ones to -1
+fn balanced(kernel: Vec<f64>) -> Vec<f64> {
+    let positive: f64 = kernel.iter().filter(|k| **k > 0.0).sum();
+    let negative: f64 = -kernel.iter().filter(|k| **k < 0.0).sum::<f64>();
+
+    kernel
+        .into_iter()
+        .map(|k| if k > 0.0 { k / positive } else { k / negative })
+        .collect()
+}
+
+/// `values` convolved with `horizontal` along rows and `vertical` along columns, repeating
+/// the pixels at the border
+fn convolve(
+    width: u32,
+    height: u32,
+    values: &[f64],
+    horizontal: &[f64],
+    vertical: &[f64],
+) -> Vec<f64> {
+    let (width, height) = (width as i64, height as i64);
+    let pass = |values: &[f64], kernel: &[f64], along_rows: bool| -> Vec<f64> {
+        let radius = (kernel.len() / 2) as i64;
+
+        (0..height)
+            .flat_map(|j| (0..width).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, weight)| {
+                        let offset = k as i64 - radius;
+                        let (x, y) = if along_rows {
+                            ((i + offset).clamp(0, width - 1), j)
+                        } else {
+                            (i, (j + offset).clamp(0, height - 1))
+                        };
+
+                        weight * values[(y * width + x) as usize]
+                    })
+                    .sum()
+            })
+            .collect()
+    };
+
+    pass(&pass(values, horizontal, true), vertical, false)
+}
+
+fn per_channel(color: &Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(color.x()), f(color.y()), f(color.z()))
+}
+
+fn multiply(matrix: &[[f64; 3]; 3], color: &Color) -> Color {
+    let row = |r: &[f64; 3]| r[0] * color.x() + r[1] * color.y() + r[2] * color.z();
+
+    Color::new(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]))
+}
+
+const RGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175_0],
+    [0.019_333_9, 0.119_192_0, 0.950_304_1],
+];
+
+const XYZ_TO_RGB: [[f64; 3]; 3] = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266_0, 1.876_010_8, 0.041_556_0],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+
+/// the XYZ of linear white, which YCxCz and L*a*b* are relative to
+fn white() -> Color {
+    multiply(&RGB_TO_XYZ, &Color::new(1.0, 1.0, 1.0))
+}
+
+fn xyz_to_rgb(xyz: &Color) -> Color {
+    multiply(&XYZ_TO_RGB, xyz)
+}
+
+/// the linear sRGB color in YCxCz: a luminance and two opponent channels, linear in XYZ
+fn ycxcz(color: &Color) -> Color {
+    let xyz = multiply(&RGB_TO_XYZ, color);
+    let white = white();
+    let (x, y, z) = (
+        xyz.x() / white.x(),
+        xyz.y() / white.y(),
+        xyz.z() / white.z(),
+    );
+
+    Color::new(116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z))
+}
+
+fn ycxcz_to_xyz(ycxcz: &Color) -> Color {
+    let white = white();
+    let y = (ycxcz.x() + 16.0) / 116.0;
+    let x = y + ycxcz.y() / 500.0;
+    let z = y - ycxcz.z() / 200.0;
+
+    Color::new(x * white.x(), y * white.y(), z * white.z())
+}
+
+/// the linear sRGB color in CIE L*a*b*
+fn lab(color: &Color) -> Color {
+    let xyz = multiply(&RGB_TO_XYZ, color);
+    let white = white();
+    let f = |t: f64| {
+        let delta: f64 = 6.0 / 29.0;
+
+        if t > delta * delta * delta {
+            f64::cbrt(t)
+        } else {
+            t / (3.0 * delta * delta) + 4.0 / 29.0
+        }
+    };
+    let (x, y, z) = (
+        f(xyz.x() / white.x()),
+        f(xyz.y() / white.y()),
+        f(xyz.z() / white.z()),
+    );
+
+    Color::new(116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z))
+}
+
+/// L*a*b* with the chroma scaled by the lightness, as colors fade in the dark
+fn hunt(lab: &Color) -> Color {
+    let scale = 0.01 * lab.x();
+
+    Color::new(lab.x(), scale * lab.y(), scale * lab.z())
+}
+
+/// the hybrid distance: city block in lightness, Euclidean in chroma
+fn hyab(a: &Color, b: &Color) -> f64 {
+    let (da, db) = (a.y() - b.y(), a.z() - b.z());
+
+    f64::abs(a.x() - b.x()) + f64::sqrt(da * da + db * db)
+}
+
+/// the magma colormap at `t` in [0, 1], linear
+fn magma(t: f64) -> Color {
+    const STOPS: [[u8; 3]; 9] = [
+        [0x00, 0x00, 0x04],
+        [0x1c, 0x10, 0x44],
+        [0x4f, 0x12, 0x7b],
+        [0x81, 0x25, 0x81],
+        [0xb5, 0x36, 0x7a],
+        [0xe5, 0x50, 0x64],
+        [0xfb, 0x87, 0x61],
+        [0xfe, 0xc2, 0x87],
+        [0xfc, 0xfd, 0xbf],
+    ];
+
+    let position = f64::clamp(t, 0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let index = usize::min(position as usize, STOPS.len() - 2);
+    let fraction = position - index as f64;
+    let channel = |c: usize| {
+        let (a, b) = (STOPS[index][c] as f64, STOPS[index + 1][c] as f64);
+        srgb_to_linear((a + (b - a) * fraction) / 255.0)
+    };
+
+    Color::new(channel(0), channel(1), channel(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(size: u32) -> Image {
+        Image::from_fn(size, size, |i, j| {
+            let bright = (i / 4 + j / 4) % 2 == 0;
+            let value = if bright { 0.8 } else { 0.1 };
+
+            Color::new(value, value * 0.5, 0.2)
+        })
+    }
+
+    #[test]
+    fn identical_images_have_no_error() {
+        let image = checkerboard(24);
+        let comparison = compare(&image, &image).unwrap();
+
+        assert_eq!(comparison.mse, 0.0);
+        assert_eq!(comparison.psnr, f64::INFINITY);
+        assert!(f64::abs(comparison.ssim - 1.0) < 1e-9);
+        assert!(comparison.flip < 1e-9);
+        assert_eq!(comparison.false_color().pixel(3, 3), magma(0.0));
+
+        assert!(compare(&image, &Image::new(24, 23)).is_err());
+    }
+
+    #[test]
+    fn errors_grow_with_the_difference() {
+        let reference = checkerboard(24);
+        let distort = |amount: f64| {
+            let mut image = reference.clone();
+
+            for (k, pixel) in image.pixels_mut().iter_mut().enumerate() {
+                let sign = if k % 3 == 0 { 1.0 } else { -0.5 };
+                *pixel += Color::new(1.0, 1.0, 1.0) * (sign * amount);
+            }
+
+            image
+        };
+        let slight = compare(&reference, &distort(0.02)).unwrap();
+        let strong = compare(&reference, &distort(0.2)).unwrap();
+
+        assert!(f64::abs(slight.rmse - f64::sqrt(slight.mse)) < 1e-12);
+        assert!(slight.mse < strong.mse);
+        assert!(slight.psnr > strong.psnr);
+        assert!(slight.ssim > strong.ssim && strong.ssim < 0.9);
+        assert!(0.0 < slight.flip && slight.flip < strong.flip && strong.flip <= 1.0);
+
+        // a blurred copy loses the checkerboard's edges, which FLIP weighs heavily
+        let (width, height) = (reference.width(), reference.height());
+        let blurred = Image::from_fn(width, height, |i, j| {
+            let neighbours = [(0, 0), (1, 0), (0, 1), (1, 1)];
+            neighbours
+                .iter()
+                .fold(Color::new_default(), |sum, (di, dj)| {
+                    sum + reference.pixel(u32::min(i + di, width - 1), u32::min(j + dj, height - 1))
+                        * 0.25
+                })
+        });
+        assert!(compare(&reference, &blurred).unwrap().flip > slight.flip);
+    }
+}
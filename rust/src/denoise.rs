@@ -0,0 +1,290 @@
+//! Denoising a rendered image with the albedo and normal AOVs as guides.
+
+use std::io::{self, ErrorKind};
+
+use crate::aov::{Aov, Layers};
+use crate::color::{luminance, Color};
+use crate::image::Image;
+use crate::vec3::{dot, Vector3};
+
+/// The B3 spline the à-trous filter spreads over its taps, in each direction.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Albedo below this is taken as this when dividing it out, so black surfaces don't blow
+/// up the noise.
+const ALBEDO_FLOOR: f64 = 0.01;
+
+/// An edge-avoiding à-trous wavelet filter, the spatial part of SVGF. The albedo is divided
+/// out first, so only the lighting is blurred and textures stay sharp; every pass then
+/// averages a 5 x 5 grid of pixels twice as far apart as the last, weighted down across
+/// edges in the normals or albedo and between pixels whose lighting differs by more than
+/// its noise explains.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoiser {
+    iterations: u32,
+    sigma_luminance: f64,
+    sigma_normal: f64,
+    sigma_albedo: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Denoiser {
+    /// five passes, with the default edge-stopping strengths
+    pub fn new() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_albedo: 0.1,
+        }
+    }
+
+    /// the number of passes; the filter reaches 2^(iterations + 1) pixels each way
+    pub fn set_iterations(&mut self, iterations: u32) -> &mut Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// how many standard deviations of noise apart two pixels' lighting may be and still
+    /// be averaged
+    pub fn set_sigma_luminance(&mut self, sigma_luminance: f64) -> &mut Self {
+        self.sigma_luminance = sigma_luminance;
+        self
+    }
+
+    /// the power of the cosine between normals; higher keeps creases sharper
+    pub fn set_sigma_normal(&mut self, sigma_normal: f64) -> &mut Self {
+        self.sigma_normal = sigma_normal;
+        self
+    }
+
+    /// how far apart albedos may be before their pixels stop being averaged
+    pub fn set_sigma_albedo(&mut self, sigma_albedo: f64) -> &mut Self {
+        self.sigma_albedo = sigma_albedo;
+        self
+    }
+
+    /// the beauty image of `layers`, denoised; the layers need the albedo and normal AOVs
+    pub fn denoise_layers(&self, layers: &Layers) -> io::Result<Image> {
+        match (layers.aov(Aov::Albedo), layers.aov(Aov::Normal)) {
+            (Some(albedo), Some(normal)) => Ok(self.denoise(&layers.beauty, albedo, normal)),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "denoising needs the albedo and normal AOVs",
+            )),
+        }
+    }
+
+    /// `beauty` denoised, guided by `albedo` and `normal` images of the same size
+    pub fn denoise(&self, beauty: &Image, albedo: &Image, normal: &Image) -> Image {
+        let (width, height) = (beauty.width(), beauty.height());
+        assert!(
+            (albedo.width(), albedo.height()) == (width, height)
+                && (normal.width(), normal.height()) == (width, height),
+            "the guides must be the size of the image"
+        );
+
+        let floor = |albedo: &Color| {
+            Color::new(
+                f64::max(albedo.x(), ALBEDO_FLOOR),
+                f64::max(albedo.y(), ALBEDO_FLOOR),
+                f64::max(albedo.z(), ALBEDO_FLOOR),
+            )
+        };
+        let albedos = albedo.pixels();
+        // misses have no normal, and only resemble each other
+        let normals: Vec<Option<Vector3>> = normal
+            .pixels()
+            .iter()
+            .map(|normal| normal.normalize().ok())
+            .collect();
+
+        let mut lighting: Vec<Color> = beauty
+            .pixels()
+            .iter()
+            .zip(albedos)
+            .map(|(color, albedo)| {
+                let albedo = floor(albedo);
+                Color::new(
+                    color.x() / albedo.x(),
+                    color.y() / albedo.y(),
+                    color.z() / albedo.z(),
+                )
+            })
+            .collect();
+        let mut variance = spatial_variance(width, height, &lighting);
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let deviation: Vec<f64> = blur_3x3(width, height, &variance)
+                .into_iter()
+                .map(f64::sqrt)
+                .collect();
+            let mut next_lighting = lighting.clone();
+            let mut next_variance = variance.clone();
+
+            for j in 0..height as i64 {
+                for i in 0..width as i64 {
+                    let p = (j * width as i64 + i) as usize;
+                    let luminance_p = luminance(&lighting[p]);
+                    let mut sum = Color::new_default();
+                    let mut sum_variance = 0.0;
+                    let mut total = 0.0;
+
+                    for (dy, ky) in KERNEL.iter().enumerate() {
+                        for (dx, kx) in KERNEL.iter().enumerate() {
+                            let x = i + (dx as i64 - 2) * step;
+                            let y = j + (dy as i64 - 2) * step;
+
+                            if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                                continue;
+                            }
+
+                            let q = (y * width as i64 + x) as usize;
+                            let w_normal = match (normals[p], normals[q]) {
+                                (Some(n_p), Some(n_q)) => {
+                                    f64::powf(f64::max(dot(&n_p, &n_q), 0.0), self.sigma_normal)
+                                }
+                                (None, None) => 1.0,
+                                _ => 0.0,
+                            };
+                            let w_albedo = f64::exp(
+                                -(albedos[p] - albedos[q]).length_squared()
+                                    / (self.sigma_albedo * self.sigma_albedo),
+                            );
+                            let w_luminance = f64::exp(
+                                -f64::abs(luminance_p - luminance(&lighting[q]))
+                                    / (self.sigma_luminance * deviation[p] + 1e-10),
+                            );
+                            let weight = kx * ky * w_normal * w_albedo * w_luminance;
+
+                            sum += lighting[q] * weight;
+                            sum_variance += weight * weight * variance[q];
+                            total += weight;
+                        }
+                    }
+
+                    // the pixel itself always counts, so the total is never 0
+                    next_lighting[p] = sum * (1.0 / total);
+                    next_variance[p] = sum_variance / (total * total);
+                }
+            }
+
+            lighting = next_lighting;
+            variance = next_variance;
+        }
+
+        Image::from_fn(width, height, |i, j| {
+            let p = (j * width + i) as usize;
+            lighting[p] * floor(&albedos[p])
+        })
+    }
+}
+
+/// the variance of the luminance in the 3 x 3 pixels around each, standing in for the
+/// variance of its samples
+fn spatial_variance(width: u32, height: u32, colors: &[Color]) -> Vec<f64> {
+    let luminances: Vec<f64> = colors.iter().map(luminance).collect();
+    let mean = box_3x3(width, height, &luminances);
+    let squares: Vec<f64> = luminances.iter().map(|l| l * l).collect();
+
+    box_3x3(width, height, &squares)
+        .into_iter()
+        .zip(mean)
+        .map(|(square, mean)| f64::max(square - mean * mean, 0.0))
+        .collect()
+}
+
+fn box_3x3(width: u32, height: u32, values: &[f64]) -> Vec<f64> {
+    convolve_3x3(width, height, values, [1.0, 1.0, 1.0])
+}
+
+fn blur_3x3(width: u32, height: u32, values: &[f64]) -> Vec<f64> {
+    convolve_3x3(width, height, values, [0.25, 0.5, 0.25])
+}
+
+/// `values` convolved with `kernel` both ways, normalized over the taps inside the image
+fn convolve_3x3(width: u32, height: u32, values: &[f64], kernel: [f64; 3]) -> Vec<f64> {
+    let (width, height) = (width as i64, height as i64);
+    let mut result = Vec::with_capacity(values.len());
+
+    for j in 0..height {
+        for i in 0..width {
+            let mut sum = 0.0;
+            let mut total = 0.0;
+
+            for (dy, ky) in kernel.iter().enumerate() {
+                for (dx, kx) in kernel.iter().enumerate() {
+                    let (x, y) = (i + dx as i64 - 1, j + dy as i64 - 1);
+
+                    if x >= 0 && y >= 0 && x < width && y < height {
+                        sum += kx * ky * values[(y * width + x) as usize];
+                        total += kx * ky;
+                    }
+                }
+            }
+
+            result.push(sum / total);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn noise_is_smoothed_without_blurring_across_edges() {
+        // two walls meeting down the middle, one lit twice as brightly as the other
+        let (width, height) = (32, 16);
+        let left = |i: u32| i < width / 2;
+        let clean = Image::from_fn(width, height, |i, _| {
+            if left(i) {
+                Color::new(0.4, 0.4, 0.4)
+            } else {
+                Color::new(0.2, 0.2, 0.2)
+            }
+        });
+        let albedo = Image::from_fn(width, height, |_, _| Color::new(0.5, 0.5, 0.5));
+        let normal = Image::from_fn(width, height, |i, _| {
+            if left(i) {
+                Color::new(1.0, 0.0, 0.0)
+            } else {
+                Color::new(0.0, 0.0, 1.0)
+            }
+        });
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut noisy = clean.clone();
+        for pixel in noisy.pixels_mut() {
+            *pixel *= rng.gen_range(0.5..1.5);
+        }
+
+        let denoised = Denoiser::new().denoise(&noisy, &albedo, &normal);
+        let error = |image: &Image| {
+            image
+                .pixels()
+                .iter()
+                .zip(clean.pixels())
+                .map(|(a, b)| (*a - *b).length_squared())
+                .sum::<f64>()
+        };
+
+        assert!(error(&denoised) < error(&noisy) / 10.0);
+
+        // the pixels either side of the edge keep their own brightness
+        for j in 0..height {
+            assert!(f64::abs(denoised.pixel(width / 2 - 1, j).x() - 0.4) < 0.1);
+            assert!(f64::abs(denoised.pixel(width / 2, j).x() - 0.2) < 0.05);
+        }
+    }
+}
@@ -0,0 +1,429 @@
+//! Lens effects on the linear image, before it is tone mapped: the glow and streaks that
+//! scatter around bright lights, colored fringes toward the edges and darkened corners.
+
+use crate::color::{luminance, Color};
+use crate::image::Image;
+use crate::util::PI;
+
+/// Light past `threshold` spread into a glow: the bright parts blurred by Gaussians of
+/// `radius`, twice that, and so on for `levels` sizes, averaged and added back `strength`
+/// times over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom {
+    threshold: f64,
+    strength: f64,
+    radius: f64,
+    levels: u32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bloom {
+    /// light past 1 glowing with a tenth of its strength, over five sizes from 2 pixels
+    pub fn new() -> Self {
+        Self {
+            threshold: 1.0,
+            strength: 0.1,
+            radius: 2.0,
+            levels: 5,
+        }
+    }
+
+    /// the luminance past which light blooms
+    pub fn set_threshold(&mut self, threshold: f64) -> &mut Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// how much of the glow is added to the image
+    pub fn set_strength(&mut self, strength: f64) -> &mut Self {
+        self.strength = strength;
+        self
+    }
+
+    /// the standard deviation of the smallest Gaussian, in pixels
+    pub fn set_radius(&mut self, radius: f64) -> &mut Self {
+        self.radius = radius;
+        self
+    }
+
+    /// how many blur sizes the glow spreads over, each twice the last
+    pub fn set_levels(&mut self, levels: u32) -> &mut Self {
+        self.levels = u32::max(levels, 1);
+        self
+    }
+
+    /// the glow to add to `image`
+    fn glow(&self, image: &Image) -> Image {
+        let mut blurred = bright_pass(image, self.threshold);
+        let mut glow = Image::new(image.width(), image.height());
+        let mut sigma = 0.0;
+
+        // each level blurs the last by what it takes to reach the next size
+        for level in 0..self.levels {
+            let next = self.radius * f64::exp2(level as f64);
+            blurred = gaussian_blur(&blurred, f64::sqrt(next * next - sigma * sigma));
+            sigma = next;
+
+            add(&mut glow, &blurred, self.strength / self.levels as f64);
+        }
+
+        glow
+    }
+}
+
+/// Light past `threshold` drawn out into a star of `streaks` arms, evenly spaced from
+/// `rotation` degrees and fading by half every `length` pixels, `strength` times as bright
+/// in all as the light they start from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glare {
+    threshold: f64,
+    strength: f64,
+    streaks: u32,
+    length: f64,
+    rotation: f64,
+}
+
+impl Default for Glare {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Glare {
+    /// light past 2 drawn into six streaks a twentieth as bright, fading by half every
+    /// 8 pixels, from 15 degrees
+    pub fn new() -> Self {
+        Self {
+            threshold: 2.0,
+            strength: 0.05,
+            streaks: 6,
+            length: 8.0,
+            rotation: 15.0,
+        }
+    }
+
+    /// the luminance past which light streaks
+    pub fn set_threshold(&mut self, threshold: f64) -> &mut Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// how bright the streaks are in all, as a fraction of the light they start from
+    pub fn set_strength(&mut self, strength: f64) -> &mut Self {
+        self.strength = strength;
+        self
+    }
+
+    /// how many streaks every bright pixel gets; none turns glare off
+    pub fn set_streaks(&mut self, streaks: u32) -> &mut Self {
+        self.streaks = streaks;
+        self
+    }
+
+    /// the distance in pixels over which a streak fades to half
+    pub fn set_length(&mut self, length: f64) -> &mut Self {
+        self.length = length;
+        self
+    }
+
+    /// the angle of the first streak, counterclockwise from pointing right, in degrees
+    pub fn set_rotation(&mut self, rotation: f64) -> &mut Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// the streaks to add to `image`
+    fn streaks(&self, image: &Image) -> Image {
+        let (width, height) = (image.width(), image.height());
+        let bright = bright_pass(image, self.threshold);
+        let mut streaks = Image::new(width, height);
+
+        if self.streaks == 0 {
+            return streaks;
+        }
+
+        // out to where a streak has faded to a thousandth
+        let reach = (self.length * f64::log2(1000.0)).ceil() as u32;
+        let falloff = f64::exp2(-1.0 / self.length);
+        let total: f64 = (1..=reach).map(|step| falloff.powi(step as i32)).sum();
+        let per_step = self.strength / (self.streaks as f64 * total);
+
+        for j in 0..height {
+            for i in 0..width {
+                let source = bright.pixel(i, j);
+
+                if source.near_zero() {
+                    continue;
+                }
+
+                for streak in 0..self.streaks {
+                    let angle =
+                        (self.rotation / 180.0 + 2.0 * streak as f64 / self.streaks as f64) * PI;
+                    // rows run down the image
+                    let (dx, dy) = (f64::cos(angle), -f64::sin(angle));
+
+                    for step in 1..=reach {
+                        let x = (i as f64 + 0.5 + dx * step as f64).floor();
+                        let y = (j as f64 + 0.5 + dy * step as f64).floor();
+
+                        if x < 0.0 || y < 0.0 || x >= width as f64 || y >= height as f64 {
+                            break;
+                        }
+
+                        let (x, y) = (x as u32, y as u32);
+                        let weight = per_step * falloff.powi(step as i32);
+                        streaks.set_pixel(x, y, &(streaks.pixel(x, y) + source * weight));
+                    }
+                }
+            }
+        }
+
+        streaks
+    }
+}
+
+/// The effects to apply, each off until set. Bloom and glare both start from the image as
+/// rendered; chromatic aberration and then vignetting follow.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Effects {
+    bloom: Option<Bloom>,
+    glare: Option<Glare>,
+    chromatic_aberration: Option<f64>,
+    vignetting: Option<f64>,
+}
+
+impl Effects {
+    /// no effects
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// spreads bright light into a glow
+    pub fn set_bloom(&mut self, bloom: Bloom) -> &mut Self {
+        self.bloom = Some(bloom);
+        self
+    }
+
+    /// draws bright light out into streaks
+    pub fn set_glare(&mut self, glare: Glare) -> &mut Self {
+        self.glare = Some(glare);
+        self
+    }
+
+    /// Red is magnified and blue shrunk about the center by `strength`, a fraction of the
+    /// distance from it, leaving fringes that widen toward the edges.
+    pub fn set_chromatic_aberration(&mut self, strength: f64) -> &mut Self {
+        self.chromatic_aberration = Some(strength);
+        self
+    }
+
+    /// Light falls off as 1 / (1 + strength r^2)^2, r being 1 in the corners: the cosine
+    /// to the fourth law of a lens whose corners are atan(sqrt(strength)) off its axis.
+    pub fn set_vignetting(&mut self, strength: f64) -> &mut Self {
+        self.vignetting = Some(strength);
+        self
+    }
+
+    /// the image with every effect set applied, the glow and streaks taken from the image
+    /// as it came
+    pub fn apply(&self, image: &Image) -> Image {
+        let mut result = image.clone();
+
+        if let Some(bloom) = &self.bloom {
+            add(&mut result, &bloom.glow(image), 1.0);
+        }
+        if let Some(glare) = &self.glare {
+            add(&mut result, &glare.streaks(image), 1.0);
+        }
+        if let Some(strength) = self.chromatic_aberration {
+            result = chromatic_aberration(&result, strength);
+        }
+        if let Some(strength) = self.vignetting {
+            result = vignetting(&result, strength);
+        }
+
+        result
+    }
+}
+
+/// the light of every pixel past `threshold` in luminance, keeping its hue
+fn bright_pass(image: &Image, threshold: f64) -> Image {
+    let mut bright = image.clone();
+
+    for pixel in bright.pixels_mut() {
+        let l = luminance(pixel);
+
+        *pixel = if l > threshold {
+            *pixel * ((l - threshold) / l)
+        } else {
+            Color::new_default()
+        };
+    }
+
+    bright
+}
+
+/// `image += other * scale`
+fn add(image: &mut Image, other: &Image, scale: f64) {
+    for (pixel, other) in image.pixels_mut().iter_mut().zip(other.pixels()) {
+        *pixel += *other * scale;
+    }
+}
+
+/// a separable Gaussian blur, normalized over the taps inside the image
+fn gaussian_blur(image: &Image, sigma: f64) -> Image {
+    if sigma <= 0.0 {
+        return image.clone();
+    }
+
+    let radius = (3.0 * sigma).ceil() as i64;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|x| f64::exp(-(x * x) as f64 / (2.0 * sigma * sigma)))
+        .collect();
+
+    let pass = |image: &Image, horizontal: bool| {
+        let (width, height) = (image.width() as i64, image.height() as i64);
+
+        Image::from_fn(image.width(), image.height(), |i, j| {
+            let mut sum = Color::new_default();
+            let mut total = 0.0;
+
+            for (offset, weight) in (-radius..=radius).zip(&kernel) {
+                let (x, y) = if horizontal {
+                    (i as i64 + offset, j as i64)
+                } else {
+                    (i as i64, j as i64 + offset)
+                };
+
+                if x >= 0 && y >= 0 && x < width && y < height {
+                    sum += image.pixel(x as u32, y as u32) * *weight;
+                    total += weight;
+                }
+            }
+
+            sum * (1.0 / total)
+        })
+    };
+
+    pass(&pass(image, true), false)
+}
+
+/// the image at continuous position (x, y), interpolated between the nearest pixel centers
+fn bilinear(image: &Image, x: f64, y: f64) -> Color {
+    let x = f64::clamp(x - 0.5, 0.0, (image.width() - 1) as f64);
+    let y = f64::clamp(y - 0.5, 0.0, (image.height() - 1) as f64);
+    let (i, j) = (x.floor() as u32, y.floor() as u32);
+    let (i1, j1) = (
+        u32::min(i + 1, image.width() - 1),
+        u32::min(j + 1, image.height() - 1),
+    );
+    let (fx, fy) = (x - i as f64, y - j as f64);
+
+    let top = image.pixel(i, j) * (1.0 - fx) + image.pixel(i1, j) * fx;
+    let bottom = image.pixel(i, j1) * (1.0 - fx) + image.pixel(i1, j1) * fx;
+
+    top * (1.0 - fy) + bottom * fy
+}
+
+fn chromatic_aberration(image: &Image, strength: f64) -> Image {
+    let (cx, cy) = (image.width() as f64 / 2.0, image.height() as f64 / 2.0);
+
+    Image::from_fn(image.width(), image.height(), |i, j| {
+        let (x, y) = (i as f64 + 0.5 - cx, j as f64 + 0.5 - cy);
+        // a channel magnified by `scale` shows here what lies 1 / scale as far out
+        let at = |scale: f64| bilinear(image, cx + x / scale, cy + y / scale);
+
+        Color::new(
+            at(1.0 + strength).x(),
+            image.pixel(i, j).y(),
+            at(1.0 - strength).z(),
+        )
+    })
+}
+
+fn vignetting(image: &Image, strength: f64) -> Image {
+    let (cx, cy) = (image.width() as f64 / 2.0, image.height() as f64 / 2.0);
+    let corner_squared = cx * cx + cy * cy;
+
+    Image::from_fn(image.width(), image.height(), |i, j| {
+        let (x, y) = (i as f64 + 0.5 - cx, j as f64 + 0.5 - cy);
+        let falloff = 1.0 + strength * (x * x + y * y) / corner_squared;
+
+        image.pixel(i, j) * (1.0 / (falloff * falloff))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point_light(size: u32, brightness: f64) -> Image {
+        let mut image = Image::from_fn(size, size, |_, _| Color::new(0.1, 0.1, 0.1));
+        image.set_pixel(
+            size / 2,
+            size / 2,
+            &Color::new(brightness, brightness, brightness),
+        );
+        image
+    }
+
+    #[test]
+    fn bloom_spreads_only_bright_light() {
+        let mut effects = Effects::new();
+        effects.set_bloom(Bloom::new());
+
+        let dim = point_light(33, 0.9);
+        assert_eq!(effects.apply(&dim), dim);
+
+        let bright = point_light(33, 100.0);
+        let bloomed = effects.apply(&bright);
+        assert!(bloomed.pixel(20, 16).x() > 0.1);
+        assert!(bloomed.pixel(20, 16).x() > bloomed.pixel(24, 16).x());
+        assert!(bloomed.pixel(24, 16).x() > bloomed.pixel(32, 16).x());
+    }
+
+    #[test]
+    fn glare_streaks_along_its_arms() {
+        let mut glare = Glare::new();
+        glare.set_streaks(4).set_rotation(0.0);
+        let mut effects = Effects::new();
+        effects.set_glare(glare);
+
+        let streaked = effects.apply(&point_light(33, 100.0));
+        let background = 0.1;
+
+        for (i, j) in [(20, 16), (12, 16), (16, 20), (16, 12)] {
+            assert!(streaked.pixel(i, j).x() > background);
+        }
+        assert_eq!(streaked.pixel(20, 20).x(), background);
+    }
+
+    #[test]
+    fn fringes_and_falloff_grow_toward_the_edges() {
+        let image = Image::from_fn(32, 32, |i, _| {
+            if i < 24 {
+                Color::new(1.0, 1.0, 1.0)
+            } else {
+                Color::new_default()
+            }
+        });
+        let mut effects = Effects::new();
+        effects.set_chromatic_aberration(0.05).set_vignetting(1.0);
+        let result = effects.apply(&image);
+
+        // red from inside the edge spills past it, blue from outside comes in
+        assert!(result.pixel(24, 16).x() > 0.0);
+        assert!(result.pixel(23, 16).z() < result.pixel(23, 16).y());
+
+        // about a quarter of the light is left in the corners
+        let r_squared = f64::powi(15.5 / 16.0, 2);
+        let corner = result.pixel(0, 0).y();
+        assert!(f64::abs(corner - 1.0 / ((1.0 + r_squared) * (1.0 + r_squared))) < 1e-9);
+        assert!(result.pixel(16, 16).y() > 0.99);
+    }
+}
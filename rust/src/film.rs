@@ -0,0 +1,379 @@
+//! The film samples are added up on, pixel by pixel, from every render thread.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::color::{luminance, Color};
+use crate::filter::Filter;
+use crate::image::Image;
+
+/// the least filter weight a pixel needs before its samples count
+const MIN_FILTER_WEIGHT: f64 = 1e-6;
+
+/// f64 accumulator that any thread can add to without a lock
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn new(value: f64) -> Self {
+        Self(AtomicU64::new(value.to_bits()))
+    }
+
+    fn add(&self, value: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+
+    fn load(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+struct AtomicColor([AtomicF64; 3]);
+
+impl AtomicColor {
+    fn new() -> Self {
+        Self([
+            AtomicF64::new(0.0),
+            AtomicF64::new(0.0),
+            AtomicF64::new(0.0),
+        ])
+    }
+
+    fn add(&self, color: &Color) {
+        self.0[0].add(color.x());
+        self.0[1].add(color.y());
+        self.0[2].add(color.z());
+    }
+
+    fn load(&self) -> Color {
+        Color::new(self.0[0].load(), self.0[1].load(), self.0[2].load())
+    }
+
+    fn store(&self, color: &Color) {
+        self.0[0].store(color.x());
+        self.0[1].store(color.y());
+        self.0[2].store(color.z());
+    }
+}
+
+/// Accumulates radiance for every pixel of the image. Camera samples are spread over the
+/// pixels around them by the reconstruction filter, while splats (e.g. from light
+/// tracing) land on the one pixel they hit and may come from any thread.
+pub struct Film {
+    width: u32,
+    height: u32,
+    filter: Arc<dyn Filter>,
+    pixels: Vec<AtomicColor>,
+    weights: Vec<AtomicF64>,
+    counts: Vec<AtomicU32>,
+    moments: Vec<[AtomicF64; 2]>, // luminance and squared luminance of the samples counted
+    splats: Vec<AtomicColor>,
+}
+
+impl Film {
+    /// an empty film of `width` x `height` pixels, reconstructing samples with `filter`
+    pub fn new(width: u32, height: u32, filter: Arc<dyn Filter>) -> Self {
+        let size = (width * height) as usize;
+
+        Self {
+            width,
+            height,
+            filter,
+            pixels: (0..size).map(|_| AtomicColor::new()).collect(),
+            weights: (0..size).map(|_| AtomicF64::new(0.0)).collect(),
+            counts: (0..size).map(|_| AtomicU32::new(0)).collect(),
+            moments: (0..size)
+                .map(|_| [AtomicF64::new(0.0), AtomicF64::new(0.0)])
+                .collect(),
+            splats: (0..size).map(|_| AtomicColor::new()).collect(),
+        }
+    }
+
+    /// in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// a camera sample taken at continuous raster position (x, y), which counts as taken
+    /// for the pixel it lies in
+    pub fn add_sample(&self, x: f64, y: f64, color: &Color) {
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return;
+        }
+
+        let home = self.index(x as u32, y as u32);
+        let luminance = luminance(color);
+
+        self.counts[home].fetch_add(1, Ordering::Relaxed);
+        self.moments[home][0].add(luminance);
+        self.moments[home][1].add(luminance * luminance);
+
+        // every pixel whose center is within the filter radius
+        let radius = self.filter.radius();
+        let first_i = f64::max(f64::floor(x - 0.5 - radius), 0.0) as u32;
+        let first_j = f64::max(f64::floor(y - 0.5 - radius), 0.0) as u32;
+        let last_i = f64::min(f64::ceil(x - 0.5 + radius), self.width as f64 - 1.0) as u32;
+        let last_j = f64::min(f64::ceil(y - 0.5 + radius), self.height as f64 - 1.0) as u32;
+
+        for j in first_j..=last_j {
+            for i in first_i..=last_i {
+                let weight = self
+                    .filter
+                    .evaluate(x - (i as f64 + 0.5), y - (j as f64 + 0.5));
+
+                if weight != 0.0 {
+                    let index = self.index(i, j);
+
+                    self.pixels[index].add(&(*color * weight));
+                    self.weights[index].add(weight);
+                }
+            }
+        }
+    }
+
+    /// the camera samples taken in pixel (i, j), splats aside
+    pub fn sample_count(&self, i: u32, j: u32) -> u32 {
+        self.counts[self.index(i, j)].load(Ordering::Relaxed)
+    }
+
+    /// Standard error of the mean luminance of the samples taken for every pixel, relative
+    /// to that mean, averaged over the pixels with at least two samples; as with adaptive
+    /// sampling, a floor on the mean keeps nearly black pixels from dominating.
+    pub fn noise(&self) -> f64 {
+        let mut total = 0.0;
+        let mut pixels = 0;
+
+        for (count, [sum, squares]) in self.counts.iter().zip(&self.moments) {
+            let count = count.load(Ordering::Relaxed) as f64;
+
+            if count < 2.0 {
+                continue;
+            }
+
+            let mean = sum.load() / count;
+            let variance =
+                f64::max(squares.load() / count - mean * mean, 0.0) * count / (count - 1.0);
+
+            total += f64::sqrt(variance / count) / f64::max(mean, 1e-3);
+            pixels += 1;
+        }
+
+        if pixels == 0 {
+            return f64::INFINITY;
+        }
+
+        total / pixels as f64
+    }
+
+    /// `x` and `y` are continuous raster coordinates, so pixel (i, j) covers [i, i + 1) x [j, j + 1)
+    pub fn add_splat(&self, x: f64, y: f64, color: &Color) {
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return;
+        }
+
+        self.splats[self.index(x as u32, y as u32)].add(color);
+    }
+
+    /// samples are a weighted average by the filter, splats are averaged over the samples
+    /// taken per pixel on average, since every sample may have splatted anywhere
+    pub fn pixel(&self, i: u32, j: u32, samples_per_pixel: f64) -> Color {
+        let index = self.index(i, j);
+        let weight = self.weights[index].load();
+        let mut color = Color::new_default();
+
+        // filters with negative lobes can leave a pixel with next to no weight, or less
+        // than none, near edges and at low sample counts; such a pixel counts as empty
+        // rather than blowing up, and what the lobes subtract stops at black
+        if weight > MIN_FILTER_WEIGHT {
+            let filtered = self.pixels[index].load() * (1.0 / weight);
+
+            color += Color::new(
+                f64::max(filtered.x(), 0.0),
+                f64::max(filtered.y(), 0.0),
+                f64::max(filtered.z(), 0.0),
+            );
+        }
+        if samples_per_pixel > 0.0 {
+            color += self.splats[index].load() * (1.0 / samples_per_pixel);
+        }
+
+        color
+    }
+
+    /// the picture accumulated so far, `samples_per_pixel` as for `pixel`
+    pub fn image(&self, samples_per_pixel: f64) -> Image {
+        Image::from_fn(self.width, self.height, |i, j| {
+            self.pixel(i, j, samples_per_pixel)
+        })
+    }
+
+    /// everything accumulated so far, for `read_from` to restore on a film of the same size
+    pub(crate) fn write_to(&self, output: &mut impl Write) -> io::Result<()> {
+        for index in 0..self.counts.len() {
+            let (pixel, splat) = (self.pixels[index].load(), self.splats[index].load());
+            let [sum, squares] = &self.moments[index];
+
+            for value in [
+                pixel.x(),
+                pixel.y(),
+                pixel.z(),
+                self.weights[index].load(),
+                sum.load(),
+                squares.load(),
+                splat.x(),
+                splat.y(),
+                splat.z(),
+            ] {
+                output.write_all(&value.to_le_bytes())?;
+            }
+
+            output.write_all(&self.counts[index].load(Ordering::Relaxed).to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read_from(&self, input: &mut impl Read) -> io::Result<()> {
+        let mut bytes = [0; 9 * 8 + 4];
+
+        for index in 0..self.counts.len() {
+            input.read_exact(&mut bytes)?;
+
+            let value = |k: usize| f64::from_le_bytes(bytes[8 * k..8 * k + 8].try_into().unwrap());
+
+            self.pixels[index].store(&Color::new(value(0), value(1), value(2)));
+            self.weights[index].store(value(3));
+            self.moments[index][0].store(value(4));
+            self.moments[index][1].store(value(5));
+            self.splats[index].store(&Color::new(value(6), value(7), value(8)));
+            self.counts[index].store(
+                u32::from_le_bytes(bytes[72..76].try_into().unwrap()),
+                Ordering::Relaxed,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn index(&self, i: u32, j: u32) -> usize {
+        (j * self.width + i) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{BoxFilter, TentFilter};
+    use std::thread;
+
+    #[test]
+    fn splats_from_many_threads_add_up() {
+        let film = Film::new(2, 2, Arc::new(BoxFilter::new(0.5)));
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        film.add_splat(1.5, 0.25, &Color::new(1.0, 0.5, 0.25));
+                    }
+                });
+            }
+        });
+
+        assert_eq!(film.pixel(1, 0, 1.0), Color::new(800.0, 400.0, 200.0));
+        assert_eq!(film.pixel(0, 0, 1.0), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn splats_outside_the_image_are_dropped() {
+        let film = Film::new(2, 2, Arc::new(BoxFilter::new(0.5)));
+
+        film.add_splat(-0.5, 1.0, &Color::new(1.0, 1.0, 1.0));
+        film.add_splat(2.0, 1.0, &Color::new(1.0, 1.0, 1.0));
+
+        for j in 0..2 {
+            for i in 0..2 {
+                assert_eq!(film.pixel(i, j, 1.0), Color::new(0.0, 0.0, 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn wide_filters_spread_samples_to_the_neighbours() {
+        let film = Film::new(3, 1, Arc::new(TentFilter::new(1.0)));
+
+        // on the borders between pixels, so only the two pixels beside each sample see it
+        film.add_sample(1.0, 0.5, &Color::new(1.0, 1.0, 1.0));
+        film.add_sample(2.0, 0.5, &Color::new(0.5, 0.5, 0.5));
+
+        assert_eq!(film.pixel(0, 0, 0.0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(film.pixel(1, 0, 0.0), Color::new(0.75, 0.75, 0.75));
+        assert_eq!(film.pixel(2, 0, 0.0), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(film.sample_count(0, 0), 0);
+        assert_eq!(film.sample_count(1, 0), 1);
+    }
+
+    /// full weight within half a pixel, then a negative lobe out to a pixel and a half
+    struct Lobed;
+
+    impl Filter for Lobed {
+        fn radius(&self) -> f64 {
+            1.5
+        }
+
+        fn evaluate(&self, x: f64, _y: f64) -> f64 {
+            match f64::abs(x) {
+                t if t < 0.5 => 1.0,
+                t if t <= 1.5 => -0.25,
+                _ => 0.0,
+            }
+        }
+    }
+
+    #[test]
+    fn negative_lobes_never_make_a_pixel_negative() {
+        let film = Film::new(3, 1, Arc::new(Lobed));
+
+        // the middle pixel only sees the negative lobes of its neighbours' samples
+        film.add_sample(0.5, 0.5, &Color::new(1.0, 1.0, 1.0));
+        film.add_sample(2.5, 0.5, &Color::new(1.0, 1.0, 1.0));
+        assert_eq!(film.pixel(1, 0, 0.0), Color::new(0.0, 0.0, 0.0));
+
+        // a dark sample of its own leaves it a positive weight but a negative sum
+        film.add_sample(1.5, 0.5, &Color::new(0.0, 0.0, 0.0));
+        assert_eq!(film.pixel(1, 0, 0.0), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(
+            film.pixel(0, 0, 0.0),
+            Color::new(1.0, 1.0, 1.0) * (1.0 / 0.75)
+        );
+    }
+
+    #[test]
+    fn noise_falls_as_samples_agree() {
+        let film = Film::new(1, 1, Arc::new(BoxFilter::new(0.5)));
+        assert_eq!(film.noise(), f64::INFINITY);
+
+        for k in 0..4 {
+            film.add_sample(0.5, 0.5, &(Color::new(1.0, 1.0, 1.0) * (k % 2) as f64));
+        }
+        let noisy = film.noise();
+
+        for _ in 0..60 {
+            film.add_sample(0.5, 0.5, &Color::new(0.5, 0.5, 0.5));
+        }
+        assert!(film.noise() < noisy / 4.0);
+    }
+}
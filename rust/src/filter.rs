@@ -0,0 +1,299 @@
+//! Reconstruction filters, which spread every sample over the pixels around it.
+
+use std::hash::Hasher;
+
+use crate::util::{hash_f64s, PI};
+
+/// Reconstruction filter: a sample taken at film position (x, y) counts toward every
+/// pixel whose center lies within `radius` of it on both axes, weighted by `evaluate` at
+/// the offset from that pixel's center. All filters here are separable.
+pub trait Filter: Sync + Send {
+    /// how far from a pixel center samples still count, in pixels
+    fn radius(&self) -> f64;
+
+    /// the weight of a sample at offset (x, y) from a pixel center
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+
+    /// feeds the kind of filter and its parameters into `state`, for the camera's
+    /// checkpoint fingerprint; the filters here feed in a name of their own and every
+    /// parameter, the default (for filters from elsewhere) the type name and the radius
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(std::any::type_name::<Self>().as_bytes());
+        hash_f64s(state, &[self.radius()]);
+    }
+}
+
+/// every sample within the radius counts the same; with radius 0.5 a pixel is the plain
+/// average of its own samples
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl BoxFilter {
+    /// counting samples within `radius` on both axes
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"box");
+        hash_f64s(state, &[self.radius]);
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        // half open, so a sample on the border of two pixels counts for one of them
+        let inside = |t: f64| -self.radius <= t && t < self.radius;
+
+        if inside(x) && inside(y) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// weights falling off linearly to zero at the radius
+pub struct TentFilter {
+    radius: f64,
+}
+
+impl TentFilter {
+    /// falling linearly to zero at `radius`
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"tent");
+        hash_f64s(state, &[self.radius]);
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        f64::max(0.0, self.radius - f64::abs(x)) * f64::max(0.0, self.radius - f64::abs(y))
+    }
+}
+
+/// a gaussian lowered so it reaches zero at the radius
+pub struct GaussianFilter {
+    radius: f64,
+    sigma: f64,
+}
+
+impl GaussianFilter {
+    /// a Gaussian of standard deviation `sigma`, cut off at `radius`
+    pub fn new(radius: f64, sigma: f64) -> Self {
+        Self { radius, sigma }
+    }
+
+    fn gaussian(&self, t: f64) -> f64 {
+        f64::exp(-t * t / (2.0 * self.sigma * self.sigma))
+    }
+
+    fn evaluate_1d(&self, t: f64) -> f64 {
+        f64::max(0.0, self.gaussian(t) - self.gaussian(self.radius))
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"gaussian");
+        hash_f64s(state, &[self.radius, self.sigma]);
+    }
+}
+
+/// Mitchell and Netravali's cubic; b = c = 1/3 is their recommended trade-off between
+/// blurring and ringing, and the negative lobes sharpen edges
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    /// the cubic of Mitchell and Netravali with parameters `b` and `c` (1/3 each is their
+    /// recommendation), stretched to `radius`
+    pub fn new(radius: f64, b: f64, c: f64) -> Self {
+        Self { radius, b, c }
+    }
+
+    /// the cubic spans [-2, 2], stretched to the radius
+    fn evaluate_1d(&self, t: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = f64::abs(2.0 * t / self.radius);
+
+        let value = if x >= 2.0 {
+            0.0
+        } else if x >= 1.0 {
+            (-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        };
+
+        value / 6.0
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"mitchell");
+        hash_f64s(state, &[self.radius, self.b, self.c]);
+    }
+}
+
+/// sinc windowed by a wider sinc, `tau` being the number of sinc lobes under the window
+pub struct LanczosFilter {
+    radius: f64,
+    tau: f64,
+}
+
+impl LanczosFilter {
+    /// a sinc windowed by a sinc `tau` lobes wide, cut off at `radius`
+    pub fn new(radius: f64, tau: f64) -> Self {
+        Self { radius, tau }
+    }
+
+    fn sinc(x: f64) -> f64 {
+        if f64::abs(x) < 1e-5 {
+            return 1.0;
+        }
+
+        f64::sin(PI * x) / (PI * x)
+    }
+
+    fn evaluate_1d(&self, t: f64) -> f64 {
+        if f64::abs(t) > self.radius {
+            return 0.0;
+        }
+
+        Self::sinc(t) * Self::sinc(t / self.tau)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"lanczos");
+        hash_f64s(state, &[self.radius, self.tau]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Fnv1a;
+
+    fn filters() -> Vec<Box<dyn Filter>> {
+        vec![
+            Box::new(BoxFilter::new(0.5)),
+            Box::new(TentFilter::new(1.0)),
+            Box::new(GaussianFilter::new(1.5, 0.5)),
+            Box::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)),
+            Box::new(LanczosFilter::new(3.0, 3.0)),
+        ]
+    }
+
+    #[test]
+    fn every_filter_integrates_to_a_positive_weight() {
+        for (k, filter) in filters().iter().enumerate() {
+            // midpoint rule over the square the filter covers
+            let steps = 200;
+            let step = 2.0 * filter.radius() / steps as f64;
+            let mut integral = 0.0;
+
+            for j in 0..steps {
+                for i in 0..steps {
+                    let x = -filter.radius() + (i as f64 + 0.5) * step;
+                    let y = -filter.radius() + (j as f64 + 0.5) * step;
+                    integral += filter.evaluate(x, y) * step * step;
+                }
+            }
+
+            assert!(integral > 0.0, "filter {k}: {integral}");
+        }
+    }
+
+    #[test]
+    fn every_filter_peaks_at_the_center_and_vanishes_at_its_radius() {
+        let gaussian = f64::exp(-1.5 * 1.5 / (2.0 * 0.5 * 0.5));
+        let centers = [
+            1.0,
+            1.0,
+            (1.0 - gaussian) * (1.0 - gaussian),
+            (16.0 / 18.0) * (16.0 / 18.0),
+            1.0,
+        ];
+
+        for (k, (filter, center)) in filters().iter().zip(centers).enumerate() {
+            let radius = filter.radius();
+
+            assert!(
+                f64::abs(filter.evaluate(0.0, 0.0) - center) < 1e-12,
+                "filter {k}"
+            );
+
+            for (x, y) in [(radius, 0.0), (0.0, radius), (radius, radius)] {
+                assert!(f64::abs(filter.evaluate(x, y)) < 1e-12, "filter {k}");
+            }
+        }
+    }
+
+    #[test]
+    fn fingerprints_tell_the_filters_apart() {
+        let fingerprint = |filter: &dyn Filter| {
+            let mut state = Fnv1a::new();
+            filter.fingerprint(&mut state);
+            state.finish()
+        };
+
+        let mut fingerprints: Vec<u64> = filters()
+            .iter()
+            .map(|filter| fingerprint(filter.as_ref()))
+            .collect();
+        fingerprints.push(fingerprint(&BoxFilter::new(1.0)));
+        fingerprints.push(fingerprint(&MitchellFilter::new(2.0, 0.5, 0.25)));
+
+        for (k, a) in fingerprints.iter().enumerate() {
+            assert!(fingerprints[k + 1..].iter().all(|b| a != b), "filter {k}");
+        }
+    }
+}
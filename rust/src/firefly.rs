@@ -0,0 +1,238 @@
+//! Firefly suppression: clamping the radiance samples carry while rendering, and rejecting
+//! pixels far brighter than their neighbours afterwards. Both trade a little energy, and
+//! so bias, for images that converge far sooner.
+
+use crate::color::{luminance, Color};
+use crate::image::Image;
+
+/// How bright a contribution may be before it is scaled down, keeping its hue.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    /// by the brightest of the red, green and blue components
+    MaxComponent(f64),
+    /// by the luminance
+    Luminance(f64),
+}
+
+impl Limit {
+    /// `<max>` for a limit on the brightest component, `luminance=<max>` for one on the
+    /// luminance, as on the command line
+    pub fn parse(text: &str) -> Option<Self> {
+        let (limit, max): (fn(f64) -> Self, &str) = match text.split_once('=') {
+            Some(("luminance", max)) => (Self::Luminance, max),
+            Some(_) => return None,
+            None => (Self::MaxComponent, text),
+        };
+
+        max.parse::<f64>().ok().filter(|max| *max >= 0.0).map(limit)
+    }
+
+    /// `color` scaled down to the limit if it is past it
+    pub fn apply(&self, color: &Color) -> Color {
+        let (measure, max) = match *self {
+            Self::MaxComponent(max) => (f64::max(color.x(), f64::max(color.y(), color.z())), max),
+            Self::Luminance(max) => (luminance(color), max),
+        };
+
+        if measure > max {
+            *color * (max / measure)
+        } else {
+            *color
+        }
+    }
+}
+
+/// Limits on what a single sample brings back, separately for direct light (reaching the
+/// first surface the camera sees straight from a light or the background) and indirect
+/// light (after further bounces), where caustics and other fireflies come from. Lights the
+/// camera sees themselves are never clamped.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RadianceClamp {
+    direct: Option<Limit>,
+    indirect: Option<Limit>,
+}
+
+impl RadianceClamp {
+    /// no clamping
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// limits light that bounced off one surface on its way to the camera
+    pub fn set_direct(&mut self, limit: Limit) -> &mut Self {
+        self.direct = Some(limit);
+        self
+    }
+
+    /// limits light that bounced off two or more surfaces on its way to the camera
+    pub fn set_indirect(&mut self, limit: Limit) -> &mut Self {
+        self.indirect = Some(limit);
+        self
+    }
+
+    /// the contribution of light that bounced off `bounces` surfaces on its way to the
+    /// camera, clamped
+    pub fn apply(&self, bounces: usize, contribution: &Color) -> Color {
+        let limit = match bounces {
+            0 => None,
+            1 => self.direct,
+            _ => self.indirect,
+        };
+
+        match limit {
+            Some(limit) => limit.apply(contribution),
+            None => *contribution,
+        }
+    }
+}
+
+/// Finds pixels much brighter than the 8 around them and brings them down to what their
+/// neighbours make plausible: the neighbours' mean luminance plus `deviations` standard
+/// deviations, and at least twice the mean. The energy taken away is held to `tolerance`,
+/// a fraction of the image's total luminance; when that doesn't cover every outlier, the
+/// worst go first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutlierRejection {
+    deviations: f64,
+    tolerance: f64,
+}
+
+impl Default for OutlierRejection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutlierRejection {
+    /// three standard deviations, taking away at most a hundredth of the light
+    pub fn new() -> Self {
+        Self {
+            deviations: 3.0,
+            tolerance: 0.01,
+        }
+    }
+
+    /// how many standard deviations past their neighbours' mean outliers start
+    pub fn set_deviations(&mut self, deviations: f64) -> &mut Self {
+        self.deviations = deviations;
+        self
+    }
+
+    /// the most light taken away, as a fraction of the image's total luminance
+    pub fn set_tolerance(&mut self, tolerance: f64) -> &mut Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// the image with its outliers brought down
+    pub fn apply(&self, image: &Image) -> Image {
+        let (width, height) = (image.width() as i64, image.height() as i64);
+        let luminances: Vec<f64> = image.pixels().iter().map(luminance).collect();
+
+        // every outlier with the luminance it would come down to
+        let mut outliers = vec![];
+
+        for j in 0..height {
+            for i in 0..width {
+                let neighbours: Vec<f64> = (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (i + dx, j + dy)))
+                    .filter(|&(x, y)| {
+                        (x, y) != (i, j) && x >= 0 && y >= 0 && x < width && y < height
+                    })
+                    .map(|(x, y)| luminances[(y * width + x) as usize])
+                    .collect();
+
+                if neighbours.is_empty() {
+                    continue;
+                }
+
+                let count = neighbours.len() as f64;
+                let mean = neighbours.iter().sum::<f64>() / count;
+                let variance = neighbours
+                    .iter()
+                    .map(|l| (l - mean) * (l - mean))
+                    .sum::<f64>()
+                    / count;
+                let bound = f64::max(mean + self.deviations * f64::sqrt(variance), 2.0 * mean);
+                let index = (j * width + i) as usize;
+
+                if luminances[index] > bound {
+                    outliers.push((index, bound));
+                }
+            }
+        }
+
+        outliers.sort_by(|a, b| {
+            let excess = |&(index, bound): &(usize, f64)| luminances[index] - bound;
+            excess(b).total_cmp(&excess(a))
+        });
+
+        let mut budget = self.tolerance * luminances.iter().sum::<f64>();
+        let mut result = image.clone();
+        let pixels = result.pixels_mut();
+
+        for (index, bound) in outliers {
+            if budget <= 0.0 {
+                break;
+            }
+
+            let taken = f64::min(luminances[index] - bound, budget);
+            pixels[index] *= (luminances[index] - taken) / luminances[index];
+            budget -= taken;
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_keep_the_hue() {
+        let color = Color::new(4.0, 2.0, 0.0);
+
+        assert_eq!(
+            Limit::MaxComponent(1.0).apply(&color),
+            Color::new(1.0, 0.5, 0.0)
+        );
+        assert_eq!(Limit::Luminance(10.0).apply(&color), color);
+
+        assert_eq!(Limit::parse("4"), Some(Limit::MaxComponent(4.0)));
+        assert_eq!(Limit::parse("luminance=2.5"), Some(Limit::Luminance(2.5)));
+        assert_eq!(Limit::parse("red=1"), None);
+        assert_eq!(Limit::parse("-1"), None);
+
+        let mut clamp = RadianceClamp::new();
+        clamp.set_indirect(Limit::MaxComponent(2.0));
+        assert_eq!(clamp.apply(0, &color), color);
+        assert_eq!(clamp.apply(1, &color), color);
+        assert_eq!(clamp.apply(3, &color), Color::new(2.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn outliers_come_down_within_the_energy_tolerance() {
+        let mut image = Image::from_fn(5, 5, |i, _| {
+            Color::new(1.0, 1.0, 1.0) * (1.0 + 0.1 * i as f64)
+        });
+        image.set_pixel(1, 1, &Color::new(50.0, 50.0, 50.0));
+        image.set_pixel(3, 3, &Color::new(20.0, 20.0, 20.0));
+        let total = |image: &Image| image.pixels().iter().map(luminance).sum::<f64>();
+
+        // enough tolerance for both
+        let cleaned = OutlierRejection::new().set_tolerance(1.0).apply(&image);
+        assert!(cleaned.pixel(1, 1).x() < 3.0);
+        assert!(cleaned.pixel(3, 3).x() < 3.0);
+        assert_eq!(cleaned.pixel(0, 4), image.pixel(0, 4));
+
+        // only enough for part of the worst
+        let tolerance = 0.1;
+        let cleaned = OutlierRejection::new()
+            .set_tolerance(tolerance)
+            .apply(&image);
+        assert!(f64::abs(total(&image) - total(&cleaned) - tolerance * total(&image)) < 1e-9);
+        assert!(cleaned.pixel(1, 1).x() < 50.0);
+        assert_eq!(cleaned.pixel(3, 3), image.pixel(3, 3));
+    }
+}
@@ -1,2 +1,4 @@
+//! The objects a scene is made of.
+
 pub mod hittable;
-pub mod sphere;
\ No newline at end of file
+pub mod sphere;
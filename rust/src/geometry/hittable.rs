@@ -1,4 +1,7 @@
-use std::sync::{Arc, RwLock};
+//! Ray intersection, common to every object and to scenes of them.
+
+use std::hash::Hasher;
+use std::sync::Arc;
 
 use crate::material::Material;
 use crate::point::Point3;
@@ -6,15 +9,37 @@ use crate::ray::Ray;
 use crate::util::interval::Interval;
 use crate::vec3::{dot, Vector3};
 
+/// Where a ray hit a surface.
+#[derive(Clone)]
 pub struct HitRecord {
+    /// the point hit
     pub p: Point3,
+    /// the unit normal there, facing against the ray
     pub normal: Vector3,
+    /// the material of the surface, if it has one
     pub mat: Option<Arc<dyn Material>>,
+    /// the ray parameter of the hit
     pub t: f64,
+    /// whether the ray hit the outside of the surface
     pub front_face: bool,
+    /// surface coordinates of the hit, each in [0, 1]
+    pub u: f64,
+    /// the other surface coordinate, see `u`
+    pub v: f64,
+    /// the object's place in the scene list, from 1; 0 for objects outside a list
+    pub object_id: u32,
+    /// the material's place among the scene's distinct materials, from 1; 0 if unknown
+    pub material_id: u32,
+}
+
+impl Default for HitRecord {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HitRecord {
+    /// a record to be filled in by `Hittable::hit`
     pub fn new() -> Self {
         Self {
             p: Point3::new_default(),
@@ -22,9 +47,15 @@ impl HitRecord {
             t: 0.0,
             mat: None,
             front_face: true,
+            u: 0.0,
+            v: 0.0,
+            object_id: 0,
+            material_id: 0,
         }
     }
 
+    /// points the normal against the ray and records which side was hit; `outward_normal`
+    /// must be a unit vector
     pub fn set_face_normal(
         &mut self,
         ray: &Ray,
@@ -36,38 +67,104 @@ impl HitRecord {
 
         self.front_face = dot(ray.direction(), outward_normal) < 0.0;
         self.normal = if self.front_face {
-            outward_normal.clone()
+            *outward_normal
         } else {
-            -outward_normal.clone()
+            -*outward_normal
         };
 
         Ok(())
     }
 
+    /// sets the material of the surface hit
     pub fn set_material(&mut self, material: &Arc<dyn Material>) {
         self.mat = Some(Arc::clone(material));
     }
 }
 
+/// Anything a ray can hit.
 pub trait Hittable: Sync + Send {
+    /// whether `ray` hits within `ray_t`, filling `rec` with the nearest hit if so
     fn hit(&self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
+
+    /// the surface area, 0 for objects that can't serve as area lights
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    /// a point distributed uniformly over the surface, with the outward normal and
+    /// `front_face` set; `None` for objects that can't serve as area lights
+    fn sample_surface(&self) -> Option<HitRecord> {
+        None
+    }
+
+    /// the material the whole object is made of, if there is just one
+    fn material(&self) -> Option<Arc<dyn Material>> {
+        None
+    }
+
+    /// feeds the shape, placement and material of the object into `state`, which tells
+    /// scenes apart (render checkpoints are only resumed with the scene they were made for).
+    /// The default only tells types apart, so a checkpoint would resume after the object
+    /// moved; objects should feed in everything that changes the image.
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(std::any::type_name::<Self>().as_bytes());
+    }
 }
 
+/// A scene: the objects in it, hit as one. Objects added with `add` are numbered in
+/// order, and so are their materials, a material shared by several objects taking one
+/// number; hits carry both.
 pub struct HittableList {
+    /// every object added, in order
     pub objects: Vec<Arc<dyn Hittable>>,
+    ids: Vec<(u32, u32)>, // object and material of each object added
+    materials: Vec<Arc<dyn Material>>,
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HittableList {
+    /// an empty scene
     pub fn new() -> Self {
-        Self { objects: vec![] }
+        Self {
+            objects: vec![],
+            ids: vec![],
+            materials: vec![],
+        }
     }
 
+    /// adds `object` to the scene, numbering it and its material
     pub fn add(&mut self, object: Arc<dyn Hittable>) {
+        let material_id = match object.material() {
+            None => 0,
+            Some(material) => {
+                match self
+                    .materials
+                    .iter()
+                    .position(|m| Arc::ptr_eq(m, &material))
+                {
+                    Some(index) => index as u32 + 1,
+                    None => {
+                        self.materials.push(material);
+                        self.materials.len() as u32
+                    }
+                }
+            }
+        };
+
         self.objects.push(object);
+        self.ids.push((self.objects.len() as u32, material_id));
     }
 
+    /// removes every object, and their numbers with them
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.ids.clear();
+        self.materials.clear();
     }
 }
 
@@ -77,10 +174,12 @@ impl Hittable for HittableList {
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
 
-        self.objects.iter().for_each(|object| {
+        self.objects.iter().enumerate().for_each(|(index, object)| {
             if object.hit(ray, Interval::new(ray_t.min, closest_so_far), &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
+                (temp_rec.object_id, temp_rec.material_id) =
+                    self.ids.get(index).copied().unwrap_or((0, 0));
             }
         });
 
@@ -90,4 +189,12 @@ impl Hittable for HittableList {
 
         hit_anything
     }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write_usize(self.objects.len());
+
+        for object in &self.objects {
+            object.fingerprint(state);
+        }
+    }
 }
@@ -1,11 +1,16 @@
+//! Spheres.
+
+use std::hash::Hasher;
 use std::sync::Arc;
 
 use super::hittable::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::dot;
+use crate::util::{hash_f64s, PI};
+use crate::vec3::{dot, random_unit_vector};
 use crate::{point::Point3, util::interval::Interval};
 
+/// A sphere of some material.
 pub struct Sphere {
     center: Point3,
     radius: f64,
@@ -13,9 +18,10 @@ pub struct Sphere {
 }
 
 impl Sphere {
+    /// a negative radius is taken as 0
     pub fn new(center: &Point3, radius: f64, mat: Arc<dyn Material>) -> Self {
         Self {
-            center: center.clone(),
+            center: *center,
             radius: f64::max(radius, 0.0),
             mat: Arc::clone(&mat),
         }
@@ -24,7 +30,7 @@ impl Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let oc = self.center.clone() - ray.origin().clone();
+        let oc = self.center - *ray.origin();
 
         let a = ray.direction().length_squared();
         let h = dot(ray.direction(), &oc);
@@ -53,8 +59,52 @@ impl Hittable for Sphere {
 
         let outward_normal = (rec.p - self.center) * (1.0 / self.radius);
         rec.set_face_normal(ray, &outward_normal).unwrap();
+        (rec.u, rec.v) = uv(&outward_normal);
+        rec.set_material(&self.mat);
+
+        true
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self) -> Option<HitRecord> {
+        let normal = random_unit_vector();
+        let mut rec = HitRecord::new();
+
+        rec.p = self.center + self.radius * normal;
+        rec.normal = normal;
+        rec.front_face = true;
         rec.set_material(&self.mat);
 
-        return true;
+        Some(rec)
     }
+
+    fn material(&self) -> Option<Arc<dyn Material>> {
+        Some(Arc::clone(&self.mat))
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"sphere");
+        hash_f64s(
+            state,
+            &[
+                self.center.x(),
+                self.center.y(),
+                self.center.z(),
+                self.radius,
+            ],
+        );
+        self.mat.fingerprint(state);
+    }
+}
+
+/// longitude and latitude of a point on the unit sphere, scaled to [0, 1]: u runs around
+/// the y axis from -x, v from the bottom up
+fn uv(p: &Point3) -> (f64, f64) {
+    let theta = f64::acos(f64::clamp(-p.y(), -1.0, 1.0));
+    let phi = f64::atan2(-p.z(), p.x()) + PI;
+
+    (phi / (2.0 * PI), theta / PI)
 }
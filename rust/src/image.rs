@@ -0,0 +1,214 @@
+//! Rendered images and the formats they are written in and read from.
+
+pub mod bmp;
+mod deflate;
+pub mod exr;
+pub mod hdr;
+pub mod pfm;
+pub mod png;
+pub mod ppm;
+pub mod tga;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::color::{to_bytes, Color};
+
+/// The file formats images are written in: 8 bits per channel for display, or the linear
+/// floats of high dynamic range formats, which keep everything the render found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// binary (P6) portable pixmap
+    Ppm,
+    /// portable network graphics, 8 bits per channel
+    Png,
+    /// Windows bitmap
+    Bmp,
+    /// Truevision TGA
+    Tga,
+    /// portable float map
+    Pfm,
+    /// Radiance RGBE
+    Hdr,
+    /// OpenEXR, half floats, zip compressed
+    Exr,
+}
+
+impl Format {
+    /// the format named by the extension of `path`, in any case
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("ppm") => Ok(Self::Ppm),
+            Some("png") => Ok(Self::Png),
+            Some("bmp") => Ok(Self::Bmp),
+            Some("tga") => Ok(Self::Tga),
+            Some("pfm") => Ok(Self::Pfm),
+            Some("hdr") => Ok(Self::Hdr),
+            Some("exr") => Ok(Self::Exr),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "can't tell the image format of {}; use .ppm, .png, .bmp, .tga, .pfm, .hdr or .exr",
+                    path.display()
+                ),
+            )),
+        }
+    }
+
+    /// whether the format stores values as they are, negative and past 1 included, rather
+    /// than colors for display
+    pub fn holds_any_float(&self) -> bool {
+        matches!(self, Self::Pfm | Self::Exr)
+    }
+
+    /// whether the format keeps values past 1, so images need no tone mapping for it
+    pub fn is_high_dynamic_range(&self) -> bool {
+        matches!(self, Self::Pfm | Self::Hdr | Self::Exr)
+    }
+
+    /// encodes `image` in the format into `output`
+    pub fn write(&self, image: &Image, output: &mut impl Write) -> io::Result<()> {
+        match self {
+            Self::Pfm => pfm::write(image, output),
+            Self::Hdr => hdr::write(image, output),
+            Self::Exr => exr::write(image, output),
+            _ => self.write_rgb8(image.width(), image.height(), &image.to_rgb8(), output),
+        }
+    }
+
+    /// decodes an image into linear values; 8-bit formats are taken to be sRGB encoded
+    pub fn read(&self, input: &mut impl Read) -> io::Result<Image> {
+        match self {
+            Self::Ppm => ppm::read(input),
+            Self::Png => png::read(input),
+            Self::Pfm => pfm::read(input),
+            Self::Exr => exr::read(input),
+            Self::Bmp | Self::Tga | Self::Hdr => Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "only PPM, PNG, PFM and EXR images can be read",
+            )),
+        }
+    }
+
+    /// encodes pixels that are already display values, row by row from the top left; float
+    /// formats store them as fractions of 255
+    pub(crate) fn write_rgb8(
+        &self,
+        width: u32,
+        height: u32,
+        pixels: &[[u8; 3]],
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        match self {
+            Self::Ppm => ppm::write_rgb8(width, height, pixels, output),
+            Self::Png => png::write_rgb8(width, height, pixels, output),
+            Self::Bmp => bmp::write_rgb8(width, height, pixels, output),
+            Self::Tga => tga::write_rgb8(width, height, pixels, output),
+            Self::Pfm | Self::Hdr | Self::Exr => {
+                let image = Image::from_fn(width, height, |i, j| {
+                    let [r, g, b] = pixels[j as usize * width as usize + i as usize];
+
+                    Color::new(r as f64, g as f64, b as f64) * (1.0 / 255.0)
+                });
+
+                self.write(&image, output)
+            }
+        }
+    }
+}
+
+/// A finished picture: linear RGB for every pixel, row by row from the top left. Nothing
+/// is clamped or sRGB encoded until a writer encodes it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    /// a black image
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::new_default(); (width * height) as usize],
+        }
+    }
+
+    /// an image with `pixel(i, j)` at column `i` and row `j`
+    pub fn from_fn(width: u32, height: u32, pixel: impl Fn(u32, u32) -> Color) -> Self {
+        Self {
+            width,
+            height,
+            pixels: (0..height)
+                .flat_map(|j| (0..width).map(move |i| (i, j)))
+                .map(|(i, j)| pixel(i, j))
+                .collect(),
+        }
+    }
+
+    /// in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// the linear color of pixel (i, j), row j counting down from the top
+    pub fn pixel(&self, i: u32, j: u32) -> Color {
+        self.pixels[self.index(i, j)]
+    }
+
+    /// sets pixel (i, j) to `color`
+    pub fn set_pixel(&mut self, i: u32, j: u32, color: &Color) {
+        let index = self.index(i, j);
+        self.pixels[index] = *color;
+    }
+
+    /// every pixel, row by row
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// every pixel, row by row, to change
+    pub fn pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    /// every pixel as 8-bit values for display, sRGB encoded and clamped
+    pub fn to_rgb8(&self) -> Vec<[u8; 3]> {
+        self.pixels.iter().map(to_bytes).collect()
+    }
+
+    /// reads the image at `path` in the format its extension names
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let format = Format::from_path(path)?;
+
+        format.read(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// writes the image to `path` in the format its extension names
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let format = Format::from_path(path)?;
+        let mut file = BufWriter::new(File::create(path)?);
+
+        format.write(self, &mut file)?;
+        file.flush()
+    }
+
+    fn index(&self, i: u32, j: u32) -> usize {
+        assert!(i < self.width && j < self.height, "pixel out of bounds");
+
+        (j * self.width + i) as usize
+    }
+}
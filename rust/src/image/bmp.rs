@@ -0,0 +1,85 @@
+//! Windows bitmaps, uncompressed 24 bits per pixel.
+
+use std::io::{self, ErrorKind, Write};
+
+use crate::image::Image;
+
+const FILE_HEADER_SIZE: u32 = 14;
+const INFO_HEADER_SIZE: u32 = 40;
+const PIXELS_PER_METER: u32 = 2835; // 72 dpi
+
+/// encodes `image` as a bitmap into `output`, sRGB encoded and clamped
+pub fn write(image: &Image, output: &mut impl Write) -> io::Result<()> {
+    write_rgb8(image.width(), image.height(), &image.to_rgb8(), output)
+}
+
+pub(crate) fn write_rgb8(
+    width: u32,
+    height: u32,
+    pixels: &[[u8; 3]],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    // rows are padded to whole 4-byte words
+    let row_size = (3 * width as u64).div_ceil(4) * 4;
+    let pixels_size = u32::try_from(row_size * height as u64)
+        .ok()
+        .filter(|size| {
+            size.checked_add(FILE_HEADER_SIZE + INFO_HEADER_SIZE)
+                .is_some()
+        })
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "image too large for a BMP"))?;
+    let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+
+    output.write_all(b"BM")?;
+    output.write_all(&(offset + pixels_size).to_le_bytes())?;
+    output.write_all(&[0; 4])?; // reserved
+    output.write_all(&offset.to_le_bytes())?;
+
+    output.write_all(&INFO_HEADER_SIZE.to_le_bytes())?;
+    output.write_all(&(width as i32).to_le_bytes())?;
+    output.write_all(&(height as i32).to_le_bytes())?; // positive: bottom row first
+    output.write_all(&1u16.to_le_bytes())?; // planes
+    output.write_all(&24u16.to_le_bytes())?; // bits per pixel
+    output.write_all(&0u32.to_le_bytes())?; // uncompressed
+    output.write_all(&pixels_size.to_le_bytes())?;
+    output.write_all(&PIXELS_PER_METER.to_le_bytes())?;
+    output.write_all(&PIXELS_PER_METER.to_le_bytes())?;
+    output.write_all(&0u32.to_le_bytes())?; // palette colors
+    output.write_all(&0u32.to_le_bytes())?; // important colors
+
+    let mut row = Vec::with_capacity(row_size as usize);
+
+    for j in (0..height).rev() {
+        row.clear();
+
+        for [r, g, b] in &pixels[j as usize * width as usize..][..width as usize] {
+            row.extend_from_slice(&[*b, *g, *r]);
+        }
+
+        row.resize(row_size as usize, 0);
+        output.write_all(&row)?;
+    }
+
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_run_bottom_up_padded_to_words() {
+        let pixels = [[1, 2, 3], [4, 5, 6], [7, 8, 9], [10, 11, 12]];
+        let mut output = vec![];
+
+        write_rgb8(2, 2, &pixels, &mut output).unwrap();
+
+        assert_eq!(&output[0..2], b"BM");
+        assert_eq!(output.len(), 54 + 2 * 8);
+        assert_eq!(&output[2..6], &(54u32 + 16).to_le_bytes());
+        assert_eq!(
+            &output[54..],
+            &[9, 8, 7, 12, 11, 10, 0, 0, 3, 2, 1, 6, 5, 4, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,507 @@
+//! Deflate compression (RFC 1951) in the zlib wrapper (RFC 1950), as PNG and EXR need:
+//! LZ77 matches found through hash chains, coded with the fixed Huffman codes. Streams
+//! from elsewhere, with stored blocks or codes of their own, inflate as well.
+
+use std::io::{self, ErrorKind};
+
+const WINDOW_SIZE: usize = 32768;
+const HASH_BITS: u32 = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64; // candidates tried per position, trading ratio for speed
+const END_OF_BLOCK: u16 = 256;
+const MAX_CODE_LENGTH: usize = 15;
+
+/// the order in which dynamic blocks give the lengths of the code length code
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// `data` compressed into a zlib stream
+pub(crate) fn zlib(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window at the default level, the header checksum making it a
+    // multiple of 31
+    let mut output = vec![0x78, 0x9c];
+
+    output.extend(deflate(data));
+    output.extend(adler32(data).to_be_bytes());
+    output
+}
+
+/// the data of a zlib stream, checked against its checksum
+pub(crate) fn unzlib(stream: &[u8]) -> io::Result<Vec<u8>> {
+    let [method, flags, ..] = *stream else {
+        return Err(corrupt("the zlib stream ends in its header"));
+    };
+
+    if method & 0x0f != 8 || u16::from_be_bytes([method, flags]) % 31 != 0 {
+        return Err(corrupt("not a deflate zlib stream"));
+    }
+    if flags & 0x20 != 0 {
+        return Err(corrupt(
+            "zlib streams with a preset dictionary aren't supported",
+        ));
+    }
+
+    let mut bits = BitReader::new(&stream[2..]);
+    let data = inflate(&mut bits)?;
+    let checksum = bits.aligned_bytes(4)?;
+
+    if checksum != adler32(&data).to_be_bytes() {
+        return Err(corrupt("the zlib checksum doesn't match the data"));
+    }
+
+    Ok(data)
+}
+
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// the data of the deflate blocks `bits` starts with
+fn inflate(bits: &mut BitReader) -> io::Result<Vec<u8>> {
+    let mut output = vec![];
+
+    loop {
+        let last = bits.read(1)? == 1;
+
+        match bits.read(2)? {
+            0 => {
+                let header = bits.aligned_bytes(4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(corrupt("a stored deflate block has a broken length"));
+                }
+
+                output.extend_from_slice(bits.aligned_bytes(length as usize)?);
+            }
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                inflate_block(bits, &literals, &distances, &mut output)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(bits)?;
+                inflate_block(bits, &literals, &distances, &mut output)?;
+            }
+            _ => return Err(corrupt("a deflate block has the reserved type")),
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// the literal/length and distance codes at the start of a dynamic block
+fn dynamic_codes(bits: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_length_count = bits.read(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = bits.read(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    // both codes' lengths in one run, which repeats may cross
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&previous) => (previous, 3 + bits.read(2)?),
+                None => return Err(corrupt("a deflate code repeats a length before the first")),
+            },
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+
+    if lengths.len() > literal_count + distance_count {
+        return Err(corrupt("deflate code lengths run past the codes"));
+    }
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(corrupt("a deflate block has no code for its end"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    output: &mut Vec<u8>,
+) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(bits)?;
+
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            END_OF_BLOCK => return Ok(()),
+            257..=285 => {
+                let index = (symbol - 257) as usize;
+                let length = LENGTH_BASES[index] as usize
+                    + bits.read(LENGTH_EXTRA_BITS[index] as u32)? as usize;
+
+                let index = distances.decode(bits)? as usize;
+                if index >= DISTANCE_BASES.len() {
+                    return Err(corrupt("a deflate distance code is out of range"));
+                }
+                let distance = DISTANCE_BASES[index] as usize
+                    + bits.read(DISTANCE_EXTRA_BITS[index] as u32)? as usize;
+
+                if distance > output.len() {
+                    return Err(corrupt("a deflate match reaches before the data"));
+                }
+
+                // a match may overlap the bytes it produces
+                let start = output.len() - distance;
+                for k in 0..length {
+                    output.push(output[start + k]);
+                }
+            }
+            _ => return Err(corrupt("a deflate length code is out of range")),
+        }
+    }
+}
+
+/// A canonical Huffman code, as deflate defines it by the length of every symbol's code.
+struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH + 1], // codes of every length
+    symbols: Vec<u16>,                  // by code length, then by symbol
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0; MAX_CODE_LENGTH + 1];
+
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // codes can't outnumber what their lengths leave room for
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = 2 * left - count as i32;
+
+            if left < 0 {
+                return Err(corrupt("a deflate code has too many codes of a length"));
+            }
+        }
+
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+
+        Ok(Self { counts, symbols })
+    }
+
+    /// the next symbol, its code read bit by bit: the codes of every length are
+    /// consecutive numbers, following on from twice the last code of the length before
+    fn decode(&self, bits: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as i32;
+
+            if code - first < count as i32 {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count as i32;
+            first = (first + count as i32) << 1;
+            code <<= 1;
+        }
+
+        Err(corrupt(
+            "a deflate stream holds a code that isn't in its table",
+        ))
+    }
+}
+
+/// Reads values out of bytes from the least significant bit up.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize, // in bits
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    /// the next `count` bits, least significant first
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        let mut value = 0;
+
+        for k in 0..count {
+            let Some(byte) = self.bytes.get(self.position / 8) else {
+                return Err(corrupt("the deflate stream ends early"));
+            };
+
+            value |= (((byte >> (self.position % 8)) & 1) as u32) << k;
+            self.position += 1;
+        }
+
+        Ok(value)
+    }
+
+    /// the next `count` whole bytes, skipping what is left of the current one
+    fn aligned_bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let start = self.position.div_ceil(8);
+        let bytes = self
+            .bytes
+            .get(start..start + count)
+            .ok_or_else(|| corrupt("the deflate stream ends early"))?;
+
+        self.position = 8 * (start + count);
+        Ok(bytes)
+    }
+}
+
+/// `data` as a single final deflate block with the fixed codes
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::new();
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut position = 0;
+
+    bits.write(1, 1); // final block
+    bits.write(1, 2); // fixed Huffman codes
+
+    let insert = |position: usize, head: &mut [usize], prev: &mut [usize]| {
+        if position + MIN_MATCH <= data.len() {
+            let hash = hash(&data[position..position + MIN_MATCH]);
+            prev[position % WINDOW_SIZE] = head[hash];
+            head[hash] = position;
+        }
+    };
+
+    while position < data.len() {
+        let (length, distance) = longest_match(data, position, &head, &prev);
+
+        if length >= MIN_MATCH {
+            write_match(&mut bits, length, distance);
+
+            for skipped in position..position + length {
+                insert(skipped, &mut head, &mut prev);
+            }
+            position += length;
+        } else {
+            write_literal(&mut bits, data[position] as u16);
+            insert(position, &mut head, &mut prev);
+            position += 1;
+        }
+    }
+
+    write_literal(&mut bits, END_OF_BLOCK);
+    bits.finish()
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// the longest earlier occurrence, within the window, of the bytes at `position`
+fn longest_match(data: &[u8], position: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if position + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+
+    let most = usize::min(MAX_MATCH, data.len() - position);
+    let (mut best_length, mut best_distance) = (0, 0);
+    let mut candidate = head[hash(&data[position..position + MIN_MATCH])];
+
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || position - candidate > WINDOW_SIZE - 1 {
+            break;
+        }
+
+        let length = (0..most)
+            .take_while(|&k| data[candidate + k] == data[position + k])
+            .count();
+
+        if length > best_length {
+            (best_length, best_distance) = (length, position - candidate);
+
+            if length == most {
+                break;
+            }
+        }
+
+        let next = prev[candidate % WINDOW_SIZE];
+
+        // the slot may have been reused by a newer position, which would loop
+        if next == usize::MAX || next >= candidate {
+            break;
+        }
+        candidate = next;
+    }
+
+    (best_length, best_distance)
+}
+
+fn write_literal(bits: &mut BitWriter, symbol: u16) {
+    let (code, length) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+
+    bits.write_code(code, length);
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let index = LENGTH_BASES.partition_point(|&base| base as usize <= length) - 1;
+    write_literal(bits, 257 + index as u16);
+    bits.write(
+        (length - LENGTH_BASES[index] as usize) as u32,
+        LENGTH_EXTRA_BITS[index] as u32,
+    );
+
+    let index = DISTANCE_BASES.partition_point(|&base| base as usize <= distance) - 1;
+    bits.write_code(index as u16, 5);
+    bits.write(
+        (distance - DISTANCE_BASES[index] as usize) as u32,
+        DISTANCE_EXTRA_BITS[index] as u32,
+    );
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    // 5552 bytes is the most that can be summed before the sums overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        (a, b) = (a % 65521, b % 65521);
+    }
+
+    (b << 16) | a
+}
+
+/// Packs values into bytes from the least significant bit up, as deflate stores them.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: vec![],
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// the low `count` bits of `value`, least significant first
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// a Huffman code, which goes most significant bit first
+    fn write_code(&mut self, code: u16, length: u32) {
+        let reversed = (code.reverse_bits() >> (16 - length)) as u32;
+        self.write(reversed, length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_data_inflates_back() {
+        let repetitive: Vec<u8> = (0..100_000).map(|k| (k % 300 / 7) as u8).collect();
+        let noisy: Vec<u8> = (0..5000u32)
+            .map(|k| (k.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+
+        for data in [
+            vec![],
+            vec![7],
+            b"abcabcabcabcd".to_vec(),
+            repetitive.clone(),
+            noisy,
+        ] {
+            assert_eq!(unzlib(&zlib(&data)).unwrap(), data);
+        }
+
+        assert!(zlib(&repetitive).len() < repetitive.len() / 20);
+    }
+
+    #[test]
+    fn stored_and_dynamic_blocks_inflate() {
+        // a stored block, then a dynamic one as zlib codes 32 a's, 76 b's and 4 c's
+        let mut stream = vec![0x78, 0x01, 0x00, 0x03, 0x00, 0xfc, 0xff, b'x', b'y', b'z'];
+        stream.extend([
+            0x05, 0xc1, 0x01, 0x01, 0x00, 0x00, 0x00, 0x82, 0xa0, 0xad, 0xd2, 0xff, 0x0f, 0x41,
+            0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x6c, 0xdb, 0x01,
+        ]);
+        let data = [&b"xyz"[..], &[b'a'; 32], &[b'b'; 76], &[b'c'; 4]].concat();
+        stream.extend(adler32(&data).to_be_bytes());
+
+        assert_eq!(unzlib(&stream).unwrap(), data);
+
+        // a broken checksum, and a stream cut short
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        assert!(unzlib(&stream).is_err());
+        assert!(unzlib(&stream[..12]).is_err());
+    }
+}
@@ -0,0 +1,729 @@
+//! OpenEXR: linear scanline images in half or single precision floats, uncompressed or
+//! zip compressed, with any number of named RGB layers in one file. Reading takes the main
+//! layer of such files back.
+
+use std::fs::File;
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use super::deflate::{unzlib, zlib};
+use crate::color::Color;
+use crate::image::Image;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+const TILED: u32 = 0x200;
+const LONG_NAMES: u32 = 0x400; // attribute or channel names past 31 bytes
+const DEEP: u32 = 0x800;
+const MULTIPART: u32 = 0x1000;
+
+/// How channel values are stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelType {
+    /// 16-bit floats, up to 65504 and about three decimal digits
+    Half,
+    /// 32-bit floats
+    Float,
+}
+
+/// How blocks of scanlines are compressed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// stored as they are
+    None,
+    /// deflate, one scanline per block
+    Zips,
+    /// deflate, 16 scanlines per block
+    Zip,
+}
+
+impl PixelType {
+    fn code(&self) -> u32 {
+        match self {
+            Self::Half => 1,
+            Self::Float => 2,
+        }
+    }
+}
+
+impl Compression {
+    fn code(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Zips => 2,
+            Self::Zip => 3,
+        }
+    }
+
+    fn lines_per_block(&self) -> u32 {
+        match self {
+            Self::None | Self::Zips => 1,
+            Self::Zip => 16,
+        }
+    }
+}
+
+/// An OpenEXR file in the making. Every layer is an image of the same size, stored as the
+/// channels `<name>.R`, `<name>.G` and `<name>.B`; the layer with an empty name is the
+/// main image, plain `R`, `G` and `B`.
+pub struct Exr {
+    width: u32,
+    height: u32,
+    pixel_type: PixelType,
+    compression: Compression,
+    layers: Vec<(String, Image)>,
+}
+
+impl Exr {
+    /// half floats, zip compressed
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixel_type: PixelType::Half,
+            compression: Compression::Zip,
+            layers: vec![],
+        }
+    }
+
+    /// how the channels of every layer are stored
+    pub fn set_pixel_type(&mut self, pixel_type: PixelType) -> &mut Self {
+        self.pixel_type = pixel_type;
+        self
+    }
+
+    /// how the scanlines are compressed
+    pub fn set_compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    /// adds the image as the layer `name`, replacing any layer of that name
+    pub fn add_layer(&mut self, name: &str, image: &Image) -> io::Result<&mut Self> {
+        if (image.width(), image.height()) != (self.width, self.height) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "layer {:?} is {} x {}, not {} x {} like the file",
+                    name,
+                    image.width(),
+                    image.height(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+
+        self.layers.retain(|(layer, _)| layer != name);
+        self.layers.push((name.to_string(), image.clone()));
+        Ok(self)
+    }
+
+    /// encodes the layers into `output`; fails without any
+    pub fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        if self.width == 0 || self.height == 0 || self.layers.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "an EXR needs at least one pixel and one layer",
+            ));
+        }
+
+        // readers expect the channels sorted by name, and the pixels in the same order
+        let mut channels: Vec<(String, &Image, usize)> = vec![];
+
+        for (name, image) in &self.layers {
+            for (component, suffix) in ["R", "G", "B"].iter().enumerate() {
+                let channel = match name.as_str() {
+                    "" => suffix.to_string(),
+                    _ => format!("{name}.{suffix}"),
+                };
+                channels.push((channel, image, component));
+            }
+        }
+        channels.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+        let header = self.header(&channels);
+        let blocks: Vec<Vec<u8>> = (0..self.height)
+            .step_by(self.compression.lines_per_block() as usize)
+            .map(|y| self.block(y, &channels))
+            .collect();
+
+        let long_names = channels.iter().any(|(name, ..)| name.len() > 31);
+        let flags = if long_names { LONG_NAMES } else { 0 };
+
+        output.write_all(&MAGIC)?;
+        output.write_all(&(VERSION | flags).to_le_bytes())?;
+        output.write_all(&header)?;
+
+        // the offset table points at every block from the start of the file
+        let mut offset = (8 + header.len() + 8 * blocks.len()) as u64;
+
+        for block in &blocks {
+            output.write_all(&offset.to_le_bytes())?;
+            offset += block.len() as u64;
+        }
+
+        for block in &blocks {
+            output.write_all(block)?;
+        }
+
+        output.flush()
+    }
+
+    /// writes the file to `path`
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        self.write(&mut BufWriter::new(File::create(path)?))
+    }
+
+    fn header(&self, channels: &[(String, &Image, usize)]) -> Vec<u8> {
+        let mut header = vec![];
+        let mut channel_list = vec![];
+
+        for (name, ..) in channels {
+            channel_list.extend(name.as_bytes());
+            channel_list.push(0);
+            channel_list.extend(self.pixel_type.code().to_le_bytes());
+            channel_list.extend([0, 0, 0, 0]); // not perceptually linear, reserved
+            channel_list.extend(1u32.to_le_bytes()); // x sampling
+            channel_list.extend(1u32.to_le_bytes()); // y sampling
+        }
+        channel_list.push(0);
+
+        let window: Vec<u8> = [0, 0, self.width as i32 - 1, self.height as i32 - 1]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+
+        attribute(&mut header, "channels", "chlist", &channel_list);
+        attribute(
+            &mut header,
+            "compression",
+            "compression",
+            &[self.compression.code()],
+        );
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]); // increasing y
+        attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        header.push(0);
+
+        header
+    }
+
+    /// the scanlines from `y`, each holding every channel in turn
+    fn block(&self, y: u32, channels: &[(String, &Image, usize)]) -> Vec<u8> {
+        let end = u32::min(y + self.compression.lines_per_block(), self.height);
+        let mut data = vec![];
+
+        for j in y..end {
+            for (_, image, component) in channels {
+                for i in 0..self.width {
+                    let color = image.pixel(i, j);
+                    let value = [color.x(), color.y(), color.z()][*component] as f32;
+
+                    match self.pixel_type {
+                        PixelType::Half => data.extend(half_from_f32(value).to_le_bytes()),
+                        PixelType::Float => data.extend(value.to_le_bytes()),
+                    }
+                }
+            }
+        }
+
+        if self.compression != Compression::None {
+            let compressed = zlib(&predict(&interleave(&data)));
+
+            // blocks that don't shrink are stored as they are, which readers tell by the size
+            if compressed.len() < data.len() {
+                data = compressed;
+            }
+        }
+
+        let mut block = vec![];
+        block.extend((y as i32).to_le_bytes());
+        block.extend((data.len() as u32).to_le_bytes());
+        block.extend(data);
+        block
+    }
+}
+
+/// the image as the main layer of an EXR with half floats, zip compressed
+pub fn write(image: &Image, output: &mut impl Write) -> io::Result<()> {
+    Exr::new(image.width(), image.height())
+        .add_layer("", image)?
+        .write(output)
+}
+
+/// Decodes the main layer of a single part scanline file: its `R`, `G` and `B` channels,
+/// or `Y` as gray, in any pixel type, uncompressed or zip compressed. Other layers and
+/// channels are skipped.
+pub fn read(input: &mut impl Read) -> io::Result<Image> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+
+    let mut position = 0;
+
+    if take(&bytes, &mut position, 4)? != MAGIC {
+        return Err(invalid("not an EXR image"));
+    }
+
+    let version = u32::from_le_bytes(take(&bytes, &mut position, 4)?.try_into().unwrap());
+
+    if version & 0xff != VERSION {
+        return Err(invalid("the EXR image is of an unknown version"));
+    }
+    if version & (TILED | DEEP | MULTIPART) != 0 {
+        return Err(unsupported(
+            "only single part scanline EXR images can be read",
+        ));
+    }
+
+    let mut channels = None;
+    let mut lines_per_block = None;
+    let mut window = None;
+
+    loop {
+        let name = text(&bytes, &mut position)?;
+
+        if name.is_empty() {
+            break;
+        }
+
+        let _kind = text(&bytes, &mut position)?;
+        let size = u32::from_le_bytes(take(&bytes, &mut position, 4)?.try_into().unwrap());
+        let value = take(&bytes, &mut position, size as usize)?;
+
+        match name {
+            b"channels" => channels = Some(channel_list(value)?),
+            b"compression" => {
+                lines_per_block = Some(match value.first() {
+                    Some(0) | Some(2) => 1,
+                    Some(3) => 16,
+                    _ => return Err(unsupported("the EXR image's compression isn't supported")),
+                })
+            }
+            b"dataWindow" if value.len() == 16 => {
+                let corner =
+                    |k: usize| i32::from_le_bytes(value[4 * k..4 * k + 4].try_into().unwrap());
+                window = Some([corner(0), corner(1), corner(2), corner(3)]);
+            }
+            _ => {}
+        }
+    }
+
+    let (Some(channels), Some(lines_per_block), Some([x_min, y_min, x_max, y_max])) =
+        (channels, lines_per_block, window)
+    else {
+        return Err(invalid("the EXR header lacks a required attribute"));
+    };
+
+    if x_max < x_min || y_max < y_min {
+        return Err(invalid("the EXR image has no pixels"));
+    }
+
+    let width = (x_max as i64 - x_min as i64 + 1) as u32;
+    let height = (y_max as i64 - y_min as i64 + 1) as u32;
+
+    if !channels.iter().any(|channel| channel.components.is_some()) {
+        return Err(invalid("the EXR image has no R, G, B or Y channel"));
+    }
+
+    let line_size: usize = channels
+        .iter()
+        .map(|channel| channel.pixel_type.size() * width as usize)
+        .sum();
+    let blocks = height.div_ceil(lines_per_block);
+    let mut image = Image::new(width, height);
+
+    for _ in 0..blocks {
+        let offset = u64::from_le_bytes(take(&bytes, &mut position, 8)?.try_into().unwrap());
+        let mut block =
+            usize::try_from(offset).map_err(|_| invalid("an EXR block is out of reach"))?;
+
+        let y = i32::from_le_bytes(take(&bytes, &mut block, 4)?.try_into().unwrap());
+        let size = u32::from_le_bytes(take(&bytes, &mut block, 4)?.try_into().unwrap());
+        let data = take(&bytes, &mut block, size as usize)?;
+
+        if y < y_min || y > y_max || !((y - y_min) as u32).is_multiple_of(lines_per_block) {
+            return Err(invalid("an EXR block starts on an unexpected line"));
+        }
+
+        let first = (y - y_min) as u32;
+        let lines = u32::min(lines_per_block, height - first);
+        let expected = lines as usize * line_size;
+
+        // blocks that compressing didn't shrink are stored as they are
+        let data = if data.len() < expected {
+            deinterleave(&unpredict(&unzlib(data)?))
+        } else {
+            data.to_vec()
+        };
+
+        if data.len() != expected {
+            return Err(invalid("an EXR block is the wrong size"));
+        }
+
+        let mut values = data.as_slice();
+
+        for j in first..first + lines {
+            for channel in &channels {
+                let size = channel.pixel_type.size();
+                let (line, rest) = values.split_at(size * width as usize);
+                values = rest;
+
+                let Some(components) = &channel.components else {
+                    continue;
+                };
+
+                for (i, value) in line.chunks(size).enumerate() {
+                    let value = channel.pixel_type.decode(value);
+                    let mut color = image.pixel(i as u32, j);
+
+                    for &component in components {
+                        color = match component {
+                            0 => Color::new(value, color.y(), color.z()),
+                            1 => Color::new(color.x(), value, color.z()),
+                            _ => Color::new(color.x(), color.y(), value),
+                        };
+                    }
+
+                    image.set_pixel(i as u32, j, &color);
+                }
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+/// A channel of a file being read, and the components of the image it fills.
+struct Channel {
+    pixel_type: StoredType,
+    components: Option<Vec<usize>>,
+}
+
+/// The pixel types a file may hold, which includes unsigned integers besides floats.
+#[derive(Clone, Copy)]
+enum StoredType {
+    Uint,
+    Half,
+    Float,
+}
+
+impl StoredType {
+    fn size(&self) -> usize {
+        match self {
+            Self::Half => 2,
+            Self::Uint | Self::Float => 4,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f64 {
+        match self {
+            Self::Uint => u32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            Self::Half => half_to_f32(u16::from_le_bytes(bytes.try_into().unwrap())) as f64,
+            Self::Float => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+        }
+    }
+}
+
+fn channel_list(value: &[u8]) -> io::Result<Vec<Channel>> {
+    let mut position = 0;
+    let mut channels = vec![];
+
+    loop {
+        let name = text(value, &mut position)?;
+
+        if name.is_empty() {
+            return Ok(channels);
+        }
+
+        // pixel type, linearity and reserved bytes, x and y sampling
+        let fields = take(value, &mut position, 16)?;
+        let field = |k: usize| u32::from_le_bytes(fields[4 * k..4 * k + 4].try_into().unwrap());
+
+        let pixel_type = match field(0) {
+            0 => StoredType::Uint,
+            1 => StoredType::Half,
+            2 => StoredType::Float,
+            _ => return Err(invalid("an EXR channel has an unknown pixel type")),
+        };
+
+        if (field(2), field(3)) != (1, 1) {
+            return Err(unsupported("subsampled EXR channels aren't supported"));
+        }
+
+        channels.push(Channel {
+            pixel_type,
+            components: match name {
+                b"R" => Some(vec![0]),
+                b"G" => Some(vec![1]),
+                b"B" => Some(vec![2]),
+                b"Y" => Some(vec![0, 1, 2]),
+                _ => None,
+            },
+        });
+    }
+}
+
+/// the next `count` bytes from `position` on
+fn take<'a>(bytes: &'a [u8], position: &mut usize, count: usize) -> io::Result<&'a [u8]> {
+    let taken = bytes
+        .get(*position..)
+        .and_then(|rest| rest.get(..count))
+        .ok_or_else(|| invalid("the EXR image ends early"))?;
+
+    *position += count;
+    Ok(taken)
+}
+
+/// the null terminated text from `position` on, without the null
+fn text<'a>(bytes: &'a [u8], position: &mut usize) -> io::Result<&'a [u8]> {
+    let rest = bytes.get(*position..).unwrap_or_default();
+    let length = rest
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| invalid("the EXR image ends early"))?;
+
+    *position += length + 1;
+    Ok(&rest[..length])
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn unsupported(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::Unsupported, message)
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend(name.as_bytes());
+    header.push(0);
+    header.extend(kind.as_bytes());
+    header.push(0);
+    header.extend((value.len() as u32).to_le_bytes());
+    header.extend(value);
+}
+
+/// the bytes at even positions followed by those at odd ones, which puts the similar high
+/// and low bytes of the values together
+fn interleave(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .step_by(2)
+        .chain(data.iter().skip(1).step_by(2))
+        .copied()
+        .collect()
+}
+
+/// undoes `interleave`
+fn deinterleave(data: &[u8]) -> Vec<u8> {
+    let (even, odd) = data.split_at(data.len().div_ceil(2));
+    let mut result = Vec::with_capacity(data.len());
+
+    for (k, &byte) in even.iter().enumerate() {
+        result.push(byte);
+        result.extend(odd.get(k));
+    }
+
+    result
+}
+
+/// every byte as its difference from the one before, offset by 128
+fn predict(data: &[u8]) -> Vec<u8> {
+    let mut previous = data.first().copied().unwrap_or(0);
+
+    data.iter()
+        .enumerate()
+        .map(|(k, &byte)| {
+            if k == 0 {
+                return byte;
+            }
+
+            let difference = byte.wrapping_sub(previous).wrapping_add(128);
+            previous = byte;
+            difference
+        })
+        .collect()
+}
+
+/// undoes `predict`
+fn unpredict(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+
+    for (k, &byte) in data.iter().enumerate() {
+        match k {
+            0 => result.push(byte),
+            _ => result.push(result[k - 1].wrapping_add(byte).wrapping_sub(128)),
+        }
+    }
+
+    result
+}
+
+/// the nearest half float, ties to even; too large values become infinity and too small
+/// ones zero
+pub(crate) fn half_from_f32(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // infinity stays infinite and NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    let (half, rest, halfway) = if exponent <= 0 {
+        // subnormal, the implicit leading bit made explicit
+        if exponent < -10 {
+            return sign;
+        }
+
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+
+        (
+            mantissa >> shift,
+            mantissa & ((1 << shift) - 1),
+            1 << (shift - 1),
+        )
+    } else {
+        (
+            ((exponent as u32) << 10) | (mantissa >> 13),
+            mantissa & 0x1fff,
+            0x1000,
+        )
+    };
+
+    // a carry out of the mantissa rightly moves on to the next exponent
+    let round_up = rest > halfway || (rest == halfway && half & 1 == 1);
+    sign | (half + round_up as u32) as u16
+}
+
+/// the half float's value, which a single precision float always holds exactly
+pub(crate) fn half_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+
+    match exponent {
+        0 => {
+            let magnitude = mantissa as f32 * 2f32.powi(-24);
+            if sign != 0 {
+                -magnitude
+            } else {
+                magnitude
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 127 - 15) << 23) | (mantissa << 13)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn halves_round_to_nearest() {
+        assert_eq!(half_from_f32(1.0), 0x3c00);
+        assert_eq!(half_from_f32(-2.0), 0xc000);
+        assert_eq!(half_from_f32(1.0 / 3.0), 0x3555);
+        assert_eq!(half_from_f32(65504.0), 0x7bff);
+        assert_eq!(half_from_f32(65520.0), 0x7c00);
+        assert_eq!(half_from_f32(2f32.powi(-24)), 0x0001);
+        assert_eq!(half_from_f32(2f32.powi(-26)), 0x0000);
+        assert_eq!(half_from_f32(f32::NAN) & 0x7e00, 0x7e00);
+
+        for half in (0..=0xffff).filter(|half| half & 0x7c00 != 0x7c00) {
+            assert_eq!(half_from_f32(half_to_f32(half)), half);
+        }
+        assert!(half_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn main_layers_read_back() {
+        // halves hold these exactly; the albedo layer has to be skipped
+        let image = Image::from_fn(7, 20, |i, j| {
+            Color::new(i as f64 * 0.25, j as f64 * 8.0, ((i * j) % 3) as f64 - 1.0)
+        });
+        let albedo = Image::from_fn(7, 20, |_, _| Color::new(0.5, 0.5, 0.5));
+
+        for compression in [Compression::None, Compression::Zips, Compression::Zip] {
+            for pixel_type in [PixelType::Half, PixelType::Float] {
+                let mut output = vec![];
+                Exr::new(7, 20)
+                    .set_compression(compression)
+                    .set_pixel_type(pixel_type)
+                    .add_layer("albedo", &albedo)
+                    .unwrap()
+                    .add_layer("", &image)
+                    .unwrap()
+                    .write(&mut output)
+                    .unwrap();
+
+                assert_eq!(read(&mut &output[..]).unwrap(), image);
+
+                output.truncate(output.len() - 1);
+                assert!(read(&mut &output[..]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn layers_become_sorted_channels() {
+        let image = Image::from_fn(3, 2, |i, j| Color::new(i as f64, j as f64, 0.5));
+        let mut exr = Exr::new(3, 2);
+        let mut output = vec![];
+
+        exr.set_compression(Compression::None)
+            .add_layer("", &image)
+            .unwrap()
+            .add_layer("albedo", &image)
+            .unwrap();
+        assert!(exr.add_layer("small", &Image::new(1, 1)).is_err());
+        exr.write(&mut output).unwrap();
+
+        let text = String::from_utf8_lossy(&output);
+        let order: Vec<usize> = [
+            "B\0",
+            "G\0",
+            "R\0",
+            "albedo.B\0",
+            "albedo.G\0",
+            "albedo.R\0",
+        ]
+        .iter()
+        .map(|name| text.find(name).unwrap())
+        .collect();
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // the second scanline block: y, size, then B, G and R of the main layer
+        let header_end = output.len() - 2 * (8 + 6 * 3 * 2) - 2 * 8;
+        let second =
+            u64::from_le_bytes(output[header_end + 8..header_end + 16].try_into().unwrap());
+        let block = &output[second as usize..];
+        assert_eq!(&block[0..8], &[1, 0, 0, 0, 36, 0, 0, 0]);
+        assert_eq!(&block[8..14], &[0x00, 0x38, 0x00, 0x38, 0x00, 0x38]); // B = 0.5
+        assert_eq!(&block[14..20], &[0x00, 0x3c, 0x00, 0x3c, 0x00, 0x3c]); // G = 1
+    }
+}
@@ -0,0 +1,160 @@
+//! Radiance pictures: RGBE, a shared exponent for the three mantissas, run-length encoded.
+
+use std::io::{self, Write};
+
+use crate::color::Color;
+use crate::image::Image;
+
+// scanlines of other widths can't be run-length encoded
+const MIN_ENCODED_WIDTH: u32 = 8;
+const MAX_ENCODED_WIDTH: u32 = 0x7fff;
+const MIN_RUN: usize = 4; // shorter runs take no less space than literals
+
+/// encodes `image` as Radiance RGBE into `output`
+pub fn write(image: &Image, output: &mut impl Write) -> io::Result<()> {
+    let (width, height) = (image.width(), image.height());
+
+    output.write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n")?;
+    output.write_all(format!("-Y {} +X {}\n", height, width).as_bytes())?;
+
+    let mut scanline = Vec::with_capacity(width as usize);
+
+    for j in 0..height {
+        scanline.clear();
+        scanline.extend((0..width).map(|i| rgbe(&image.pixel(i, j))));
+
+        if (MIN_ENCODED_WIDTH..=MAX_ENCODED_WIDTH).contains(&width) {
+            write_encoded(&scanline, output)?;
+        } else {
+            output.write_all(scanline.as_flattened())?;
+        }
+    }
+
+    output.flush()
+}
+
+/// the mantissas scaled so the largest falls in [128, 256), with the power of two that
+/// scales them back; negative and non-finite components become 0
+fn rgbe(color: &Color) -> [u8; 4] {
+    let component = |value: f64| {
+        if value.is_finite() {
+            f64::max(value, 0.0)
+        } else {
+            0.0
+        }
+    };
+    let (r, g, b) = (
+        component(color.x()),
+        component(color.y()),
+        component(color.z()),
+    );
+    let largest = f64::max(r, f64::max(g, b));
+
+    if largest < 1e-32 {
+        return [0; 4];
+    }
+
+    // largest = fraction * 2^exponent with fraction in [0.5, 1)
+    let exponent = largest.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f64.powi(exponent);
+
+    match exponent {
+        ..=-128 => [0; 4],
+        128.. => [255, 255, 255, 255], // beyond what RGBE holds
+        _ => [
+            (r * scale).min(255.0) as u8,
+            (g * scale).min(255.0) as u8,
+            (b * scale).min(255.0) as u8,
+            (exponent + 128) as u8,
+        ],
+    }
+}
+
+/// a new-style run-length encoded scanline: each of the four components in turn, as runs
+/// of a repeated byte and stretches of literal bytes
+fn write_encoded(scanline: &[[u8; 4]], output: &mut impl Write) -> io::Result<()> {
+    let width = scanline.len();
+    let mut encoded = vec![2, 2, (width >> 8) as u8, width as u8];
+
+    for component in 0..4 {
+        let bytes: Vec<u8> = scanline.iter().map(|pixel| pixel[component]).collect();
+        let mut start = 0;
+
+        while start < width {
+            let run = run_length(&bytes[start..]);
+
+            if run >= MIN_RUN {
+                encoded.extend([128 + run as u8, bytes[start]]);
+                start += run;
+                continue;
+            }
+
+            // literals up to the next run worth encoding
+            let mut end = start;
+
+            while end < width && end - start < 128 && run_length(&bytes[end..]) < MIN_RUN {
+                end += 1;
+            }
+
+            encoded.push((end - start) as u8);
+            encoded.extend_from_slice(&bytes[start..end]);
+            start = end;
+        }
+    }
+
+    output.write_all(&encoded)
+}
+
+/// how many times the first byte repeats, up to the 127 a run holds
+fn run_length(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .take(127)
+        .take_while(|&&byte| byte == bytes[0])
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgbe_keeps_the_brightest_component_to_eight_bits() {
+        assert_eq!(rgbe(&Color::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(rgbe(&Color::new(1000.0, 0.0, -1.0)), [250, 0, 0, 138]);
+        assert_eq!(rgbe(&Color::new(0.0, 0.0, 0.0)), [0; 4]);
+    }
+
+    #[test]
+    fn scanlines_are_run_length_encoded() {
+        let mut scanline = vec![[7, 7, 7, 130]; 10];
+        scanline[9] = [1, 2, 3, 130];
+        let mut output = vec![];
+
+        write_encoded(&scanline, &mut output).unwrap();
+
+        assert_eq!(
+            output,
+            [
+                2,
+                2,
+                0,
+                10, // header
+                128 + 9,
+                7,
+                1,
+                1, // red: a run of nine sevens and a literal one
+                128 + 9,
+                7,
+                1,
+                2, // green
+                128 + 9,
+                7,
+                1,
+                3, // blue
+                128 + 10,
+                130, // exponent
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Portable float maps: linear RGB as 32-bit floats, nothing clamped.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use super::ppm::{header_number, header_token};
+use crate::color::Color;
+use crate::image::Image;
+
+/// encodes the image little endian, bottom row first as the format has it
+pub fn write(image: &Image, output: &mut impl Write) -> io::Result<()> {
+    // a negative scale marks the data little endian
+    output.write_all(format!("PF\n{} {}\n-1.0\n", image.width(), image.height()).as_bytes())?;
+
+    let mut row = Vec::with_capacity(12 * image.width() as usize);
+
+    for j in (0..image.height()).rev() {
+        row.clear();
+
+        for i in 0..image.width() {
+            let color = image.pixel(i, j);
+
+            for value in [color.x(), color.y(), color.z()] {
+                row.extend((value as f32).to_le_bytes());
+            }
+        }
+
+        output.write_all(&row)?;
+    }
+
+    output.flush()
+}
+
+/// decodes a color (PF) or grayscale (Pf) float map in either byte order
+pub fn read(input: &mut impl Read) -> io::Result<Image> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+
+    let mut position = 0;
+    let channels = match header_token(&bytes, &mut position)? {
+        b"PF" => 3,
+        b"Pf" => 1,
+        _ => return Err(invalid("not a PFM image")),
+    };
+    let width: u32 = header_number(&bytes, &mut position)?;
+    let height: u32 = header_number(&bytes, &mut position)?;
+    let scale: f64 = header_number(&bytes, &mut position)?;
+
+    let count = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| invalid("the PFM image is too large"))?;
+    // a single whitespace character separates the header from the data
+    let data = bytes
+        .get(position + 1..)
+        .and_then(|data| data.get(..4 * count))
+        .ok_or_else(|| invalid("the PFM image ends early"))?;
+    let values: Vec<f64> = data
+        .chunks(4)
+        .map(|value| {
+            let value = value.try_into().unwrap();
+
+            if scale < 0.0 {
+                f32::from_le_bytes(value) as f64
+            } else {
+                f32::from_be_bytes(value) as f64
+            }
+        })
+        .collect();
+
+    Ok(Image::from_fn(width, height, |i, j| {
+        let k = channels * ((height - 1 - j) as usize * width as usize + i as usize);
+
+        match channels {
+            1 => Color::new(values[k], values[k], values[k]),
+            _ => Color::new(values[k], values[k + 1], values[k + 2]),
+        }
+    }))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_read_back_unclamped() {
+        let image = Image::from_fn(2, 3, |i, j| Color::new(i as f64, j as f64 * 4.0, -0.5));
+        let mut output = vec![];
+        write(&image, &mut output).unwrap();
+
+        assert_eq!(read(&mut &output[..]).unwrap(), image);
+
+        let big_endian = b"Pf\n1 1\n1.0\n\x40\x00\x00\x00";
+        assert_eq!(
+            read(&mut &big_endian[..]).unwrap().pixel(0, 0),
+            Color::new(2.0, 2.0, 2.0)
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! Portable Network Graphics: written as 8-bit RGB, read in any bit depth and color type.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use super::deflate::{unzlib, zlib};
+use crate::color::{srgb_to_linear, Color};
+use crate::image::Image;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const BYTES_PER_PIXEL: usize = 3;
+
+/// encodes `image` as a PNG into `output`, sRGB encoded and clamped
+pub fn write(image: &Image, output: &mut impl Write) -> io::Result<()> {
+    write_rgb8(image.width(), image.height(), &image.to_rgb8(), output)
+}
+
+pub(crate) fn write_rgb8(
+    width: u32,
+    height: u32,
+    pixels: &[[u8; 3]],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    // the format has no room for empty or 2^31 pixel wide images
+    if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "PNG images are 1 to 2^31 - 1 pixels on a side",
+        ));
+    }
+
+    let mut header = vec![];
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    header.extend([8, 2, 0, 0, 0]); // 8 bits, truecolor, deflate, adaptive filters, no interlace
+
+    output.write_all(&SIGNATURE)?;
+    write_chunk(output, b"IHDR", &header)?;
+    write_chunk(
+        output,
+        b"IDAT",
+        &zlib(&filter(width, pixels.as_flattened())),
+    )?;
+    write_chunk(output, b"IEND", &[])?;
+
+    output.flush()
+}
+
+fn write_chunk(output: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    output.write_all(kind)?;
+    output.write_all(data)?;
+    output.write_all(&crc.finish().to_be_bytes())
+}
+
+/// every row behind the filter byte that makes it smallest by the sum of absolute
+/// differences, the heuristic the PNG specification suggests
+fn filter(width: u32, bytes: &[u8]) -> Vec<u8> {
+    let row_size = width as usize * BYTES_PER_PIXEL;
+    let zeros = vec![0; row_size];
+    let mut filtered = Vec::with_capacity(bytes.len() + bytes.len() / row_size);
+    let mut candidate = vec![0; row_size];
+    let mut best = vec![0; row_size];
+
+    for (j, row) in bytes.chunks(row_size).enumerate() {
+        let above = if j == 0 {
+            &zeros[..]
+        } else {
+            &bytes[(j - 1) * row_size..j * row_size]
+        };
+        let mut best_kind = 0;
+        let mut best_cost = u64::MAX;
+
+        for kind in 0..5 {
+            for k in 0..row_size {
+                let left = if k >= BYTES_PER_PIXEL {
+                    row[k - BYTES_PER_PIXEL]
+                } else {
+                    0
+                };
+                let upper_left = if k >= BYTES_PER_PIXEL {
+                    above[k - BYTES_PER_PIXEL]
+                } else {
+                    0
+                };
+                let predicted = match kind {
+                    0 => 0,
+                    1 => left,
+                    2 => above[k],
+                    3 => ((left as u16 + above[k] as u16) / 2) as u8,
+                    _ => paeth(left, above[k], upper_left),
+                };
+
+                candidate[k] = row[k].wrapping_sub(predicted);
+            }
+
+            // small differences either way count as small
+            let cost = candidate
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as u64)
+                .sum();
+
+            if cost < best_cost {
+                (best_kind, best_cost) = (kind, cost);
+                best.copy_from_slice(&candidate);
+            }
+        }
+
+        filtered.push(best_kind);
+        filtered.extend_from_slice(&best);
+    }
+
+    filtered
+}
+
+fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - upper_left as i16;
+    let distance = |byte: u8| (estimate - byte as i16).abs();
+
+    if distance(left) <= distance(above) && distance(left) <= distance(upper_left) {
+        left
+    } else if distance(above) <= distance(upper_left) {
+        above
+    } else {
+        upper_left
+    }
+}
+
+/// Decodes a PNG that isn't interlaced. Transparency is dropped and the values are taken
+/// to be sRGB encoded, coming back linear.
+pub fn read(input: &mut impl Read) -> io::Result<Image> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(invalid("not a PNG image"));
+    }
+
+    let mut position = SIGNATURE.len();
+    let mut header = None;
+    let mut palette = vec![];
+    let mut compressed = vec![];
+
+    loop {
+        let length = bytes
+            .get(position..position + 4)
+            .map(|length| u32::from_be_bytes(length.try_into().unwrap()) as usize)
+            .ok_or_else(|| invalid("the PNG image ends early"))?;
+        let chunk = bytes
+            .get(position + 4..)
+            .and_then(|rest| rest.get(..length + 8))
+            .ok_or_else(|| invalid("the PNG image ends early"))?;
+        let (kind, data) = (&chunk[..4], &chunk[4..4 + length]);
+        position += 12 + length;
+
+        let mut crc = Crc32::new();
+        crc.update(&chunk[..4 + length]);
+        if crc.finish().to_be_bytes() != chunk[4 + length..] {
+            return Err(invalid("a PNG chunk doesn't match its checksum"));
+        }
+
+        match kind {
+            b"IHDR" => header = Some(Header::parse(data)?),
+            b"PLTE" => {
+                palette = data
+                    .chunks_exact(3)
+                    .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                    .collect()
+            }
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            // chunks whose name starts in upper case can't be skipped
+            _ if kind[0].is_ascii_uppercase() => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "the PNG image needs the unknown {} chunk",
+                        String::from_utf8_lossy(kind)
+                    ),
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or_else(|| invalid("the PNG image has no header"))?;
+    let rows = unfilter(&header, &unzlib(&compressed)?)?;
+    let max = ((1u32 << header.depth) - 1) as f64;
+    let channels = header.channels();
+
+    let mut pixels = Vec::with_capacity(header.width as usize * header.height as usize);
+
+    for row in rows.chunks(header.row_size()) {
+        for i in 0..header.width as usize {
+            let sample = |channel: usize| header.sample(row, i * channels + channel);
+            let value = |channel: usize| srgb_to_linear(sample(channel) as f64 / max);
+
+            pixels.push(match header.color_type {
+                0 | 4 => Color::new(value(0), value(0), value(0)),
+                3 => {
+                    let [r, g, b] = *palette
+                        .get(sample(0) as usize)
+                        .ok_or_else(|| invalid("a PNG pixel is past the end of the palette"))?;
+                    let value = |byte: u8| srgb_to_linear(byte as f64 / 255.0);
+
+                    Color::new(value(r), value(g), value(b))
+                }
+                _ => Color::new(value(0), value(1), value(2)),
+            });
+        }
+    }
+
+    let mut image = Image::new(header.width, header.height);
+    image.pixels_mut().copy_from_slice(&pixels);
+    Ok(image)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+struct Header {
+    width: u32,
+    height: u32,
+    depth: u32,
+    color_type: u8,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        if data.len() != 13 {
+            return Err(invalid("the PNG header is the wrong size"));
+        }
+
+        let header = Self {
+            width: u32::from_be_bytes(data[0..4].try_into().unwrap()),
+            height: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            depth: data[8] as u32,
+            color_type: data[9],
+        };
+        let depths: &[u32] = match header.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => &[],
+        };
+
+        if !depths.contains(&header.depth) {
+            return Err(invalid(
+                "the PNG header has an impossible bit depth or color type",
+            ));
+        }
+        if data[10] != 0 || data[11] != 0 {
+            return Err(invalid(
+                "the PNG image uses an unknown compression or filter method",
+            ));
+        }
+        if data[12] != 0 {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "interlaced PNG images aren't supported",
+            ));
+        }
+
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            4 => 2,
+            2 => 3,
+            _ => 4,
+        }
+    }
+
+    /// bytes in a row, without the filter byte
+    fn row_size(&self) -> usize {
+        (self.width as usize * self.channels() * self.depth as usize).div_ceil(8)
+    }
+
+    /// what filters take as the pixel to the left: the bytes of a pixel, at least one
+    fn filter_distance(&self) -> usize {
+        usize::max(self.channels() * self.depth as usize / 8, 1)
+    }
+
+    /// the `index`th value in `row`; values smaller than a byte are packed from its top bit
+    fn sample(&self, row: &[u8], index: usize) -> u32 {
+        match self.depth {
+            8 => row[index] as u32,
+            16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]) as u32,
+            depth => {
+                let bit = index * depth as usize;
+                let shift = 8 - depth as usize - bit % 8;
+
+                ((row[bit / 8] >> shift) & ((1 << depth) - 1)) as u32
+            }
+        }
+    }
+}
+
+/// the rows of the image with the filters undone and the filter bytes dropped
+fn unfilter(header: &Header, data: &[u8]) -> io::Result<Vec<u8>> {
+    let row_size = header.row_size();
+    let distance = header.filter_distance();
+
+    if data.len() != (row_size + 1) * header.height as usize {
+        return Err(invalid("the PNG image data is the wrong size"));
+    }
+
+    let mut rows = vec![0; row_size * header.height as usize];
+
+    for (j, filtered) in data.chunks(row_size + 1).enumerate() {
+        let (done, rest) = rows.split_at_mut(j * row_size);
+        let above = if j == 0 {
+            None
+        } else {
+            Some(&done[(j - 1) * row_size..])
+        };
+        let row = &mut rest[..row_size];
+
+        for k in 0..row_size {
+            let left = if k >= distance { row[k - distance] } else { 0 };
+            let up = above.map_or(0, |above| above[k]);
+            let upper_left = match above {
+                Some(above) if k >= distance => above[k - distance],
+                _ => 0,
+            };
+            let predicted = match filtered[0] {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, upper_left),
+                _ => return Err(invalid("a PNG row has an unknown filter")),
+            };
+
+            row[k] = filtered[k + 1].wrapping_add(predicted);
+        }
+    }
+
+    Ok(rows)
+}
+
+/// CRC-32 as PNG chunks are checked with (ISO 3309, reflected, polynomial 0xedb88320)
+struct Crc32 {
+    table: [u32; 256],
+    value: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        let mut table = [0; 256];
+
+        for (n, entry) in table.iter_mut().enumerate() {
+            *entry = (0..8).fold(n as u32, |c, _| {
+                if c & 1 == 1 {
+                    0xedb88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                }
+            });
+        }
+
+        Self {
+            table,
+            value: 0xffffffff,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.value =
+                self.table[((self.value ^ byte as u32) & 0xff) as usize] ^ (self.value >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.value ^ 0xffffffff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_carry_their_checksums() {
+        let mut output = vec![];
+
+        write_rgb8(1, 1, &[[255, 0, 0]], &mut output).unwrap();
+
+        assert_eq!(&output[..8], &SIGNATURE);
+        // the IEND chunk, empty, always ends with the same checksum
+        assert_eq!(
+            &output[output.len() - 12..],
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+        );
+        // an IHDR of a 1 x 1 truecolor image
+        assert_eq!(&output[29..33], &[0x90, 0x77, 0x53, 0xde]);
+    }
+
+    #[test]
+    fn images_read_back_with_every_filter() {
+        // rows alike, rows rising and noise make the encoder pick different filters
+        let pixels: Vec<[u8; 3]> = (0..12u32 * 6)
+            .map(|k| {
+                let (i, j) = (k % 12, k / 12);
+                [
+                    (i * 20) as u8,
+                    (j * 40 + i) as u8,
+                    (k.wrapping_mul(2654435761) >> 24) as u8,
+                ]
+            })
+            .collect();
+        let mut output = vec![];
+        write_rgb8(12, 6, &pixels, &mut output).unwrap();
+
+        let image = read(&mut &output[..]).unwrap();
+
+        assert_eq!(image.to_rgb8(), pixels);
+        output[40] ^= 1;
+        assert!(read(&mut &output[..]).is_err());
+    }
+
+    #[test]
+    fn packed_grayscale_reads() {
+        // a 3 x 1 two-bit gray image: black, one third, white
+        let mut output = SIGNATURE.to_vec();
+        let header = [0, 0, 0, 3, 0, 0, 0, 1, 2, 0, 0, 0, 0];
+        write_chunk(&mut output, b"IHDR", &header).unwrap();
+        write_chunk(&mut output, b"IDAT", &zlib(&[0, 0b0001_1100])).unwrap();
+        write_chunk(&mut output, b"IEND", &[]).unwrap();
+
+        let image = read(&mut &output[..]).unwrap();
+
+        assert_eq!(image.pixel(0, 0), Color::new(0.0, 0.0, 0.0));
+        assert!(f64::abs(image.pixel(1, 0).y() - srgb_to_linear(1.0 / 3.0)) < 1e-12);
+        assert_eq!(image.pixel(2, 0), Color::new(1.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,182 @@
+//! Portable pixmaps, binary (P6) and plain (P3).
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::color::{srgb_to_linear, write_color, Color};
+use crate::image::Image;
+
+/// encodes the image as a binary P6 file, sRGB encoded and clamped to bytes
+pub fn write(image: &Image, output: &mut impl Write) -> io::Result<()> {
+    write_rgb8(image.width(), image.height(), &image.to_rgb8(), output)
+}
+
+/// encodes the image as a plain P3 file, one pixel per line of text
+pub fn write_plain(image: &Image, output: &mut impl Write) -> io::Result<()> {
+    output.write_all(format!("P3\n{} {}\n255\n", image.width(), image.height()).as_bytes())?;
+
+    for color in image.pixels() {
+        write_color(output, color)?;
+    }
+
+    output.flush()
+}
+
+pub(crate) fn write_rgb8(
+    width: u32,
+    height: u32,
+    pixels: &[[u8; 3]],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    output.write_all(format!("P6\n{} {}\n255\n", width, height).as_bytes())?;
+    output.write_all(pixels.as_flattened())?;
+
+    output.flush()
+}
+
+/// Decodes a binary or plain pixmap, or graymap (P5 and P2), of up to 16 bits a value. The
+/// values are taken to be sRGB encoded and come back linear.
+pub fn read(input: &mut impl Read) -> io::Result<Image> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+
+    let mut position = 0;
+    let (channels, binary) = match header_token(&bytes, &mut position)? {
+        b"P2" => (1, false),
+        b"P3" => (3, false),
+        b"P5" => (1, true),
+        b"P6" => (3, true),
+        _ => return Err(invalid("not a PPM or PGM image")),
+    };
+    let width: u32 = header_number(&bytes, &mut position)?;
+    let height: u32 = header_number(&bytes, &mut position)?;
+    let max: u32 = header_number(&bytes, &mut position)?;
+
+    if max == 0 || max > 65535 {
+        return Err(invalid("PPM values must range up to 1 to 65535"));
+    }
+
+    let count = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| invalid("the PPM image is too large"))?;
+
+    let values: Vec<u32> = if binary {
+        // a single whitespace character separates the header from the data
+        let size = if max < 256 { 1 } else { 2 };
+        let data = bytes
+            .get(position + 1..)
+            .and_then(|data| data.get(..count * size))
+            .ok_or_else(|| invalid("the PPM image ends early"))?;
+
+        data.chunks(size)
+            .map(|value| value.iter().fold(0, |sum, &byte| sum << 8 | byte as u32))
+            .collect()
+    } else {
+        (0..count)
+            .map(|_| header_number(&bytes, &mut position))
+            .collect::<io::Result<_>>()?
+    };
+
+    let value = |k: usize| srgb_to_linear(f64::min(values[k] as f64 / max as f64, 1.0));
+
+    Ok(Image::from_fn(width, height, |i, j| {
+        let k = channels * (j as usize * width as usize + i as usize);
+
+        match channels {
+            1 => Color::new(value(k), value(k), value(k)),
+            _ => Color::new(value(k), value(k + 1), value(k + 2)),
+        }
+    }))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// the next word of a Netpbm style header, past whitespace and `#` comments
+pub(super) fn header_token<'a>(bytes: &'a [u8], position: &mut usize) -> io::Result<&'a [u8]> {
+    loop {
+        match bytes.get(*position) {
+            Some(byte) if byte.is_ascii_whitespace() => *position += 1,
+            Some(b'#') => {
+                while bytes.get(*position).is_some_and(|&byte| byte != b'\n') {
+                    *position += 1;
+                }
+            }
+            Some(_) => break,
+            None => return Err(invalid("the image header ends early")),
+        }
+    }
+
+    let start = *position;
+    while bytes
+        .get(*position)
+        .is_some_and(|byte| !byte.is_ascii_whitespace())
+    {
+        *position += 1;
+    }
+
+    Ok(&bytes[start..*position])
+}
+
+pub(super) fn header_number<T: std::str::FromStr>(
+    bytes: &[u8],
+    position: &mut usize,
+) -> io::Result<T> {
+    let token = header_token(bytes, position)?;
+
+    std::str::from_utf8(token)
+        .ok()
+        .and_then(|token| token.parse().ok())
+        .ok_or_else(|| invalid("the image header holds something other than a number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn pixels_are_written_row_by_row() {
+        let image = Image::from_fn(2, 1, |i, _| Color::new(i as f64 * 0.25, 1.0, 4.0));
+        let mut plain = vec![];
+        let mut binary = vec![];
+
+        write_plain(&image, &mut plain).unwrap();
+        write(&image, &mut binary).unwrap();
+
+        assert_eq!(
+            String::from_utf8(plain).unwrap(),
+            "P3\n2 1\n255\n0 255 255\n137 255 255\n"
+        );
+        assert_eq!(binary, b"P6\n2 1\n255\n\x00\xff\xff\x89\xff\xff");
+    }
+
+    #[test]
+    fn pixels_read_back_to_a_bytes_precision() {
+        let image = Image::from_fn(2, 1, |i, _| Color::new(i as f64 * 0.25, 1.0, 4.0));
+        let expected = Image::from_fn(2, 1, |i, _| Color::new(i as f64 * 0.25, 1.0, 1.0));
+        let mut plain = vec![];
+        let mut binary = vec![];
+
+        write_plain(&image, &mut plain).unwrap();
+        write(&image, &mut binary).unwrap();
+
+        for encoded in [plain, binary] {
+            let decoded = read(&mut &encoded[..]).unwrap();
+
+            for (decoded, expected) in decoded.pixels().iter().zip(expected.pixels()) {
+                assert!((*decoded - *expected).length() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn headers_may_hold_comments_and_graymaps_wide_values() {
+        let image = read(&mut &b"P5 # gray\n2 1\n# max\n65535\n\xff\xff\x00\x00"[..]).unwrap();
+
+        assert_eq!(image.pixel(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(image.pixel(1, 0), Color::new(0.0, 0.0, 0.0));
+        assert!(read(&mut &b"P6\n2 1\n255\n\x00"[..]).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//! Truevision TGA, uncompressed 24 bits per pixel.
+
+use std::io::{self, ErrorKind, Write};
+
+use crate::image::Image;
+
+const UNCOMPRESSED_TRUE_COLOR: u8 = 2;
+const TOP_LEFT_ORIGIN: u8 = 0x20;
+
+/// encodes `image` as a TGA into `output`, sRGB encoded and clamped
+pub fn write(image: &Image, output: &mut impl Write) -> io::Result<()> {
+    write_rgb8(image.width(), image.height(), &image.to_rgb8(), output)
+}
+
+pub(crate) fn write_rgb8(
+    width: u32,
+    height: u32,
+    pixels: &[[u8; 3]],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let too_large = || io::Error::new(ErrorKind::InvalidInput, "image too large for a TGA");
+    let width16 = u16::try_from(width).map_err(|_| too_large())?;
+    let height16 = u16::try_from(height).map_err(|_| too_large())?;
+
+    output.write_all(&[0, 0, UNCOMPRESSED_TRUE_COLOR])?; // no id, no color map
+    output.write_all(&[0; 5])?; // color map specification
+    output.write_all(&0u16.to_le_bytes())?; // x origin
+    output.write_all(&0u16.to_le_bytes())?; // y origin
+    output.write_all(&width16.to_le_bytes())?;
+    output.write_all(&height16.to_le_bytes())?;
+    output.write_all(&[24, TOP_LEFT_ORIGIN])?;
+
+    let bgr: Vec<u8> = pixels.iter().flat_map(|&[r, g, b]| [b, g, r]).collect();
+    output.write_all(&bgr)?;
+
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_run_top_down_in_bgr_order() {
+        let pixels = [[1, 2, 3], [4, 5, 6], [7, 8, 9], [10, 11, 12]];
+        let mut output = vec![];
+
+        write_rgb8(2, 2, &pixels, &mut output).unwrap();
+
+        assert_eq!(output.len(), 18 + 2 * 2 * 3);
+        assert_eq!(output[2], UNCOMPRESSED_TRUE_COLOR);
+        assert_eq!(&output[12..14], &2u16.to_le_bytes());
+        assert_eq!(&output[14..16], &2u16.to_le_bytes());
+        assert_eq!(output[16], 24);
+        assert_eq!(output[17], TOP_LEFT_ORIGIN);
+        assert_eq!(&output[18..], &[3, 2, 1, 6, 5, 4, 9, 8, 7, 12, 11, 10]);
+    }
+
+    #[test]
+    fn images_wider_than_a_tga_can_say_are_rejected() {
+        let error = write_rgb8(70_000, 1, &[], &mut vec![]).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
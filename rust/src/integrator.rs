@@ -0,0 +1,86 @@
+//! Integrators, the ways light is found along camera rays.
+
+pub mod bdpt;
+pub mod mlt;
+pub mod path;
+pub mod photon;
+
+use std::hash::Hasher;
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::film::Film;
+use crate::geometry::hittable::{HitRecord, Hittable, HittableList};
+use crate::point::Point3;
+use crate::ray::Ray;
+use crate::util::{interval::Interval, random_double, INFINITY};
+
+/// A way of finding the light that arrives along camera rays.
+pub trait Integrator: Sync + Send {
+    /// radiance arriving through pixel (i, j) along one camera sample; contributions
+    /// that belong to other pixels are splatted onto `film` instead. The camera ray has to
+    /// be the first thing drawn, since the film places the sample where `get_ray` put it
+    fn sample(&self, camera: &Camera, world: &HittableList, i: u32, j: u32, film: &Film) -> Color;
+
+    /// number of times the whole image is rendered, each with `samples_per_pixel` samples
+    fn passes(&self) -> u32 {
+        1
+    }
+
+    /// called before every pass, e.g. to trace photons
+    fn begin_pass(&self, _pass: u32, _camera: &Camera, _world: &HittableList) {}
+
+    /// integrators that choose image positions themselves render the whole pass into
+    /// `film` here and return true; otherwise `sample` is called for every pixel
+    fn render_pass(&self, _camera: &Camera, _world: &HittableList, _film: &Film) -> bool {
+        false
+    }
+
+    /// feeds the kind of integrator and its settings into `state`, for the camera's
+    /// checkpoint fingerprint; the default only feeds in the type
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(std::any::type_name::<Self>().as_bytes());
+    }
+}
+
+/// picks a light uniformly, then a point uniformly on its surface, and returns it with
+/// the area density of that choice
+fn sample_light(lights: &HittableList) -> Option<(HitRecord, f64)> {
+    let count = lights.objects.len();
+
+    if count == 0 {
+        return None;
+    }
+
+    let index = usize::min((random_double() * count as f64) as usize, count - 1);
+    let light = &lights.objects[index];
+    let rec = light.sample_surface()?;
+
+    Some((rec, 1.0 / (count as f64 * light.area())))
+}
+
+/// area density with which `sample_light` produces the emitter point `to`,
+/// found by tracing toward it from `from`
+fn light_origin_pdf(lights: &HittableList, from: &Point3, to: &Point3) -> f64 {
+    let count = lights.objects.len() as f64;
+    let ray = Ray::new(from, &(*to - *from));
+
+    lights
+        .objects
+        .iter()
+        .filter(|light| {
+            let mut rec = HitRecord::new();
+
+            light.hit(&ray, Interval::new(0.001, INFINITY), &mut rec)
+                && f64::abs(rec.t - 1.0) < 1e-4
+        })
+        .map(|light| 1.0 / (count * light.area()))
+        .sum()
+}
+
+fn visible(world: &HittableList, from: &Point3, to: &Point3) -> bool {
+    let ray = Ray::new(from, &(*to - *from));
+    let mut rec = HitRecord::new();
+
+    !world.hit(&ray, Interval::new(0.001, 0.999), &mut rec)
+}
@@ -0,0 +1,718 @@
+//! Bidirectional path tracing.
+
+use std::hash::Hasher;
+use std::sync::Arc;
+
+use super::{light_origin_pdf, sample_light, visible, Integrator};
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::film::Film;
+use crate::geometry::hittable::{HitRecord, Hittable, HittableList};
+use crate::point::Point3;
+use crate::ray::Ray;
+use crate::util::{interval::Interval, INFINITY, PI};
+use crate::vec3::{dot, random_unit_vector, Vector3};
+
+/// Bidirectional path tracing (Veach, chapter 10). Every camera sample traces one subpath
+/// from the camera and one from a randomly chosen light, then connects every prefix of
+/// the two and weights each connection strategy with the balance heuristic. Connections
+/// straight to the camera (light tracing) are splatted onto the film.
+pub struct Bdpt {
+    lights: Arc<HittableList>,
+}
+
+impl Bdpt {
+    /// `lights` holds the emissive objects of the world, which must support `sample_surface`
+    pub fn new(lights: Arc<HittableList>) -> Self {
+        Self { lights }
+    }
+
+    fn light_subpath(
+        &self,
+        camera: &Camera,
+        world: &HittableList,
+        max_vertices: usize,
+        path: &mut Vec<Vertex>,
+    ) {
+        let Some((rec, pdf_pos)) = sample_light(&self.lights) else {
+            return;
+        };
+        let emitted = rec.mat.as_ref().unwrap().emitted(&rec);
+
+        if emitted.near_zero() {
+            return;
+        }
+
+        // cosine-weighted emission direction
+        let Ok(direction) = (rec.normal + random_unit_vector()).normalize() else {
+            return;
+        };
+        let cosine = dot(&rec.normal, &direction);
+
+        if cosine <= 0.0 {
+            return;
+        }
+
+        let pdf_dir = cosine / PI;
+        let ray = Ray::new(&rec.p, &direction);
+        let beta = emitted * (cosine / (pdf_pos * pdf_dir));
+
+        path.push(Vertex {
+            kind: VertexKind::Light,
+            rec,
+            wo: Vector3::new_default(),
+            beta: emitted * (1.0 / pdf_pos),
+            delta: false,
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+        });
+
+        random_walk(camera, world, ray, beta, pdf_dir, max_vertices, path, false);
+    }
+
+    /// contribution of the path made of the first `s` light and `t` camera vertices,
+    /// for t >= 2; the t = 1 case is handled by `connect_to_camera`
+    fn connect(
+        &self,
+        camera: &Camera,
+        world: &HittableList,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+    ) -> Color {
+        let pt = &camera_path[t - 1];
+
+        let contribution = if s == 0 {
+            // the camera subpath found an emitter on its own
+            match (pt.kind, pt.rec.mat.as_ref()) {
+                (VertexKind::Surface, Some(material)) => pt.beta * material.emitted(&pt.rec),
+                _ => return Color::new_default(),
+            }
+        } else {
+            let qs = &light_path[s - 1];
+
+            if !qs.is_connectible() || !pt.is_connectible() {
+                return Color::new_default();
+            }
+
+            let unoccluded = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta;
+
+            if unoccluded.near_zero() {
+                return Color::new_default();
+            }
+
+            unoccluded * geometry_term(world, qs, pt)
+        };
+
+        if contribution.near_zero() {
+            return Color::new_default();
+        }
+
+        contribution * self.mis_weight(camera, light_path, camera_path, None, s, t)
+    }
+
+    /// light tracing: connects the end of the light subpath straight to the camera and
+    /// returns the raster position the contribution lands on
+    fn connect_to_camera(
+        &self,
+        camera: &Camera,
+        world: &HittableList,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+    ) -> Option<(f64, f64, Color)> {
+        let qs = &light_path[s - 1];
+
+        if !qs.is_connectible() {
+            return None;
+        }
+
+        let lens = camera.sample_lens();
+        let (x, y) = camera.raster_position(&lens, &qs.rec.p)?;
+        let to_point = qs.rec.p - lens;
+        let (importance, _) = camera.importance(&lens, &to_point);
+        let distance_squared = to_point.length_squared();
+        let cos_camera = dot(&to_point.normalize().ok()?, &camera.forward());
+
+        if importance <= 0.0 {
+            return None;
+        }
+
+        let sampled = Vertex::camera(&lens, importance * cos_camera / distance_squared);
+
+        let wi = (-to_point).normalize().ok()?;
+        let contribution =
+            qs.beta * qs.f(&sampled) * sampled.beta * f64::abs(dot(&wi, &qs.rec.normal));
+
+        if contribution.near_zero() || !visible(world, &qs.rec.p, &sampled.rec.p) {
+            return None;
+        }
+
+        let weight = self.mis_weight(camera, light_path, camera_path, Some(&sampled), s, 1);
+
+        Some((x, y, contribution * weight))
+    }
+
+    /// balance heuristic weight of strategy (s, t) against every other strategy that
+    /// could have produced the same path; `sampled` replaces the camera vertex when t = 1
+    fn mis_weight(
+        &self,
+        camera: &Camera,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        sampled: Option<&Vertex>,
+        s: usize,
+        t: usize,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        // densities rewired for this strategy, copied so the subpaths stay untouched
+        let mut light_pdfs: Vec<PathDensity> =
+            light_path[..s].iter().map(PathDensity::of).collect();
+        let mut camera_pdfs: Vec<PathDensity> =
+            camera_path[..t].iter().map(PathDensity::of).collect();
+
+        let pt = sampled.unwrap_or(&camera_path[t - 1]);
+        let pt_minus = if t > 1 {
+            Some(&camera_path[t - 2])
+        } else {
+            None
+        };
+        let qs = if s > 0 {
+            Some(&light_path[s - 1])
+        } else {
+            None
+        };
+        let qs_minus = if s > 1 {
+            Some(&light_path[s - 2])
+        } else {
+            None
+        };
+
+        // the connection endpoints are no longer specular
+        camera_pdfs[t - 1].delta = false;
+        if s > 0 {
+            light_pdfs[s - 1].delta = false;
+        }
+
+        camera_pdfs[t - 1].pdf_rev = match qs {
+            Some(qs) => qs.pdf(camera, qs_minus, pt),
+            None => light_origin_pdf(&self.lights, &pt_minus.unwrap().rec.p, &pt.rec.p),
+        };
+
+        if let Some(pt_minus) = pt_minus {
+            camera_pdfs[t - 2].pdf_rev = match qs {
+                Some(qs) => pt.pdf(camera, Some(qs), pt_minus),
+                None => pt.emission_pdf(pt_minus),
+            };
+        }
+
+        if let Some(qs) = qs {
+            light_pdfs[s - 1].pdf_rev = pt.pdf(camera, pt_minus, qs);
+        }
+
+        if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+            light_pdfs[s - 2].pdf_rev = qs.pdf(camera, Some(pt), qs_minus);
+        }
+
+        // delta densities are recorded as zero, which must not blow up the ratios
+        let remap0 = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum_ri = 0.0;
+
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap0(camera_pdfs[i].pdf_rev) / remap0(camera_pdfs[i].pdf_fwd);
+
+            if !camera_pdfs[i].delta && !camera_pdfs[i - 1].delta {
+                sum_ri += ri;
+            }
+        }
+
+        let mut ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap0(light_pdfs[i].pdf_rev) / remap0(light_pdfs[i].pdf_fwd);
+
+            let delta_light_vertex = i > 0 && light_pdfs[i - 1].delta;
+
+            if !light_pdfs[i].delta && !delta_light_vertex {
+                sum_ri += ri;
+            }
+        }
+
+        1.0 / (1.0 + sum_ri)
+    }
+}
+
+impl Integrator for Bdpt {
+    fn sample(&self, camera: &Camera, world: &HittableList, i: u32, j: u32, film: &Film) -> Color {
+        let max_depth = camera.max_depth() as usize;
+
+        let Some(ray) = camera.get_ray(i, j) else {
+            return Color::new_default();
+        };
+        let (_, pdf_dir) = camera.importance(ray.origin(), ray.direction());
+        let mut camera_vertex = Vertex::camera(ray.origin(), 1.0);
+
+        // other projections can't be connected to, which rules out light tracing
+        camera_vertex.delta = !camera.is_perspective();
+
+        let mut camera_path = vec![camera_vertex];
+        // rays escaping to the background can only be found by the camera subpath,
+        // so they need no weighting
+        let background = random_walk(
+            camera,
+            world,
+            ray,
+            Color::new(1.0, 1.0, 1.0),
+            pdf_dir,
+            max_depth + 2,
+            &mut camera_path,
+            true,
+        );
+
+        let clamp = camera.radiance_clamp();
+        // the walk only brings background back when it escapes past its last vertex
+        let mut radiance = clamp.apply(camera_path.len() - 1, &background);
+
+        let mut light_path = vec![];
+        self.light_subpath(camera, world, max_depth + 1, &mut light_path);
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 2 > max_depth {
+                    continue;
+                }
+
+                if t == 1 {
+                    if let Some((x, y, contribution)) =
+                        self.connect_to_camera(camera, world, &light_path, &camera_path, s)
+                    {
+                        film.add_splat(x, y, &clamp.apply(s - 1, &contribution));
+                    }
+                } else {
+                    let contribution = self.connect(camera, world, &light_path, &camera_path, s, t);
+                    radiance += clamp.apply(s + t - 2, &contribution);
+                }
+            }
+        }
+
+        radiance
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"bdpt");
+        self.lights.fingerprint(state);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+struct Vertex {
+    kind: VertexKind,
+    rec: HitRecord,
+    wo: Vector3, // unit direction toward the previous vertex, zero at the endpoints
+    beta: Color,
+    delta: bool,
+    /// area densities of sampling this vertex from the previous / next one
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl Vertex {
+    fn camera(center: &Point3, beta: f64) -> Self {
+        let mut rec = HitRecord::new();
+        rec.p = *center;
+
+        Self {
+            kind: VertexKind::Camera,
+            rec,
+            wo: Vector3::new_default(),
+            beta: Color::new(beta, beta, beta),
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn on_surface(&self) -> bool {
+        self.kind != VertexKind::Camera
+    }
+
+    fn is_connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Camera | VertexKind::Light => true,
+            VertexKind::Surface => self
+                .rec
+                .mat
+                .as_ref()
+                .is_some_and(|material| !material.is_specular()),
+        }
+    }
+
+    /// throughput of the vertex when the path continues toward `next`
+    fn f(&self, next: &Vertex) -> Color {
+        let Ok(wi) = (next.rec.p - self.rec.p).normalize() else {
+            return Color::new_default();
+        };
+
+        match self.kind {
+            VertexKind::Surface => match self.rec.mat.as_ref() {
+                Some(material) => material.bsdf(&self.rec, &self.wo, &wi),
+                None => Color::new_default(),
+            },
+            // emission is uniform over the front hemisphere and already part of beta
+            VertexKind::Light if dot(&self.rec.normal, &wi) > 0.0 => Color::new(1.0, 1.0, 1.0),
+            _ => Color::new_default(),
+        }
+    }
+
+    /// turns a solid angle density at this vertex into an area density at `next`
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.rec.p - self.rec.p;
+        let distance_squared = w.length_squared();
+
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+
+        let mut pdf = pdf / distance_squared;
+
+        if next.on_surface() {
+            pdf *= f64::abs(dot(&next.rec.normal, &w)) / f64::sqrt(distance_squared);
+        }
+
+        pdf
+    }
+
+    /// area density of sampling `next` from this vertex, having arrived from `prev`
+    fn pdf(&self, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let pdf = match self.kind {
+            VertexKind::Camera => camera.importance(&self.rec.p, &(next.rec.p - self.rec.p)).1,
+            VertexKind::Light => return self.emission_pdf(next),
+            VertexKind::Surface => {
+                let (Some(prev), Some(material)) = (prev, self.rec.mat.as_ref()) else {
+                    return 0.0;
+                };
+                let (Ok(wo), Ok(wi)) = (
+                    (prev.rec.p - self.rec.p).normalize(),
+                    (next.rec.p - self.rec.p).normalize(),
+                ) else {
+                    return 0.0;
+                };
+
+                material.scattering_pdf(&self.rec, &wo, &wi)
+            }
+        };
+
+        self.convert_density(pdf, next)
+    }
+
+    /// area density of the cosine-weighted emission from this emitter reaching `next`
+    fn emission_pdf(&self, next: &Vertex) -> f64 {
+        let Ok(w) = (next.rec.p - self.rec.p).normalize() else {
+            return 0.0;
+        };
+        let cosine = dot(&self.rec.normal, &w);
+
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+
+        self.convert_density(cosine / PI, next)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct PathDensity {
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    delta: bool,
+}
+
+impl PathDensity {
+    fn of(vertex: &Vertex) -> Self {
+        Self {
+            pdf_fwd: vertex.pdf_fwd,
+            pdf_rev: vertex.pdf_rev,
+            delta: vertex.delta,
+        }
+    }
+}
+
+/// Extends `path` by following scattered rays, recording forward and reverse densities
+/// for the MIS weights. Returns the background radiance picked up when the walk escapes
+/// the scene, if `gather_background` is set.
+#[allow(clippy::too_many_arguments)]
+fn random_walk(
+    camera: &Camera,
+    world: &HittableList,
+    mut ray: Ray,
+    mut beta: Color,
+    mut pdf_fwd: f64,
+    max_vertices: usize,
+    path: &mut Vec<Vertex>,
+    gather_background: bool,
+) -> Color {
+    while path.len() < max_vertices {
+        let mut rec = HitRecord::new();
+
+        if !world.hit(&ray, Interval::new(0.001, INFINITY), &mut rec) {
+            if gather_background {
+                return beta * camera.background(&ray);
+            }
+
+            break;
+        }
+
+        let Ok(wo) = (-*ray.direction()).normalize() else {
+            break;
+        };
+        let material = rec.mat.clone().unwrap();
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface,
+            rec,
+            wo,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        vertex.pdf_fwd = path.last().unwrap().convert_density(pdf_fwd, &vertex);
+        path.push(vertex);
+
+        if path.len() >= max_vertices {
+            break;
+        }
+
+        let current = path.len() - 1;
+        let mut attenuation = Color::new_default();
+        let mut scattered = Ray::new_default();
+
+        if !material.scatter(&ray, &path[current].rec, &mut attenuation, &mut scattered) {
+            break;
+        }
+
+        let Ok(wi) = scattered.direction().normalize() else {
+            break;
+        };
+
+        let pdf_rev = if material.is_specular() {
+            path[current].delta = true;
+            pdf_fwd = 0.0;
+
+            0.0
+        } else {
+            pdf_fwd = material.scattering_pdf(&path[current].rec, &wo, &wi);
+
+            material.scattering_pdf(&path[current].rec, &wi, &wo)
+        };
+
+        // `scatter` samples proportionally to bsdf * cosine, leaving the attenuation as the weight
+        beta = beta * attenuation;
+        path[current - 1].pdf_rev = path[current].convert_density(pdf_rev, &path[current - 1]);
+        ray = scattered;
+    }
+
+    Color::new_default()
+}
+
+/// G(a <-> b), zero when something blocks the segment
+fn geometry_term(world: &HittableList, a: &Vertex, b: &Vertex) -> f64 {
+    let d = b.rec.p - a.rec.p;
+    let distance_squared = d.length_squared();
+
+    if distance_squared == 0.0 || !visible(world, &a.rec.p, &b.rec.p) {
+        return 0.0;
+    }
+
+    let d = d * (1.0 / f64::sqrt(distance_squared));
+    let mut g = 1.0 / distance_squared;
+
+    if a.on_surface() {
+        g *= f64::abs(dot(&a.rec.normal, &d));
+    }
+    if b.on_surface() {
+        g *= f64::abs(dot(&b.rec.normal, &d));
+    }
+
+    g
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Builder;
+    use crate::geometry::sphere::Sphere;
+    use crate::integrator::path::PathTracer;
+    use crate::material::{DiffuseLight, Lambertian, Material};
+
+    fn light() -> Arc<Sphere> {
+        let emitter = Arc::new(DiffuseLight::new(&Color::new(4.0, 4.0, 4.0)));
+
+        Arc::new(Sphere::new(&Point3::new(0.0, 2.0, -2.5), 0.5, emitter))
+    }
+
+    fn camera(integrator: Arc<dyn Integrator>, samples_per_pixel: u32) -> Camera {
+        Builder::new()
+            .set_image_width(16)
+            .set_image_aspect_ratio(4.0 / 3.0)
+            .set_samples_per_pixel(samples_per_pixel)
+            .set_max_depth(4)
+            .set_vfov(60.0)
+            .set_lookat(&Point3::new(0.0, 0.0, -1.0))
+            .set_vup(&Vector3::new(0.0, 1.0, 0.0))
+            .set_focus_dist(2.0)
+            // all the light comes from the emitter, where the two differ
+            .set_background(&Color::new_default())
+            .set_seed(1)
+            .set_integrator(integrator)
+            .build()
+    }
+
+    #[test]
+    fn converges_to_the_same_image_as_path_tracing() {
+        let gray = Arc::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, -100.5, -2.0),
+            100.0,
+            gray.clone(),
+        )));
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, 0.0, -2.0),
+            0.5,
+            gray,
+        )));
+        world.add(light());
+        lights.add(light());
+
+        let world = Arc::new(world);
+        let mean = |integrator: Arc<dyn Integrator>| {
+            let image = camera(integrator, 256).render(world.clone()).unwrap();
+            let sum = image
+                .pixels()
+                .iter()
+                .fold(Color::new_default(), |sum, pixel| sum + *pixel);
+
+            sum.x() / image.pixels().len() as f64
+        };
+
+        let bdpt = mean(Arc::new(Bdpt::new(Arc::new(lights))));
+        let path = mean(Arc::new(PathTracer));
+
+        assert!(f64::abs(bdpt - path) < 0.05 * path, "{bdpt} against {path}");
+    }
+
+    #[test]
+    fn mis_weights_of_every_strategy_for_a_path_sum_to_one() {
+        let light = light();
+        let mut lights = HittableList::new();
+        lights.add(light.clone());
+        let bdpt = Bdpt::new(Arc::new(lights));
+        let camera = camera(Arc::new(PathTracer), 1);
+
+        // the camera, a floor, a wall and the point of the light facing the wall
+        let gray: Arc<dyn Material> = Arc::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+        let emitter = light.material().unwrap();
+        let wall = Point3::new(1.0, 0.5, -2.5);
+        let to_wall = (wall - Point3::new(0.0, 2.0, -2.5)).normalize().unwrap();
+        let vertices = |last: VertexKind| {
+            let surface = |p: Point3, normal: Vector3, kind, mat: &Arc<dyn Material>| {
+                let mut rec = HitRecord::new();
+                rec.p = p;
+                rec.normal = normal;
+                rec.mat = Some(mat.clone());
+
+                Vertex {
+                    kind,
+                    rec,
+                    wo: Vector3::new_default(),
+                    beta: Color::new(1.0, 1.0, 1.0),
+                    delta: false,
+                    pdf_fwd: 0.0,
+                    pdf_rev: 0.0,
+                }
+            };
+
+            vec![
+                Vertex::camera(&Point3::new_default(), 1.0),
+                surface(
+                    Point3::new(0.0, -0.2, -2.0),
+                    Vector3::new(0.0, 1.0, 0.0),
+                    VertexKind::Surface,
+                    &gray,
+                ),
+                surface(
+                    wall,
+                    Vector3::new(-1.0, 0.0, 0.0),
+                    VertexKind::Surface,
+                    &gray,
+                ),
+                surface(
+                    Point3::new(0.0, 2.0, -2.5) + 0.5 * to_wall,
+                    to_wall,
+                    last,
+                    &emitter,
+                ),
+            ]
+        };
+
+        // the densities of sampling every vertex from the camera's side and the light's
+        let path = vertices(VertexKind::Light);
+        let n = path.len();
+        let forward: Vec<f64> = (0..n)
+            .map(|i| match i {
+                0 => 0.0,
+                _ => path[i - 1].pdf(&camera, i.checked_sub(2).map(|k| &path[k]), &path[i]),
+            })
+            .collect();
+        let backward: Vec<f64> = (0..n)
+            .map(|i| match i {
+                _ if i == n - 1 => 1.0 / light.area(),
+                _ => path[i + 1].pdf(&camera, path.get(i + 2), &path[i]),
+            })
+            .collect();
+
+        let mut camera_path = vertices(VertexKind::Surface);
+        for (i, vertex) in camera_path.iter_mut().enumerate() {
+            vertex.pdf_fwd = forward[i];
+            vertex.pdf_rev = if i < n - 1 { backward[i] } else { 0.0 };
+        }
+
+        let mut light_path: Vec<Vertex> = vertices(VertexKind::Light).into_iter().rev().collect();
+        light_path.pop();
+        for (k, vertex) in light_path.iter_mut().enumerate() {
+            let i = n - 1 - k;
+            vertex.pdf_fwd = backward[i];
+            vertex.pdf_rev = forward[i];
+        }
+
+        let sampled = Vertex::camera(&Point3::new_default(), 1.0);
+        let weights: Vec<f64> = (1..=n)
+            .map(|t| {
+                let sampled = if t == 1 { Some(&sampled) } else { None };
+
+                bdpt.mis_weight(&camera, &light_path, &camera_path, sampled, n - t, t)
+            })
+            .collect();
+
+        assert!(
+            weights.iter().all(|&weight| weight > 0.0 && weight < 1.0),
+            "{weights:?}"
+        );
+        assert!(
+            f64::abs(weights.iter().sum::<f64>() - 1.0) < 1e-9,
+            "{weights:?}"
+        );
+    }
+}
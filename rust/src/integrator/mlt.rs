@@ -0,0 +1,480 @@
+//! Metropolis light transport over primary sample space.
+
+use std::cell::RefCell;
+use std::hash::Hasher;
+use std::rc::Rc;
+use std::thread;
+
+use super::{path::PathTracer, Integrator};
+use crate::camera::Camera;
+use crate::color::{luminance, Color};
+use crate::film::Film;
+use crate::geometry::hittable::HittableList;
+use crate::util::{
+    hash_f64s, mix_seed, random_double, with_sample_source, Pcg32, SampleSource, PI,
+};
+
+struct PrimarySample {
+    value: f64,
+    last_modified: u64,
+    value_backup: f64,
+    modified_backup: u64,
+}
+
+/// Primary sample space sampler (Kelemen et al.): hands out the coordinates of a point in
+/// the unit hypercube, created lazily as the path asks for them, and mutates that point
+/// either with a small gaussian step or by resampling it entirely (a large step).
+struct MltSampler {
+    rng: Pcg32,
+    sigma: f64,
+    large_step_probability: f64,
+    samples: Vec<PrimarySample>,
+    sample_index: usize,
+    current_iteration: u64,
+    large_step: bool,
+    last_large_step_iteration: u64,
+}
+
+impl MltSampler {
+    fn new(seed: u64, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng: Pcg32::new(seed, 0),
+            sigma,
+            large_step_probability,
+            samples: vec![],
+            sample_index: 0,
+            current_iteration: 0,
+            // the very first path is an independent one
+            large_step: true,
+            last_large_step_iteration: 0,
+        }
+    }
+
+    fn uniform(&mut self) -> f64 {
+        self.rng.next()
+    }
+
+    fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.uniform() < self.large_step_probability;
+        self.sample_index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.current_iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.last_modified == self.current_iteration {
+                sample.value = sample.value_backup;
+                sample.last_modified = sample.modified_backup;
+            }
+        }
+
+        self.current_iteration -= 1;
+    }
+
+    /// brings the coordinate up to date with the current iteration before it's read
+    fn ensure_ready(&mut self, index: usize) {
+        // coordinates the path never asked for before start out uniform; stepping from a
+        // fixed value would trap rejection sampling loops
+        while self.samples.len() <= index {
+            let value = self.uniform();
+
+            self.samples.push(PrimarySample {
+                value,
+                last_modified: self.current_iteration,
+                value_backup: value,
+                modified_backup: self.current_iteration,
+            });
+        }
+
+        // a large step happened since this coordinate was last used, so it starts afresh
+        if self.samples[index].last_modified < self.last_large_step_iteration {
+            let value = self.uniform();
+            let sample = &mut self.samples[index];
+            sample.value = value;
+            sample.last_modified = self.last_large_step_iteration;
+        }
+
+        let sample = &mut self.samples[index];
+        sample.value_backup = sample.value;
+        sample.modified_backup = sample.last_modified;
+
+        if self.large_step {
+            let value = self.uniform();
+            self.samples[index].value = value;
+        } else {
+            // the small steps missed while the coordinate was unused add up to one
+            // gaussian with a wider spread
+            let missed_steps = self.current_iteration - self.samples[index].last_modified;
+            let sigma = self.sigma * f64::sqrt(missed_steps as f64);
+            let normal = f64::sqrt(-2.0 * f64::ln(1.0 - self.uniform()))
+                * f64::cos(2.0 * PI * self.uniform());
+
+            let sample = &mut self.samples[index];
+            sample.value += normal * sigma;
+            sample.value -= f64::floor(sample.value);
+        }
+
+        self.samples[index].last_modified = self.current_iteration;
+    }
+}
+
+impl SampleSource for MltSampler {
+    fn next(&mut self) -> f64 {
+        let index = self.sample_index;
+
+        self.ensure_ready(index);
+        self.sample_index += 1;
+
+        self.samples[index].value
+    }
+}
+
+/// Primary sample space Metropolis light transport on top of path tracing. A bootstrap
+/// phase traces independent paths to estimate the image brightness `b` and to pick
+/// starting points, then Markov chains mutate the numbers `random_double` hands to the
+/// path tracer, so that image positions are visited in proportion to their luminance.
+pub struct Mlt {
+    bootstrap_samples: u32,
+    chains: u32,
+    sigma: f64,
+    large_step_probability: f64,
+}
+
+impl Default for Mlt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mlt {
+    /// a hundred thousand bootstrap samples for a thousand chains, with small steps of
+    /// 0.01 and three in ten steps large
+    pub fn new() -> Self {
+        Self {
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+        }
+    }
+
+    /// paths traced to find the image's brightness and start the chains from
+    pub fn set_bootstrap_samples(&mut self, bootstrap_samples: u32) -> &mut Self {
+        self.bootstrap_samples = u32::max(bootstrap_samples, 1);
+        self
+    }
+
+    /// Markov chains run side by side, the mutations shared among them
+    pub fn set_chains(&mut self, chains: u32) -> &mut Self {
+        self.chains = u32::max(chains, 1);
+        self
+    }
+
+    /// standard deviation of the small steps, in primary sample space
+    pub fn set_sigma(&mut self, sigma: f64) -> &mut Self {
+        self.sigma = sigma;
+        self
+    }
+
+    /// how often a step throws the path away for a new one, in [0, 1]
+    pub fn set_large_step_probability(&mut self, probability: f64) -> &mut Self {
+        self.large_step_probability = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// one path traced with every random number drawn from `sampler`: the first two pick
+    /// the pixel, the rest go to the path tracer
+    fn evaluate(
+        camera: &Camera,
+        world: &HittableList,
+        film: &Film,
+        sampler: &Rc<RefCell<MltSampler>>,
+    ) -> (u32, u32, Color) {
+        let source: Rc<RefCell<dyn SampleSource>> = sampler.clone();
+
+        with_sample_source(source, || {
+            let i = u32::min(
+                (random_double() * camera.image_width() as f64) as u32,
+                camera.image_width() - 1,
+            );
+            let j = u32::min(
+                (random_double() * camera.image_height() as f64) as u32,
+                camera.image_height() - 1,
+            );
+
+            (i, j, PathTracer.sample(camera, world, i, j, film))
+        })
+    }
+
+    /// luminance of every bootstrap path, path k being the first path of a sampler seeded
+    /// with mix_seed(&[seed, k])
+    fn bootstrap(&self, camera: &Camera, world: &HittableList, film: &Film, seed: u64) -> Vec<f64> {
+        let threads = camera.threads() as u32;
+        let per_thread = self.bootstrap_samples.div_ceil(threads);
+
+        thread::scope(|scope| {
+            let joins: Vec<_> = (0..threads)
+                .map(|t| {
+                    scope.spawn(move || {
+                        let first = t * per_thread;
+                        let last = u32::min(first + per_thread, self.bootstrap_samples);
+
+                        (first..last)
+                            .map(|k| {
+                                let sampler = Rc::new(RefCell::new(MltSampler::new(
+                                    mix_seed(&[seed, k as u64]),
+                                    self.sigma,
+                                    self.large_step_probability,
+                                )));
+                                let (_, _, radiance) =
+                                    Self::evaluate(camera, world, film, &sampler);
+
+                                luminance(&radiance)
+                            })
+                            .collect::<Vec<f64>>()
+                    })
+                })
+                .collect();
+
+            joins
+                .into_iter()
+                .flat_map(|join| join.join().unwrap())
+                .collect()
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run_chain(
+        &self,
+        camera: &Camera,
+        world: &HittableList,
+        film: &Film,
+        seed: u64,
+        chain: u32,
+        bootstrap: &[f64],
+        bootstrap_total: f64,
+        mutations: u64,
+        splat_scale: f64,
+    ) {
+        // start from a bootstrap path picked in proportion to its luminance
+        let target = Pcg32::new(mix_seed(&[seed, chain as u64]), 2).next() * bootstrap_total;
+        let mut accumulated = 0.0;
+        let start = bootstrap
+            .iter()
+            .position(|weight| {
+                accumulated += weight;
+                accumulated > target
+            })
+            .unwrap_or(bootstrap.len() - 1);
+
+        let sampler = Rc::new(RefCell::new(MltSampler::new(
+            mix_seed(&[seed, start as u64]),
+            self.sigma,
+            self.large_step_probability,
+        )));
+        let (mut i, mut j, mut radiance) = Self::evaluate(camera, world, film, &sampler);
+        let mut contribution = luminance(&radiance);
+
+        // chains starting from the same bootstrap path must not mutate in lockstep
+        sampler.borrow_mut().rng = Pcg32::new(mix_seed(&[seed, chain as u64]), 1);
+
+        for _ in 0..mutations {
+            sampler.borrow_mut().start_iteration();
+
+            let (proposed_i, proposed_j, proposed_radiance) =
+                Self::evaluate(camera, world, film, &sampler);
+            let proposed_contribution = luminance(&proposed_radiance);
+
+            let acceptance = if contribution > 0.0 {
+                f64::min(1.0, proposed_contribution / contribution)
+            } else {
+                1.0
+            };
+
+            // splat both states weighted by their acceptance, which lowers the variance
+            if acceptance > 0.0 && proposed_contribution > 0.0 {
+                film.add_splat(
+                    proposed_i as f64,
+                    proposed_j as f64,
+                    &(proposed_radiance * (splat_scale * acceptance / proposed_contribution)),
+                );
+            }
+            if acceptance < 1.0 && contribution > 0.0 {
+                film.add_splat(
+                    i as f64,
+                    j as f64,
+                    &(radiance * (splat_scale * (1.0 - acceptance) / contribution)),
+                );
+            }
+
+            let mut sampler = sampler.borrow_mut();
+
+            if sampler.uniform() < acceptance {
+                (i, j, radiance, contribution) = (
+                    proposed_i,
+                    proposed_j,
+                    proposed_radiance,
+                    proposed_contribution,
+                );
+                sampler.accept();
+            } else {
+                sampler.reject();
+            }
+        }
+    }
+}
+
+impl Integrator for Mlt {
+    /// a plain path traced sample, for callers that visit the pixels themselves
+    fn sample(&self, camera: &Camera, world: &HittableList, i: u32, j: u32, film: &Film) -> Color {
+        PathTracer.sample(camera, world, i, j, film)
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"mlt");
+        state.write_u32(self.bootstrap_samples);
+        state.write_u32(self.chains);
+        hash_f64s(state, &[self.sigma, self.large_step_probability]);
+    }
+
+    fn render_pass(&self, camera: &Camera, world: &HittableList, film: &Film) -> bool {
+        let seed = camera.seed().unwrap_or_else(rand::random);
+        let bootstrap = self.bootstrap(camera, world, film, seed);
+        let bootstrap_total: f64 = bootstrap.iter().sum();
+
+        if bootstrap_total <= 0.0 {
+            return true;
+        }
+
+        // as many mutations as the pixel-by-pixel renderer would take samples
+        let pixels = camera.image_width() as u64 * camera.image_height() as u64;
+        let total_mutations = pixels * camera.samples_per_pixel() as u64;
+        let mutations_per_chain = total_mutations.div_ceil(self.chains as u64);
+
+        // each mutation carries b / I of its radiance, rescaled so the film's division
+        // by the samples per pixel yields the pixel average
+        let b = bootstrap_total / bootstrap.len() as f64;
+        let splat_scale =
+            b * total_mutations as f64 / (mutations_per_chain * self.chains as u64) as f64;
+
+        let threads = camera.threads() as u32;
+
+        thread::scope(|scope| {
+            for t in 0..threads {
+                let bootstrap = &bootstrap;
+
+                scope.spawn(move || {
+                    for chain in (t..self.chains).step_by(threads as usize) {
+                        self.run_chain(
+                            camera,
+                            world,
+                            film,
+                            seed,
+                            chain,
+                            bootstrap,
+                            bootstrap_total,
+                            mutations_per_chain,
+                            splat_scale,
+                        );
+                    }
+                });
+            }
+        });
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Builder;
+    use crate::geometry::sphere::Sphere;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::point::Point3;
+    use crate::vec3::Vector3;
+    use std::sync::Arc;
+
+    #[test]
+    fn converges_to_the_same_image_as_path_tracing() {
+        let gray = Arc::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+        let emitter = Arc::new(DiffuseLight::new(&Color::new(4.0, 4.0, 4.0)));
+        let mut world = HittableList::new();
+
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, -100.5, -2.0),
+            100.0,
+            gray.clone(),
+        )));
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, 0.0, -2.0),
+            0.5,
+            gray,
+        )));
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, 2.0, -2.5),
+            0.5,
+            emitter,
+        )));
+
+        let world = Arc::new(world);
+        let mean = |integrator: Arc<dyn Integrator>| {
+            let image = Builder::new()
+                .set_image_width(16)
+                .set_image_aspect_ratio(4.0 / 3.0)
+                .set_samples_per_pixel(256)
+                .set_max_depth(4)
+                .set_vfov(60.0)
+                .set_lookat(&Point3::new(0.0, 0.0, -1.0))
+                .set_vup(&Vector3::new(0.0, 1.0, 0.0))
+                .set_focus_dist(2.0)
+                .set_background(&Color::new_default())
+                .set_seed(1)
+                .set_integrator(integrator)
+                .build()
+                .render(world.clone())
+                .unwrap();
+            let sum = image
+                .pixels()
+                .iter()
+                .fold(Color::new_default(), |sum, pixel| sum + *pixel);
+
+            sum.x() / image.pixels().len() as f64
+        };
+
+        let mut mlt = Mlt::new();
+        mlt.set_bootstrap_samples(10_000).set_chains(64);
+
+        let mlt = mean(Arc::new(mlt));
+        let path = mean(Arc::new(PathTracer));
+
+        assert!(f64::abs(mlt - path) < 0.05 * path, "{mlt} against {path}");
+    }
+
+    #[test]
+    fn rejected_mutations_replay_the_previous_samples() {
+        let mut sampler = MltSampler::new(7, 0.01, 0.3);
+        let initial: Vec<f64> = (0..16).map(|_| sampler.next()).collect();
+
+        for _ in 0..10 {
+            sampler.start_iteration();
+            let mutated: Vec<f64> = (0..16).map(|_| sampler.next()).collect();
+            assert_ne!(mutated, initial);
+            sampler.reject();
+        }
+
+        // rejecting leaves the values as they were, so a zero-sized step reads them back
+        sampler.sigma = 0.0;
+        sampler.large_step_probability = 0.0;
+        sampler.start_iteration();
+        let replayed: Vec<f64> = (0..16).map(|_| sampler.next()).collect();
+
+        assert_eq!(replayed, initial);
+    }
+}
@@ -0,0 +1,54 @@
+//! Unidirectional path tracing.
+
+use super::Integrator;
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::film::Film;
+use crate::geometry::hittable::{HitRecord, Hittable, HittableList};
+use crate::ray::Ray;
+use crate::util::{interval::Interval, INFINITY};
+
+/// unidirectional path tracing, following scattered rays until they leave the scene
+pub struct PathTracer;
+
+impl PathTracer {
+    fn ray_color(camera: &Camera, mut ray: Ray, world: &HittableList) -> Color {
+        let clamp = camera.radiance_clamp();
+        let mut beta = Color::new(1.0, 1.0, 1.0);
+        let mut radiance = Color::new_default();
+
+        for bounces in 0..camera.max_depth() as usize {
+            let mut rec = HitRecord::new();
+
+            if !world.hit(&ray, Interval::new(0.001, INFINITY), &mut rec) {
+                radiance += clamp.apply(bounces, &(beta * camera.background(&ray)));
+                break;
+            }
+
+            let mut scattered = Ray::new_default();
+            let mut attenuation = Color::new_default();
+            let material = rec.mat.clone().unwrap();
+
+            radiance += clamp.apply(bounces, &(beta * material.emitted(&rec)));
+
+            if !material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                break;
+            }
+
+            beta = beta * attenuation;
+            ray = scattered;
+        }
+
+        radiance
+    }
+}
+
+impl Integrator for PathTracer {
+    fn sample(&self, camera: &Camera, world: &HittableList, i: u32, j: u32, _film: &Film) -> Color {
+        let Some(ray) = camera.get_ray(i, j) else {
+            return Color::new_default();
+        };
+
+        PathTracer::ray_color(camera, ray, world)
+    }
+}
@@ -0,0 +1,490 @@
+//! Photon mapping.
+
+mod kdtree;
+
+use std::hash::Hasher;
+use std::sync::{Arc, RwLock};
+
+use self::kdtree::KdTree;
+use super::{sample_light, visible, Integrator};
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::film::Film;
+use crate::geometry::hittable::{HitRecord, Hittable, HittableList};
+use crate::material::Material;
+use crate::point::Point3;
+use crate::ray::Ray;
+use crate::util::{hash_f64s, interval::Interval, random_double, INFINITY, PI};
+use crate::vec3::{dot, random_unit_vector, Vector3};
+
+#[derive(Clone)]
+struct Photon {
+    p: Point3,
+    wi: Vector3, // unit direction back toward where the photon came from
+    power: Color,
+}
+
+struct PhotonMaps {
+    caustic: KdTree,
+    global: KdTree,
+    caustic_radius: f64,
+    global_radius: f64,
+}
+
+/// Two-pass photon mapping (Jensen). Photons are shot from the emitters in `lights` and
+/// stored where they land on diffuse surfaces: the caustic map keeps photons that got
+/// there only through specular bounces, the global map those that bounced off a diffuse
+/// surface first. Camera rays follow specular bounces to the first diffuse hit, where
+/// direct light is sampled explicitly and the rest comes from density estimation.
+///
+/// In progressive mode (Knaus and Zwicker) every pass traces fresh photons with a
+/// smaller gather radius, so averaging the passes converges to the correct image.
+///
+/// Only emissive objects shoot photons, so the background lights diffuse surfaces
+/// only through what camera rays see directly or in specular reflections.
+pub struct PhotonMapper {
+    lights: Arc<HittableList>,
+    caustic_photons: u32,
+    global_photons: u32,
+    caustic_radius: f64,
+    global_radius: f64,
+    passes: u32,
+    alpha: f64,
+    maps: RwLock<PhotonMaps>,
+}
+
+impl PhotonMapper {
+    /// the smallest alpha `set_progressive` accepts
+    pub const MIN_ALPHA: f64 = 0.01;
+
+    /// `lights` holds the emissive objects of the world, which must support `sample_surface`
+    pub fn new(lights: Arc<HittableList>) -> Self {
+        Self {
+            lights,
+            caustic_photons: 100_000,
+            global_photons: 50_000,
+            caustic_radius: 0.05,
+            global_radius: 0.2,
+            passes: 1,
+            alpha: 2.0 / 3.0,
+            maps: RwLock::new(PhotonMaps {
+                caustic: KdTree::new(vec![]),
+                global: KdTree::new(vec![]),
+                caustic_radius: 0.0,
+                global_radius: 0.0,
+            }),
+        }
+    }
+
+    /// photons shot for the caustic map
+    pub fn set_caustic_photons(&mut self, photons: u32) -> &mut Self {
+        self.caustic_photons = photons;
+        self
+    }
+
+    /// photons shot for the global map
+    pub fn set_global_photons(&mut self, photons: u32) -> &mut Self {
+        self.global_photons = photons;
+        self
+    }
+
+    /// how far from a hit caustic photons are gathered from
+    pub fn set_caustic_radius(&mut self, radius: f64) -> &mut Self {
+        self.caustic_radius = f64::max(radius, 0.0);
+        self
+    }
+
+    /// how far from a hit global photons are gathered from
+    pub fn set_global_radius(&mut self, radius: f64) -> &mut Self {
+        self.global_radius = f64::max(radius, 0.0);
+        self
+    }
+
+    /// renders `passes` times, shrinking the squared radii by (i + alpha) / (i + 1) after
+    /// pass i; alpha in (0, 1] trades noise (small) against bias (large) and is clamped
+    /// to at least `MIN_ALPHA`, since at 0 the radii collapse and the photons stop counting
+    pub fn set_progressive(&mut self, passes: u32, alpha: f64) -> &mut Self {
+        self.passes = u32::max(passes, 1);
+        self.alpha = alpha.clamp(Self::MIN_ALPHA, 1.0);
+        self
+    }
+
+    fn trace_photons(
+        &self,
+        camera: &Camera,
+        world: &HittableList,
+        count: u32,
+        caustic: bool,
+    ) -> Vec<Photon> {
+        let mut photons = vec![];
+
+        for _ in 0..count {
+            let Some((rec, pdf_pos)) = sample_light(&self.lights) else {
+                break;
+            };
+            let emitted = rec.mat.as_ref().unwrap().emitted(&rec);
+            let Ok(direction) = (rec.normal + random_unit_vector()).normalize() else {
+                continue;
+            };
+
+            if emitted.near_zero() {
+                continue;
+            }
+
+            // with cosine-weighted emission every photon carries the same share of the flux
+            let mut power = emitted * (PI / (pdf_pos * count as f64));
+            let mut ray = Ray::new(&rec.p, &direction);
+            let mut diffuse_bounces = 0;
+            let mut specular_bounces = 0;
+
+            for _ in 0..camera.max_depth() {
+                let mut rec = HitRecord::new();
+
+                if !world.hit(&ray, Interval::new(0.001, INFINITY), &mut rec) {
+                    break;
+                }
+
+                let material = rec.mat.clone().unwrap();
+
+                if material.is_specular() {
+                    specular_bounces += 1;
+                } else {
+                    let photon = Photon {
+                        p: rec.p,
+                        wi: (-*ray.direction()).normalize().unwrap(),
+                        power,
+                    };
+
+                    // photons landing straight from a light are left to direct lighting
+                    if caustic {
+                        if specular_bounces > 0 {
+                            photons.push(photon);
+                        }
+                        break;
+                    }
+
+                    if diffuse_bounces > 0 {
+                        photons.push(photon);
+                    }
+                    diffuse_bounces += 1;
+                }
+
+                let mut attenuation = Color::new_default();
+                let mut scattered = Ray::new_default();
+
+                if !material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                    break;
+                }
+
+                // russian roulette keeps the photon power constant instead of fading it
+                let survival = f64::min(
+                    f64::max(attenuation.x(), f64::max(attenuation.y(), attenuation.z())),
+                    1.0,
+                );
+
+                if random_double() >= survival {
+                    break;
+                }
+
+                power = power * attenuation * (1.0 / survival);
+                ray = scattered;
+            }
+        }
+
+        photons
+    }
+
+    /// radiance reflected toward `wo` by the photons within `radius` of the hit point
+    fn estimate(
+        map: &KdTree,
+        radius: f64,
+        material: &dyn Material,
+        rec: &HitRecord,
+        wo: &Vector3,
+    ) -> Color {
+        if map.is_empty() || radius <= 0.0 {
+            return Color::new_default();
+        }
+
+        let mut flux = Color::new_default();
+
+        map.for_each_within(&rec.p, radius, |photon| {
+            flux += photon.power * material.bsdf(rec, wo, &photon.wi);
+        });
+
+        flux * (1.0 / (PI * radius * radius))
+    }
+
+    /// next event estimation toward one point sampled on the lights
+    fn direct_light(
+        &self,
+        world: &HittableList,
+        material: &dyn Material,
+        rec: &HitRecord,
+        wo: &Vector3,
+    ) -> Color {
+        let Some((light, pdf)) = sample_light(&self.lights) else {
+            return Color::new_default();
+        };
+        let to_light = light.p - rec.p;
+        let distance_squared = to_light.length_squared();
+        let Ok(wi) = to_light.normalize() else {
+            return Color::new_default();
+        };
+
+        let cos_light = -dot(&light.normal, &wi);
+        let f = material.bsdf(rec, wo, &wi);
+
+        if cos_light <= 0.0 || f.near_zero() || !visible(world, &rec.p, &light.p) {
+            return Color::new_default();
+        }
+
+        let emitted = light.mat.as_ref().unwrap().emitted(&light);
+
+        emitted * f * (f64::abs(dot(&rec.normal, &wi)) * cos_light / (distance_squared * pdf))
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn sample(&self, camera: &Camera, world: &HittableList, i: u32, j: u32, _film: &Film) -> Color {
+        let maps = self.maps.read().unwrap();
+
+        let Some(mut ray) = camera.get_ray(i, j) else {
+            return Color::new_default();
+        };
+        let clamp = camera.radiance_clamp();
+        let mut beta = Color::new(1.0, 1.0, 1.0);
+        let mut radiance = Color::new_default();
+
+        for bounces in 0..camera.max_depth() as usize {
+            let mut rec = HitRecord::new();
+
+            if !world.hit(&ray, Interval::new(0.001, INFINITY), &mut rec) {
+                radiance += clamp.apply(bounces, &(beta * camera.background(&ray)));
+                break;
+            }
+
+            let material = rec.mat.clone().unwrap();
+            radiance += clamp.apply(bounces, &(beta * material.emitted(&rec)));
+
+            if !material.is_specular() {
+                let wo = (-*ray.direction()).normalize().unwrap();
+                let caustic = Self::estimate(
+                    &maps.caustic,
+                    maps.caustic_radius,
+                    material.as_ref(),
+                    &rec,
+                    &wo,
+                );
+                let global = Self::estimate(
+                    &maps.global,
+                    maps.global_radius,
+                    material.as_ref(),
+                    &rec,
+                    &wo,
+                );
+                let direct = self.direct_light(world, material.as_ref(), &rec, &wo);
+
+                // direct light bounces once more here, the photons at least twice
+                radiance += clamp.apply(bounces + 1, &(beta * direct));
+                radiance += clamp.apply(bounces + 2, &(beta * (caustic + global)));
+                break;
+            }
+
+            let mut attenuation = Color::new_default();
+            let mut scattered = Ray::new_default();
+
+            if !material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
+                break;
+            }
+
+            beta = beta * attenuation;
+            ray = scattered;
+        }
+
+        radiance
+    }
+
+    fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"photon");
+        state.write_u32(self.caustic_photons);
+        state.write_u32(self.global_photons);
+        state.write_u32(self.passes);
+        hash_f64s(
+            state,
+            &[self.caustic_radius, self.global_radius, self.alpha],
+        );
+        self.lights.fingerprint(state);
+    }
+
+    fn begin_pass(&self, pass: u32, camera: &Camera, world: &HittableList) {
+        let mut shrink = 1.0;
+
+        for i in 1..=pass {
+            shrink *= (i as f64 + self.alpha) / (i as f64 + 1.0);
+        }
+
+        let caustic = KdTree::new(self.trace_photons(camera, world, self.caustic_photons, true));
+        let global = KdTree::new(self.trace_photons(camera, world, self.global_photons, false));
+
+        *self.maps.write().unwrap() = PhotonMaps {
+            caustic,
+            global,
+            caustic_radius: self.caustic_radius * f64::sqrt(shrink),
+            global_radius: self.global_radius * f64::sqrt(shrink),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Builder;
+    use crate::geometry::sphere::Sphere;
+    use crate::integrator::path::PathTracer;
+    use crate::material::{DiffuseLight, Lambertian, Metal};
+
+    fn light() -> Arc<Sphere> {
+        let emitter = Arc::new(DiffuseLight::new(&Color::new(4.0, 4.0, 4.0)));
+
+        Arc::new(Sphere::new(&Point3::new(0.0, 2.0, -2.5), 0.5, emitter))
+    }
+
+    fn camera(integrator: Arc<dyn Integrator>, samples_per_pixel: u32) -> Camera {
+        Builder::new()
+            .set_image_width(16)
+            .set_image_aspect_ratio(4.0 / 3.0)
+            .set_samples_per_pixel(samples_per_pixel)
+            .set_max_depth(4)
+            .set_vfov(60.0)
+            .set_lookat(&Point3::new(0.0, 0.0, -1.0))
+            .set_vup(&Vector3::new(0.0, 1.0, 0.0))
+            .set_focus_dist(2.0)
+            // all the light comes from the emitter, which is all the photons see
+            .set_background(&Color::new_default())
+            .set_seed(1)
+            .set_integrator(integrator)
+            .build()
+    }
+
+    #[test]
+    fn converges_to_the_same_image_as_path_tracing() {
+        let gray = Arc::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, -100.5, -2.0),
+            100.0,
+            gray.clone(),
+        )));
+        world.add(Arc::new(Sphere::new(
+            &Point3::new(0.0, 0.0, -2.0),
+            0.5,
+            gray,
+        )));
+        world.add(light());
+        lights.add(light());
+
+        let world = Arc::new(world);
+        let mean = |integrator: Arc<dyn Integrator>| {
+            let image = camera(integrator, 256).render(world.clone()).unwrap();
+            let sum = image
+                .pixels()
+                .iter()
+                .fold(Color::new_default(), |sum, pixel| sum + *pixel);
+
+            sum.x() / image.pixels().len() as f64
+        };
+
+        let mut photon = PhotonMapper::new(Arc::new(lights));
+        photon.set_global_photons(200_000).set_global_radius(0.1);
+
+        let photon = mean(Arc::new(photon));
+        let path = mean(Arc::new(PathTracer));
+
+        assert!(
+            f64::abs(photon - path) < 0.05 * path,
+            "{photon} against {path}"
+        );
+    }
+
+    #[test]
+    fn only_photons_bounced_off_a_specular_surface_are_caustic() {
+        let gray = Arc::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+        let scene = |ball: Arc<dyn Material>| {
+            let mut world = HittableList::new();
+            world.add(Arc::new(Sphere::new(
+                &Point3::new(0.0, -100.5, -2.0),
+                100.0,
+                gray.clone(),
+            )));
+            world.add(Arc::new(Sphere::new(
+                &Point3::new(0.0, 0.0, -2.0),
+                0.5,
+                ball,
+            )));
+            world.add(light());
+            world
+        };
+        let mut lights = HittableList::new();
+        lights.add(light());
+        let mapper = PhotonMapper::new(Arc::new(lights));
+        let camera = camera(Arc::new(PathTracer), 1);
+
+        let diffuse = scene(gray.clone());
+        assert!(mapper
+            .trace_photons(&camera, &diffuse, 2000, true)
+            .is_empty());
+        assert!(!mapper
+            .trace_photons(&camera, &diffuse, 2000, false)
+            .is_empty());
+
+        let mirror = scene(Arc::new(Metal::new(&Color::new(0.9, 0.9, 0.9), 0.0)));
+        assert!(!mapper
+            .trace_photons(&camera, &mirror, 2000, true)
+            .is_empty());
+    }
+
+    #[test]
+    fn progressive_passes_shrink_the_squared_radii_by_the_running_product() {
+        let mut mapper = PhotonMapper::new(Arc::new(HittableList::new()));
+        mapper
+            .set_caustic_radius(0.4)
+            .set_global_radius(1.0)
+            .set_progressive(4, 0.5);
+        let camera = camera(Arc::new(PathTracer), 1);
+        let world = HittableList::new();
+
+        let mut expected = 1.0;
+
+        for pass in 0..4 {
+            if pass > 0 {
+                expected *= (pass as f64 + 0.5) / (pass as f64 + 1.0);
+            }
+
+            mapper.begin_pass(pass, &camera, &world);
+            let maps = mapper.maps.read().unwrap();
+
+            assert!(f64::abs(maps.global_radius.powi(2) - expected) < 1e-12);
+            assert!(f64::abs(maps.caustic_radius.powi(2) - 0.16 * expected) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn alpha_is_kept_above_zero() {
+        let mut mapper = PhotonMapper::new(Arc::new(HittableList::new()));
+        mapper.set_progressive(2, 0.0);
+        assert_eq!(mapper.alpha, PhotonMapper::MIN_ALPHA);
+
+        mapper.set_progressive(2, -1.0);
+        assert_eq!(mapper.alpha, PhotonMapper::MIN_ALPHA);
+
+        mapper.set_progressive(2, 3.0);
+        assert_eq!(mapper.alpha, 1.0);
+    }
+}
@@ -0,0 +1,149 @@
+use super::Photon;
+use crate::point::Point3;
+
+fn coordinate(p: &Point3, axis: usize) -> f64 {
+    match axis {
+        0 => p.x(),
+        1 => p.y(),
+        _ => p.z(),
+    }
+}
+
+/// Balanced kd-tree stored implicitly: the photon splitting the range [lo, hi) sits at
+/// its middle index, with the lower half before it and the upper half after it.
+pub struct KdTree {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl KdTree {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        let len = photons.len();
+
+        Self::build(&mut photons, &mut axes, 0, len);
+
+        Self { photons, axes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// calls `visit` for every photon closer than `radius` to `p`
+    pub fn for_each_within<F>(&self, p: &Point3, radius: f64, mut visit: F)
+    where
+        F: FnMut(&Photon),
+    {
+        self.search(0, self.photons.len(), p, radius * radius, &mut visit);
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [usize], lo: usize, hi: usize) {
+        if hi - lo <= 1 {
+            return;
+        }
+
+        // split along the axis with the widest spread
+        let mut min = photons[lo].p;
+        let mut max = photons[lo].p;
+
+        for photon in &photons[lo..hi] {
+            min = Point3::new(
+                f64::min(min.x(), photon.p.x()),
+                f64::min(min.y(), photon.p.y()),
+                f64::min(min.z(), photon.p.z()),
+            );
+            max = Point3::new(
+                f64::max(max.x(), photon.p.x()),
+                f64::max(max.y(), photon.p.y()),
+                f64::max(max.z(), photon.p.z()),
+            );
+        }
+
+        let extent = max - min;
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+            0
+        } else if extent.y() >= extent.z() {
+            1
+        } else {
+            2
+        };
+
+        let mid = (lo + hi) / 2;
+        photons[lo..hi].select_nth_unstable_by(mid - lo, |a, b| {
+            coordinate(&a.p, axis).total_cmp(&coordinate(&b.p, axis))
+        });
+        axes[mid] = axis;
+
+        Self::build(photons, axes, lo, mid);
+        Self::build(photons, axes, mid + 1, hi);
+    }
+
+    fn search<F>(&self, lo: usize, hi: usize, p: &Point3, radius_squared: f64, visit: &mut F)
+    where
+        F: FnMut(&Photon),
+    {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+
+        if (photon.p - *p).length_squared() <= radius_squared {
+            visit(photon);
+        }
+
+        let axis = self.axes[mid];
+        let delta = coordinate(p, axis) - coordinate(&photon.p, axis);
+        let ((near_lo, near_hi), (far_lo, far_hi)) = if delta < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+
+        self.search(near_lo, near_hi, p, radius_squared, visit);
+
+        if delta * delta <= radius_squared {
+            self.search(far_lo, far_hi, p, radius_squared, visit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::vec3::{random_in_range, Vector3};
+
+    #[test]
+    fn finds_the_same_photons_as_a_linear_scan() {
+        let photons: Vec<Photon> = (0..500)
+            .map(|_| Photon {
+                p: random_in_range(-1.0, 1.0),
+                wi: Vector3::new(0.0, 1.0, 0.0),
+                power: Color::new(1.0, 1.0, 1.0),
+            })
+            .collect();
+        let tree = KdTree::new(photons.clone());
+
+        for _ in 0..20 {
+            let center = random_in_range(-1.0, 1.0);
+            let radius = 0.3;
+
+            let mut expected: Vec<Point3> = photons
+                .iter()
+                .filter(|photon| (photon.p - center).length() <= radius)
+                .map(|photon| photon.p)
+                .collect();
+            let mut found = vec![];
+            tree.for_each_within(&center, radius, |photon| found.push(photon.p));
+
+            let by_x = |a: &Point3, b: &Point3| a.x().total_cmp(&b.x());
+            expected.sort_by(by_x);
+            found.sort_by(by_x);
+
+            assert_eq!(found, expected);
+        }
+    }
+}
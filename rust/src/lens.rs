@@ -0,0 +1,498 @@
+//! Real lenses, as a sequence of spherical surfaces traced through.
+
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, ErrorKind};
+
+use crate::point::Point3;
+use crate::util::{hash_f64s, random_double_2d};
+use crate::vec3::{dot, refract, Vector3};
+
+/// film distances the exit pupil is tabulated for, from the center out to the corner
+const PUPIL_BINS: usize = 64;
+/// points tried across the rear element per bin, along each axis
+const PUPIL_GRID: usize = 64;
+
+/// One surface of a lens prescription, front (scene side) first. Lengths are in
+/// millimeters; a radius of zero marks the aperture stop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Surface {
+    radius: f64,    // of curvature, positive when the center lies toward the film
+    thickness: f64, // along the axis to the next surface, or to the film for the last one
+    ior: f64,       // of the medium behind the surface, toward the film
+    aperture: f64,  // diameter
+}
+
+/// A camera lens described surface by surface, as in lens design books: rays are traced
+/// from the film through every element, which blurs, distorts and vignettes the image
+/// like a real lens does.
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    surfaces: Vec<Surface>,
+    film_diagonal: f64,
+    scale: f64,
+}
+
+impl LensSystem {
+    /// Reads a prescription with one surface per line: radius, thickness, index of
+    /// refraction and aperture diameter, in millimeters and front element first. Blank
+    /// lines and everything after a '#' are skipped; an index of 0 stands for air. The last
+    /// thickness is the distance from the rear element to the film and must be positive.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+        let mut surfaces = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|_| invalid(format!("line {}: expected numbers", number + 1)))?;
+
+            let [radius, thickness, ior, aperture] = values[..] else {
+                return Err(invalid(format!(
+                    "line {}: expected radius, thickness, ior and aperture",
+                    number + 1
+                )));
+            };
+
+            surfaces.push(Surface {
+                radius,
+                thickness,
+                ior: if ior == 0.0 { 1.0 } else { ior },
+                aperture,
+            });
+        }
+
+        if surfaces.is_empty() {
+            return Err(invalid("the prescription has no surfaces".to_string()));
+        }
+
+        // the rear thickness is the distance to the film, which autofocus starts from
+        let film_distance = surfaces[surfaces.len() - 1].thickness;
+
+        if film_distance.is_nan() || film_distance <= 0.0 {
+            return Err(invalid(
+                "the last surface's thickness, its distance to the film, must be positive"
+                    .to_string(),
+            ));
+        }
+
+        Ok(Self {
+            surfaces,
+            film_diagonal: 35.0,
+            scale: 0.001,
+        })
+    }
+
+    /// reads the prescription in the file at `path`, see `parse`
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// diagonal of the sensor in millimeters, 35 by default
+    pub fn set_film_diagonal(&mut self, diagonal: f64) -> &mut Self {
+        self.film_diagonal = diagonal;
+        self
+    }
+
+    /// world units per millimeter, 0.001 by default for scenes measured in meters
+    pub fn set_scale(&mut self, scale: f64) -> &mut Self {
+        self.scale = scale;
+        self
+    }
+
+    /// The lens moved so that objects `focus_distance` world units in front of the film
+    /// are sharp (or left as prescribed for a distance of zero), for a sensor of the given
+    /// aspect ratio, with its exit pupil worked out.
+    pub(crate) fn focus(&self, focus_distance: f64, aspect_ratio: f64) -> RealisticLens {
+        let film_height = self.film_diagonal / f64::sqrt(1.0 + aspect_ratio * aspect_ratio);
+
+        let mut lens = RealisticLens {
+            surfaces: self.surfaces.clone(),
+            film_width: film_height * aspect_ratio,
+            film_height,
+            scale: self.scale,
+            pupil_bounds: vec![],
+            largest_pupil: 0.0,
+        };
+
+        if focus_distance > 0.0 {
+            lens.autofocus(focus_distance / self.scale);
+        }
+
+        lens.find_exit_pupil();
+
+        lens
+    }
+}
+
+/// A lens system placed in front of the film: the film lies at z = 0 in millimeters,
+/// the lens and the scene toward +z.
+pub(crate) struct RealisticLens {
+    surfaces: Vec<Surface>,
+    film_width: f64,
+    film_height: f64,
+    scale: f64,
+    /// bounds (min x, min y, max x, max y) on the rear element of the rays that get
+    /// through, for film points on the +x axis at increasing distances
+    pupil_bounds: Vec<[f64; 4]>,
+    largest_pupil: f64,
+}
+
+impl RealisticLens {
+    /// world units per millimeter
+    pub(crate) fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// feeds the focused prescription and the film size into `state`, for the camera's
+    /// fingerprint
+    pub(crate) fn fingerprint(&self, state: &mut dyn Hasher) {
+        for surface in &self.surfaces {
+            hash_f64s(
+                state,
+                &[
+                    surface.radius,
+                    surface.thickness,
+                    surface.ior,
+                    surface.aperture,
+                ],
+            );
+        }
+
+        hash_f64s(state, &[self.film_width, self.film_height, self.scale]);
+    }
+
+    /// A ray leaving the front element for the film point at (s, t) of the unit square,
+    /// (0, 0) being the top left of the image, in millimeters with the film at the origin
+    /// and the scene toward +z. Rays stopped inside the lens are None, and so is a share
+    /// of the rest to darken the image toward its corners like real lenses do (natural
+    /// vignetting, cos⁴ and the shrinking exit pupil).
+    pub(crate) fn ray(&self, s: f64, t: f64, accept: f64) -> Option<(Point3, Vector3)> {
+        // the image on the film is upside down and mirrored
+        let film = Point3::new(
+            (0.5 - s) * self.film_width,
+            (t - 0.5) * self.film_height,
+            0.0,
+        );
+        let radius = f64::sqrt(film.x() * film.x() + film.y() * film.y());
+        let bounds = self.pupil_bounds[self.pupil_bin(radius)];
+        let area = (bounds[2] - bounds[0]) * (bounds[3] - bounds[1]);
+
+        if area <= 0.0 {
+            return None;
+        }
+
+        // the bounds were found along +x, so turn them toward the film point
+        let (u, v) = random_double_2d();
+        let (bx, by) = (
+            bounds[0] + u * (bounds[2] - bounds[0]),
+            bounds[1] + v * (bounds[3] - bounds[1]),
+        );
+        let (sin, cos) = if radius > 0.0 {
+            (film.y() / radius, film.x() / radius)
+        } else {
+            (0.0, 1.0)
+        };
+        let on_rear = Point3::new(bx * cos - by * sin, bx * sin + by * cos, self.rear_z());
+
+        let direction = (on_rear - film).normalize().ok()?;
+        let cos2_theta = direction.z() * direction.z();
+
+        if accept >= cos2_theta * cos2_theta * area / self.largest_pupil {
+            return None;
+        }
+
+        self.trace(film, direction, true)
+    }
+
+    fn pupil_bin(&self, radius: f64) -> usize {
+        let corner = f64::hypot(self.film_width, self.film_height) / 2.0;
+
+        usize::min(
+            (radius / corner * PUPIL_BINS as f64) as usize,
+            PUPIL_BINS - 1,
+        )
+    }
+
+    /// distance of every surface from the film
+    fn positions(&self) -> Vec<f64> {
+        let mut z = 0.0;
+        let mut positions: Vec<f64> = self
+            .surfaces
+            .iter()
+            .rev()
+            .map(|surface| {
+                z += surface.thickness;
+                z
+            })
+            .collect();
+
+        positions.reverse();
+        positions
+    }
+
+    fn rear_z(&self) -> f64 {
+        self.surfaces[self.surfaces.len() - 1].thickness
+    }
+
+    /// follows a ray through every surface, from the film toward the scene or the other way
+    /// around; None if it misses an element, hits a stop or reflects internally
+    fn trace(
+        &self,
+        origin: Point3,
+        direction: Vector3,
+        from_film: bool,
+    ) -> Option<(Point3, Vector3)> {
+        let positions = self.positions();
+        let (mut origin, mut direction) = (origin, direction);
+        let order: Vec<usize> = if from_film {
+            (0..self.surfaces.len()).rev().collect()
+        } else {
+            (0..self.surfaces.len()).collect()
+        };
+
+        for index in order {
+            let surface = &self.surfaces[index];
+            let vertex = positions[index];
+
+            let (hit, normal) = if surface.radius == 0.0 {
+                let t = (vertex - origin.z()) / direction.z();
+
+                if !t.is_finite() || t <= 0.0 {
+                    return None;
+                }
+
+                (origin + t * direction, Vector3::new(0.0, 0.0, 1.0))
+            } else {
+                let center = Point3::new(0.0, 0.0, vertex - surface.radius);
+                let oc = origin - center;
+                let b = dot(&oc, &direction);
+                let c = oc.length_squared() - surface.radius * surface.radius;
+                let discriminant = b * b - c;
+
+                if discriminant < 0.0 {
+                    return None;
+                }
+
+                // of the two crossings, the one on the cap around the vertex
+                let root = f64::sqrt(discriminant);
+                let t = [-b - root, -b + root]
+                    .into_iter()
+                    .filter(|&t| t > 1e-9)
+                    .min_by(|&a, &b| {
+                        let distance = |t: f64| f64::abs((origin + t * direction).z() - vertex);
+                        distance(a).total_cmp(&distance(b))
+                    })?;
+                let hit = origin + t * direction;
+
+                (hit, (hit - center) * (1.0 / surface.radius.abs()))
+            };
+
+            let aperture_radius = surface.aperture / 2.0;
+
+            if hit.x() * hit.x() + hit.y() * hit.y() > aperture_radius * aperture_radius {
+                return None;
+            }
+
+            if surface.radius != 0.0 {
+                let front_ior = if index == 0 {
+                    1.0
+                } else {
+                    self.surfaces[index - 1].ior
+                };
+                let (from, to) = if from_film {
+                    (surface.ior, front_ior)
+                } else {
+                    (front_ior, surface.ior)
+                };
+
+                let normal = if dot(&normal, &direction) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                };
+                let ratio = from / to;
+                let cos_theta = f64::min(-dot(&direction, &normal), 1.0);
+
+                // total internal reflection
+                if ratio * ratio * (1.0 - cos_theta * cos_theta) > 1.0 {
+                    return None;
+                }
+
+                direction = refract(&direction, &normal, ratio).normalize().ok()?;
+            }
+
+            origin = hit;
+        }
+
+        Some((origin, direction))
+    }
+
+    /// Moves the lens along the axis (the last thickness) until a point on the axis
+    /// `distance` millimeters in front of the film is imaged onto the film.
+    fn autofocus(&mut self, distance: f64) {
+        let last = self.surfaces.len() - 1;
+        let front_aperture = self.surfaces[0].aperture / 2.0;
+
+        // where a ray from the object point, close to the axis, crosses the axis again
+        // behind the lens; positive when the image falls in front of the film
+        let image_z = |lens: &RealisticLens| -> Option<f64> {
+            let front = lens.positions()[0];
+            let object = Point3::new(0.0, 0.0, distance);
+            let target = Point3::new(0.01 * front_aperture, 0.0, front);
+            let (origin, direction) =
+                lens.trace(object, (target - object).normalize().ok()?, false)?;
+
+            if direction.x() == 0.0 {
+                return None;
+            }
+
+            Some(origin.z() - origin.x() / direction.x() * direction.z())
+        };
+
+        // the image moves the same way as the lens, so bisect on the film distance
+        let mut low = 0.0;
+        let mut high = self.surfaces[last].thickness;
+
+        while high < distance {
+            self.surfaces[last].thickness = high;
+
+            if image_z(self).is_some_and(|z| z > 0.0) {
+                break;
+            }
+
+            high *= 2.0;
+        }
+
+        for _ in 0..64 {
+            let middle = (low + high) / 2.0;
+            self.surfaces[last].thickness = middle;
+
+            match image_z(self) {
+                Some(z) if z > 0.0 => high = middle,
+                _ => low = middle,
+            }
+        }
+
+        self.surfaces[last].thickness = (low + high) / 2.0;
+    }
+
+    /// For film points along +x, the region of the rear element that rays from there get
+    /// through the lens by: found by trying a grid of points over the rear element.
+    fn find_exit_pupil(&mut self) {
+        let rear = &self.surfaces[self.surfaces.len() - 1];
+        let rear_radius = rear.aperture / 2.0;
+        let rear_z = self.rear_z();
+        let corner = f64::hypot(self.film_width, self.film_height) / 2.0;
+        let spacing = 2.0 * rear_radius / PUPIL_GRID as f64;
+
+        self.pupil_bounds = (0..PUPIL_BINS)
+            .map(|bin| {
+                let film = Point3::new((bin as f64 + 0.5) / PUPIL_BINS as f64 * corner, 0.0, 0.0);
+                let mut bounds = [f64::INFINITY, f64::INFINITY, -f64::INFINITY, -f64::INFINITY];
+
+                for gy in 0..PUPIL_GRID {
+                    for gx in 0..PUPIL_GRID {
+                        let x = -rear_radius + (gx as f64 + 0.5) * spacing;
+                        let y = -rear_radius + (gy as f64 + 0.5) * spacing;
+                        let Ok(direction) = (Point3::new(x, y, rear_z) - film).normalize() else {
+                            continue;
+                        };
+
+                        if self.trace(film, direction, true).is_some() {
+                            bounds = [
+                                f64::min(bounds[0], x),
+                                f64::min(bounds[1], y),
+                                f64::max(bounds[2], x),
+                                f64::max(bounds[3], y),
+                            ];
+                        }
+                    }
+                }
+
+                if bounds[0] > bounds[2] {
+                    return [0.0; 4];
+                }
+
+                // grown by a grid cell, since the real edge lies somewhere in between
+                [
+                    bounds[0] - spacing,
+                    bounds[1] - spacing,
+                    bounds[2] + spacing,
+                    bounds[3] + spacing,
+                ]
+            })
+            .collect();
+
+        self.largest_pupil = self
+            .pupil_bounds
+            .iter()
+            .map(|bounds| (bounds[2] - bounds[0]) * (bounds[3] - bounds[1]))
+            .fold(0.0, f64::max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a biconvex singlet of about 50 mm focal length behind a stop
+    const SINGLET: &str = "
+        # radius  thickness  ior    aperture
+          0       20         0      12        # stop
+          50      6          1.517  20
+         -50      45         1      20
+    ";
+
+    #[test]
+    fn autofocus_images_the_focus_distance_onto_the_film() {
+        let system = LensSystem::parse(SINGLET).unwrap();
+        let lens = system.focus(2.0, 1.5);
+
+        // rays from the point in focus through different parts of the stop meet on the film
+        let object = Point3::new(0.0, 0.0, 2000.0);
+        let stop_z = lens.positions()[0];
+
+        for height in [0.5, 2.0, 4.0] {
+            let target = Point3::new(height, 0.0, stop_z);
+            let (origin, direction) = lens
+                .trace(object, (target - object).normalize().unwrap(), false)
+                .unwrap();
+            let on_film = origin.x() - origin.z() / direction.z() * direction.x();
+
+            assert!(
+                f64::abs(on_film) < 0.05,
+                "{} mm off at height {}",
+                on_film,
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_a_film_distance_that_is_not_positive() {
+        for thickness in ["0", "-45", "NaN"] {
+            let prescription = SINGLET.replace("-50      45", &format!("-50 {thickness}"));
+            let error = LensSystem::parse(&prescription).err().unwrap();
+
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn the_exit_pupil_shrinks_toward_the_corners() {
+        let lens = LensSystem::parse(SINGLET).unwrap().focus(2.0, 1.5);
+        let area = |bounds: &[f64; 4]| (bounds[2] - bounds[0]) * (bounds[3] - bounds[1]);
+
+        assert!(area(&lens.pupil_bounds[0]) > 0.0);
+        assert!(area(&lens.pupil_bounds[PUPIL_BINS - 1]) <= area(&lens.pupil_bounds[0]));
+    }
+}
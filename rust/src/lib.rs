@@ -0,0 +1,65 @@
+//! A physically based ray tracer: build a scene out of [`geometry`] and [`material`]s,
+//! point a [`camera::Camera`] at it, render and write the [`image::Image`] out.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use std::sync::Arc;
+//!
+//! use rust::camera::Builder;
+//! use rust::color::Color;
+//! use rust::geometry::hittable::HittableList;
+//! use rust::geometry::sphere::Sphere;
+//! use rust::material::Lambertian;
+//! use rust::point::Point3;
+//! use rust::vec3::Vector3;
+//!
+//! let mut world = HittableList::new();
+//! let gray = Arc::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+//! world.add(Arc::new(Sphere::new(&Point3::new(0.0, 0.0, -1.0), 0.5, gray)));
+//!
+//! let camera = Builder::new()
+//!     .set_image_width(400)
+//!     .set_image_aspect_ratio(16.0 / 9.0)
+//!     .set_samples_per_pixel(100)
+//!     .set_max_depth(50)
+//!     .set_vfov(90.0)
+//!     .set_lookfrom(&Point3::new(0.0, 0.0, 0.0))
+//!     .set_lookat(&Point3::new(0.0, 0.0, -1.0))
+//!     .set_vup(&Vector3::new(0.0, 1.0, 0.0))
+//!     .set_focus_dist(1.0)
+//!     .build();
+//!
+//! let image = camera.render(Arc::new(world)).unwrap();
+//! image.save(Path::new("image.png")).unwrap();
+//! ```
+
+#![warn(missing_docs)]
+
+pub mod animation;
+pub mod aov;
+pub mod aperture;
+pub mod camera;
+pub mod checkpoint;
+pub mod color;
+pub mod compare;
+pub mod denoise;
+pub mod effects;
+pub mod film;
+pub mod filter;
+pub mod firefly;
+pub mod geometry;
+pub mod image;
+pub mod integrator;
+pub mod lens;
+pub mod material;
+pub mod point;
+pub mod progressive;
+pub mod projection;
+pub mod quaternion;
+pub mod ray;
+pub mod sampler;
+mod scheduler;
+pub mod stereo;
+pub mod tonemap;
+pub mod util;
+pub mod vec3;
@@ -1,24 +1,255 @@
-mod camera;
-mod color;
-mod geometry;
-mod material;
-mod point;
-mod ray;
-mod util;
-mod vec3;
-
-use crate::camera::{Builder, Camera};
-use color::Color;
-use geometry::hittable::{Hittable, HittableList};
-use geometry::sphere::Sphere;
-use material::{Dielectric, Lambertian, Metal};
-use point::Point3;
-use std::sync::{Arc, LazyLock, RwLock};
+use rust::aov::Aov;
+use rust::camera::Builder;
+use rust::color::Color;
+use rust::compare::compare;
+use rust::denoise::Denoiser;
+use rust::effects::{Bloom, Effects, Glare};
+use rust::firefly::{Limit, OutlierRejection, RadianceClamp};
+use rust::geometry::hittable::HittableList;
+use rust::geometry::sphere::Sphere;
+use rust::image::{ppm, Format, Image};
+use rust::material::{Dielectric, Lambertian, Metal};
+use rust::point::Point3;
+use rust::tonemap::{ToneMapper, ToneMapping};
+use rust::vec3::Vector3;
+use std::env;
+use std::io::{self, BufWriter, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::SystemTime;
-use vec3::Vector3;
+
+const USAGE: &str = "usage: rust [--output <image.ppm|png|bmp|tga|pfm|hdr|exr>] \
+                     [--aovs <all|albedo,normal,depth,position,uv,material_id,object_id,alpha>] \
+                     [--exposure <stops> | --exposure-scale <factor>] \
+                     [--tonemap <clamp|reinhard|extended-reinhard[=white]|hable|aces|agx>] \
+                     [--clamp-direct <max|luminance=max>] [--clamp-indirect <max|luminance=max>] \
+                     [--reject-outliers] [--denoise] [--bloom <strength>] [--glare <strength>] \
+                     [--chromatic-aberration <strength>] [--vignetting <strength>]\n       \
+                     rust compare <reference> <image> [--diff <image>] \
+                     [--threshold <error>] [--metric <flip|mse|rmse>]";
+
+/// Where the image goes: to the file, in the format its extension names, or as a plain PPM
+/// to standard output; the AOVs to write with it, which need a file; how fireflies are
+/// kept down; whether it is denoised; the lens effects; and how it is exposed and tone
+/// mapped.
+struct Options {
+    output: Option<PathBuf>,
+    aovs: Vec<Aov>,
+    radiance_clamp: RadianceClamp,
+    reject_outliers: bool,
+    denoise: bool,
+    effects: Effects,
+    tone_mapping: ToneMapping,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidInput, message);
+        let mut options = Self {
+            output: None,
+            aovs: vec![],
+            radiance_clamp: RadianceClamp::new(),
+            reject_outliers: false,
+            denoise: false,
+            effects: Effects::new(),
+            tone_mapping: ToneMapping::default(),
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "--output" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| invalid(format!("{arg} needs a path\n{USAGE}")))?;
+                    options.output = Some(PathBuf::from(path));
+                }
+                "--aovs" => {
+                    let names = args
+                        .next()
+                        .ok_or_else(|| invalid(format!("{arg} needs a list of AOVs\n{USAGE}")))?;
+
+                    options.aovs = match names.as_str() {
+                        "all" => Aov::ALL.to_vec(),
+                        _ => names
+                            .split(',')
+                            .map(|name| {
+                                Aov::from_name(name)
+                                    .ok_or_else(|| invalid(format!("unknown AOV {name}\n{USAGE}")))
+                            })
+                            .collect::<io::Result<_>>()?,
+                    };
+                }
+                "--exposure" | "--exposure-scale" => {
+                    let value = args
+                        .next()
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .filter(|value| value.is_finite())
+                        .ok_or_else(|| invalid(format!("{arg} needs a number\n{USAGE}")))?;
+
+                    if arg == "--exposure" {
+                        options.tone_mapping.set_exposure(value);
+                    } else if value > 0.0 {
+                        options.tone_mapping.set_exposure_scale(value);
+                    } else {
+                        return Err(invalid(format!("{arg} must be positive\n{USAGE}")));
+                    }
+                }
+                "--clamp-direct" | "--clamp-indirect" => {
+                    let limit = args
+                        .next()
+                        .and_then(|limit| Limit::parse(&limit))
+                        .ok_or_else(|| invalid(format!("{arg} needs a limit\n{USAGE}")))?;
+
+                    if arg == "--clamp-direct" {
+                        options.radiance_clamp.set_direct(limit);
+                    } else {
+                        options.radiance_clamp.set_indirect(limit);
+                    }
+                }
+                "--reject-outliers" => options.reject_outliers = true,
+                "--denoise" => options.denoise = true,
+                "--bloom" | "--glare" | "--chromatic-aberration" | "--vignetting" => {
+                    let strength = args
+                        .next()
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .filter(|value| value.is_finite() && *value >= 0.0)
+                        .ok_or_else(|| invalid(format!("{arg} needs a strength\n{USAGE}")))?;
+                    let effects = &mut options.effects;
+
+                    match arg.as_str() {
+                        "--bloom" => effects.set_bloom(*Bloom::new().set_strength(strength)),
+                        "--glare" => effects.set_glare(*Glare::new().set_strength(strength)),
+                        "--chromatic-aberration" => effects.set_chromatic_aberration(strength),
+                        _ => effects.set_vignetting(strength),
+                    };
+                }
+                "--tonemap" => {
+                    let mapper = args
+                        .next()
+                        .and_then(|name| ToneMapper::from_name(&name))
+                        .ok_or_else(|| invalid(format!("{arg} needs a tone mapper\n{USAGE}")))?;
+                    let exposure = options.tone_mapping.exposure();
+
+                    options.tone_mapping = ToneMapping::new(mapper);
+                    options.tone_mapping.set_exposure(exposure);
+                }
+                _ => return Err(invalid(format!("unknown argument {arg}\n{USAGE}"))),
+            }
+        }
+
+        if !options.aovs.is_empty() && options.output.is_none() {
+            return Err(invalid(format!(
+                "AOVs are written beside --output\n{USAGE}"
+            )));
+        }
+
+        Ok(options)
+    }
+}
+
+/// The images to compare, where the false color difference goes, and the error past which
+/// the comparison fails.
+struct CompareOptions {
+    reference: PathBuf,
+    test: PathBuf,
+    diff: Option<PathBuf>,
+    threshold: Option<f64>,
+    metric: String,
+}
+
+impl CompareOptions {
+    fn parse(mut args: impl Iterator<Item = String>) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidInput, message);
+        let mut paths = vec![];
+        let mut diff = None;
+        let mut threshold = None;
+        let mut metric = "flip".to_string();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--diff" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| invalid(format!("{arg} needs a path\n{USAGE}")))?;
+                    diff = Some(PathBuf::from(path));
+                }
+                "--threshold" => {
+                    let value = args
+                        .next()
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .ok_or_else(|| invalid(format!("{arg} needs a number\n{USAGE}")))?;
+                    threshold = Some(value);
+                }
+                "--metric" => {
+                    metric = args
+                        .next()
+                        .filter(|name| ["flip", "mse", "rmse"].contains(&name.as_str()))
+                        .ok_or_else(|| {
+                            invalid(format!("{arg} needs flip, mse or rmse\n{USAGE}"))
+                        })?;
+                }
+                _ if !arg.starts_with('-') => paths.push(PathBuf::from(arg)),
+                _ => return Err(invalid(format!("unknown argument {arg}\n{USAGE}"))),
+            }
+        }
+
+        match <[PathBuf; 2]>::try_from(paths) {
+            Ok([reference, test]) => Ok(Self {
+                reference,
+                test,
+                diff,
+                threshold,
+                metric,
+            }),
+            Err(_) => Err(invalid(format!("compare needs two images\n{USAGE}"))),
+        }
+    }
+}
+
+/// prints how the images differ, and fails the process if it is by more than the threshold
+fn run_compare(options: &CompareOptions) -> io::Result<()> {
+    let comparison = compare(
+        &Image::load(&options.reference)?,
+        &Image::load(&options.test)?,
+    )?;
+
+    println!("{comparison}");
+
+    if let Some(path) = &options.diff {
+        comparison.false_color().save(path)?;
+    }
+
+    let error = match options.metric.as_str() {
+        "mse" => comparison.mse,
+        "rmse" => comparison.rmse,
+        _ => comparison.flip,
+    };
+
+    if let Some(threshold) = options.threshold.filter(|threshold| error > *threshold) {
+        eprintln!(
+            "{} {error} is above the threshold {threshold}",
+            options.metric.to_uppercase()
+        );
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
 
 fn main() -> std::io::Result<()> {
+    let mut args = env::args().skip(1).peekable();
+
+    if args.peek().is_some_and(|arg| arg == "compare") {
+        return run_compare(&CompareOptions::parse(args.skip(1))?);
+    }
+
     let start = SystemTime::now();
+    let options = Options::parse(args)?;
+
+    // an unknown format fails before the render rather than after it
+    if let Some(path) = &options.output {
+        Format::from_path(path)?;
+    }
 
     // world
     let mut world = HittableList::new();
@@ -58,6 +289,16 @@ fn main() -> std::io::Result<()> {
 
     let world_arc = Arc::new(world);
 
+    // the denoiser is guided by the albedo and normals, written out only if asked for
+    let mut aovs = options.aovs.clone();
+    if options.denoise {
+        for aov in [Aov::Albedo, Aov::Normal] {
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+    }
+
     // camera
     let camera = Builder::new()
         .set_image_width(400)
@@ -70,9 +311,38 @@ fn main() -> std::io::Result<()> {
         .set_vup(&Vector3::new(0.0, 1.0, 0.0))
         .set_defocus_angle(0.5)
         .set_focus_dist(3.4)
+        .set_radiance_clamp(options.radiance_clamp)
+        .set_aovs(&aovs)
         .build();
 
-    camera.render(Arc::clone(&world_arc))?;
+    let mut layers = camera.render_layers(Arc::clone(&world_arc))?;
+
+    if options.reject_outliers {
+        layers.beauty = OutlierRejection::new().apply(&layers.beauty);
+    }
+
+    if options.denoise {
+        layers.beauty = Denoiser::new().denoise_layers(&layers)?;
+        layers.aovs.retain(|(aov, _)| options.aovs.contains(aov));
+    }
+
+    layers.beauty = options.effects.apply(&layers.beauty);
+
+    // formats for display get the curve, float formats keep everything past 1
+    let high_dynamic_range = match &options.output {
+        Some(path) => Format::from_path(path)?.is_high_dynamic_range(),
+        None => false,
+    };
+    layers.beauty = if high_dynamic_range {
+        options.tone_mapping.expose(&layers.beauty)
+    } else {
+        options.tone_mapping.apply(&layers.beauty)
+    };
+
+    match &options.output {
+        Some(path) => layers.save(path)?,
+        None => ppm::write_plain(&layers.beauty, &mut BufWriter::new(io::stdout().lock()))?,
+    }
 
     let now = SystemTime::now().duration_since(start).unwrap();
 
@@ -1,10 +1,17 @@
+//! The materials surfaces are made of.
+
+use std::hash::Hasher;
+
 use crate::color::Color;
 use crate::geometry::hittable::HitRecord;
 use crate::ray::Ray;
-use crate::util::random_double;
-use crate::vec3::{dot, random, reflect, refract, Vector3};
+use crate::util::{hash_f64s, random_double, PI};
+use crate::vec3::{dot, random_unit_vector, reflect, refract, Vector3};
 
+/// How a surface scatters and emits light.
 pub trait Material: Sync + Send {
+    /// whether the ray is scattered, setting `scattered` and the `attenuation` it carries
+    /// if so; absorbed rays return false
     fn scatter(
         &self,
         r_in: &Ray,
@@ -12,57 +19,117 @@ pub trait Material: Sync + Send {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool;
+
+    /// the light given off at the hit, none by default
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// the surface's own color, without lighting, as the albedo AOV shows it
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// whether `scatter` picks a single direction (mirror, glass),
+    /// in which case `bsdf` and `scattering_pdf` carry no information
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    /// `wo` points back along the incoming ray and `wi` along the scattered one,
+    /// both normalized
+    fn bsdf(&self, _rec: &HitRecord, _wo: &Vector3, _wi: &Vector3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// solid angle density with which `scatter` picks `wi`
+    fn scattering_pdf(&self, _rec: &HitRecord, _wo: &Vector3, _wi: &Vector3) -> f64 {
+        0.0
+    }
+
+    /// feeds the kind of material and its parameters into `state`, which tells scenes
+    /// apart (render checkpoints are only resumed with the scene they were made for). The
+    /// default only tells types apart; materials should feed in their parameters too.
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(std::any::type_name::<Self>().as_bytes());
+    }
 }
 
+/// An ideal diffuse surface.
 pub struct Lambertian {
     albedo: Color,
 }
 
 impl Lambertian {
+    /// reflecting `albedo` of the light
     pub fn new(albedo: &Color) -> Self {
-        Self {
-            albedo: albedo.clone(),
-        }
+        Self { albedo: *albedo }
     }
 }
 
 impl Material for Lambertian {
     fn scatter(
         &self,
-        r_in: &Ray,
+        _r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        let mut random_unit_vector = Vector3::new_default();
-
-        while let Ok(vector) = random().normalize() {
-            random_unit_vector = vector;
-            break;
-        }
-
-        let mut scatter_direction = rec.normal + random_unit_vector;
+        let mut scatter_direction = rec.normal + random_unit_vector();
 
         if scatter_direction.near_zero() {
-            scatter_direction = rec.normal.clone();
+            scatter_direction = rec.normal;
         }
 
         *scattered = Ray::new(&rec.p, &scatter_direction);
-        *attenuation = self.albedo.clone();
+        *attenuation = self.albedo;
 
         true
     }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    fn bsdf(&self, rec: &HitRecord, wo: &Vector3, wi: &Vector3) -> Color {
+        if dot(&rec.normal, wo) <= 0.0 || dot(&rec.normal, wi) <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        self.albedo * (1.0 / PI)
+    }
+
+    fn scattering_pdf(&self, rec: &HitRecord, _wo: &Vector3, wi: &Vector3) -> f64 {
+        let cosine = dot(&rec.normal, wi);
+
+        if cosine <= 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"lambertian");
+        hash_f64s(state, &[self.albedo.x(), self.albedo.y(), self.albedo.z()]);
+    }
 }
 
+/// A mirror, blurred by `fuzz` (between 0 and 1).
 pub struct Metal {
     albedo: Color,
     fuzz: f64,
 }
 
 impl Metal {
+    /// reflecting `albedo` of the light, blurred by `fuzz` of at most 1
     pub fn new(albedo: &Color, fuzz: f64) -> Self {
         Self {
-            albedo: albedo.clone(),
+            albedo: *albedo,
             fuzz: f64::min(fuzz, 1.0),
         }
     }
@@ -76,28 +143,36 @@ impl Material for Metal {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        let mut reflected = reflect(&r_in.direction(), &rec.normal);
-        let mut random_unit_vector = Vector3::new_default();
+        let mut reflected = reflect(r_in.direction(), &rec.normal);
 
-        while let Ok(vec) = random().normalize() {
-            random_unit_vector = vec;
-            break;
-        }
-
-        reflected = reflected.normalize().unwrap() + (self.fuzz * random_unit_vector);
+        reflected = reflected.normalize().unwrap() + (self.fuzz * random_unit_vector());
 
         *scattered = Ray::new(&rec.p, &reflected);
-        *attenuation = self.albedo.clone();
+        *attenuation = self.albedo;
 
         dot(scattered.direction(), &rec.normal) > 0.0
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"metal");
+        hash_f64s(
+            state,
+            &[self.albedo.x(), self.albedo.y(), self.albedo.z(), self.fuzz],
+        );
+    }
 }
 
+/// Clear glass, water and the like, refracting and reflecting by its index of refraction.
 pub struct Dielectric {
     refraction_index: f64,
 }
 
 impl Dielectric {
+    /// of index `refraction_index`, relative to the medium around it
     pub fn new(refraction_index: f64) -> Self {
         Self { refraction_index }
     }
@@ -140,4 +215,62 @@ impl Material for Dielectric {
 
         true
     }
+
+    // clear glass lets everything through
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"dielectric");
+        hash_f64s(state, &[self.refraction_index]);
+    }
+}
+
+/// A surface giving off light evenly in every direction, scattering none.
+pub struct DiffuseLight {
+    emit: Color,
+}
+
+impl DiffuseLight {
+    /// giving off `emit` of radiance
+    pub fn new(emit: &Color) -> Self {
+        Self { emit: *emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _attenuation: &mut Color,
+        _scattered: &mut Ray,
+    ) -> bool {
+        false
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        if rec.front_face {
+            self.emit
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    // the color of the light, its brightness aside
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        let brightest = f64::max(self.emit.x(), f64::max(self.emit.y(), self.emit.z()));
+
+        if brightest > 1.0 {
+            self.emit * (1.0 / brightest)
+        } else {
+            self.emit
+        }
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(b"diffuse light");
+        hash_f64s(state, &[self.emit.x(), self.emit.y(), self.emit.z()]);
+    }
 }
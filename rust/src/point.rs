@@ -1,3 +1,6 @@
+//! Points in space.
+
 use crate::vec3::Vector3;
 
+/// A position in space.
 pub type Point3 = Vector3;
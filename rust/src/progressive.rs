@@ -0,0 +1,107 @@
+//! Progressive rendering: the whole image a sample at a time, with snapshots along the
+//! way.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Renders the image one sample per pixel at a time, writing what has accumulated so far
+/// to `snapshot`, in the format its extension names, as it goes: every so many passes or
+/// seconds (every pass if neither is set). The render stops at the camera's samples per
+/// pixel, or earlier once the time budget is spent or the noise falls below the
+/// threshold. Adaptive sampling is ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progressive {
+    snapshot: PathBuf,
+    snapshot_passes: Option<u32>,
+    snapshot_interval: Option<Duration>,
+    time_budget: Option<Duration>,
+    noise_threshold: Option<f64>,
+}
+
+impl Progressive {
+    /// writing a snapshot to `snapshot` every pass, with no time budget or noise threshold
+    pub fn new(snapshot: &str) -> Self {
+        Self {
+            snapshot: PathBuf::from(snapshot),
+            snapshot_passes: None,
+            snapshot_interval: None,
+            time_budget: None,
+            noise_threshold: None,
+        }
+    }
+
+    /// writes a snapshot every `passes` passes
+    pub fn set_snapshot_passes(&mut self, passes: u32) -> &mut Self {
+        self.snapshot_passes = Some(u32::max(passes, 1));
+        self
+    }
+
+    /// writes a snapshot after a pass when `interval` has gone by since the last
+    pub fn set_snapshot_interval(&mut self, interval: Duration) -> &mut Self {
+        self.snapshot_interval = Some(interval);
+        self
+    }
+
+    /// stops after the first pass that ends past `budget` of rendering
+    pub fn set_time_budget(&mut self, budget: Duration) -> &mut Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// stops once the standard error of the pixels, relative to their brightness and
+    /// averaged over the image, is at most `relative_error`
+    pub fn set_noise_threshold(&mut self, relative_error: f64) -> &mut Self {
+        self.noise_threshold = Some(relative_error);
+        self
+    }
+
+    pub(crate) fn snapshot_path(&self) -> &Path {
+        &self.snapshot
+    }
+
+    /// whether to write a snapshot after `passes` passes, the last one `since` ago
+    pub(crate) fn snapshot_due(&self, passes: u32, since: Duration) -> bool {
+        match (self.snapshot_passes, self.snapshot_interval) {
+            (None, None) => true,
+            (every, interval) => {
+                every.is_some_and(|every| passes.is_multiple_of(every))
+                    || interval.is_some_and(|interval| since >= interval)
+            }
+        }
+    }
+
+    /// whether to stop `elapsed` into the render, `noise` measuring the image so far
+    pub(crate) fn should_stop(&self, elapsed: Duration, noise: impl FnOnce() -> f64) -> bool {
+        self.time_budget.is_some_and(|budget| elapsed >= budget)
+            || self
+                .noise_threshold
+                .is_some_and(|threshold| noise() <= threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_and_stops_follow_the_settings() {
+        let mut progressive = Progressive::new("snapshot.ppm");
+        assert!(progressive.snapshot_due(3, Duration::ZERO));
+        assert!(!progressive.should_stop(Duration::from_secs(3600), || 0.0));
+
+        progressive
+            .set_snapshot_passes(4)
+            .set_time_budget(Duration::from_secs(10))
+            .set_noise_threshold(0.05);
+
+        assert!(!progressive.snapshot_due(3, Duration::ZERO));
+        assert!(progressive.snapshot_due(8, Duration::ZERO));
+        assert!(!progressive.should_stop(Duration::from_secs(1), || 0.1));
+        assert!(progressive.should_stop(Duration::from_secs(1), || 0.01));
+        assert!(progressive.should_stop(Duration::from_secs(11), || 0.1));
+
+        progressive.set_snapshot_interval(Duration::from_secs(5));
+        assert!(progressive.snapshot_due(3, Duration::from_secs(6)));
+        assert!(!progressive.snapshot_due(3, Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,142 @@
+//! How the camera maps the image onto rays.
+
+use crate::util::{degrees_to_radians, PI};
+
+/// How a fisheye lens spreads the angle off its axis over the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// distance from the image center grows linearly with the angle off the axis
+    Equidistant,
+    /// equal solid angles cover equal image areas
+    Equisolid,
+}
+
+/// How the camera maps the image onto rays. Only the perspective projection uses the
+/// vertical field of view, and only it can be connected to by light tracing (BDPT).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// a pinhole or thin lens, covering the vertical field of view
+    Perspective,
+    /// parallel rays covering `view_width` world units across the image
+    Orthographic {
+        /// in world units
+        view_width: f64,
+    },
+    /// a circular image, as wide as the shorter side, covering `fov` degrees across
+    Fisheye {
+        /// how the angle off the axis spreads over the circle
+        mapping: FisheyeMapping,
+        /// in degrees
+        fov: f64,
+    },
+    /// the whole sphere of directions: longitude across, latitude down
+    Equirectangular,
+}
+
+impl Projection {
+    /// direction through the point (x, y) of an image `width` x `height`, in camera space:
+    /// (right, up, forward) components; None where the image shows nothing. Only for the
+    /// projections that map the image to directions alone
+    pub(crate) fn direction(&self, x: f64, y: f64, width: f64, height: f64) -> Option<[f64; 3]> {
+        match *self {
+            Projection::Fisheye { mapping, fov } => {
+                let half = f64::min(width, height) / 2.0;
+                let px = (x - width / 2.0) / half;
+                let py = (height / 2.0 - y) / half;
+                let r = f64::sqrt(px * px + py * py);
+
+                if r > 1.0 {
+                    return None;
+                }
+
+                let theta_max = degrees_to_radians(fov) / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * theta_max,
+                    FisheyeMapping::Equisolid => {
+                        2.0 * f64::asin(f64::min(r * f64::sin(theta_max / 2.0), 1.0))
+                    }
+                };
+
+                if r == 0.0 {
+                    return Some([0.0, 0.0, 1.0]);
+                }
+
+                let sin_theta = f64::sin(theta);
+
+                Some([sin_theta * px / r, sin_theta * py / r, f64::cos(theta)])
+            }
+            Projection::Equirectangular => {
+                let (longitude, latitude) = Self::longitude_latitude(x, y, width, height);
+
+                Some([
+                    f64::cos(latitude) * f64::sin(longitude),
+                    f64::sin(latitude),
+                    f64::cos(latitude) * f64::cos(longitude),
+                ])
+            }
+            Projection::Perspective | Projection::Orthographic { .. } => None,
+        }
+    }
+
+    /// longitude in [-π, π), zero straight ahead and growing to the right, and latitude in
+    /// [-π/2, π/2], growing upward
+    pub(crate) fn longitude_latitude(x: f64, y: f64, width: f64, height: f64) -> (f64, f64) {
+        ((x / width - 0.5) * 2.0 * PI, (0.5 - y / height) * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: [f64; 3], b: [f64; 3]) {
+        for k in 0..3 {
+            assert!(f64::abs(a[k] - b[k]) < 1e-9, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn angular_projections_map_the_image_to_directions() {
+        let fisheye = Projection::Fisheye {
+            mapping: FisheyeMapping::Equidistant,
+            fov: 180.0,
+        };
+
+        assert_close(
+            fisheye.direction(50.0, 50.0, 100.0, 100.0).unwrap(),
+            [0.0, 0.0, 1.0],
+        );
+        assert_close(
+            fisheye.direction(100.0, 50.0, 100.0, 100.0).unwrap(),
+            [1.0, 0.0, 0.0],
+        );
+        assert_eq!(fisheye.direction(0.0, 0.0, 100.0, 100.0), None);
+
+        // halfway out, the equisolid mapping bends less than the equidistant one
+        let equisolid = Projection::Fisheye {
+            mapping: FisheyeMapping::Equisolid,
+            fov: 180.0,
+        };
+        let forward = equisolid.direction(75.0, 50.0, 100.0, 100.0).unwrap()[2];
+        assert!(forward > f64::cos(PI / 4.0) && forward < 1.0);
+
+        let panorama = Projection::Equirectangular;
+
+        assert_close(
+            panorama.direction(100.0, 50.0, 200.0, 100.0).unwrap(),
+            [0.0, 0.0, 1.0],
+        );
+        assert_close(
+            panorama.direction(150.0, 50.0, 200.0, 100.0).unwrap(),
+            [1.0, 0.0, 0.0],
+        );
+        assert_close(
+            panorama.direction(0.0, 50.0, 200.0, 100.0).unwrap(),
+            [0.0, 0.0, -1.0],
+        );
+        assert_close(
+            panorama.direction(100.0, 0.0, 200.0, 100.0).unwrap(),
+            [0.0, 1.0, 0.0],
+        );
+    }
+}
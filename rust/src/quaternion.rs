@@ -0,0 +1,287 @@
+//! Quaternions, for turning the camera smoothly between orientations.
+
+use std::ops::{Mul, Neg};
+
+use crate::vec3::{cross, Vector3};
+
+/// A rotation as a unit quaternion w + xi + yj + zk, which can be blended smoothly
+/// without the gimbal lock and wobble of interpolating angles or matrices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Quaternion {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+}
+
+impl Neg for Quaternion {
+    type Output = Quaternion;
+
+    fn neg(self) -> Self::Output {
+        Quaternion {
+            w: -self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl Quaternion {
+    /// no rotation
+    pub fn identity() -> Self {
+        Self {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    /// the rotation taking the x, y and z axes to `right`, `up` and `back`, which must be
+    /// orthonormal and right handed
+    pub fn from_basis(right: &Vector3, up: &Vector3, back: &Vector3) -> Self {
+        // the matrix has the basis vectors as columns
+        let (m00, m01, m02) = (right.x(), up.x(), back.x());
+        let (m10, m11, m12) = (right.y(), up.y(), back.y());
+        let (m20, m21, m22) = (right.z(), up.z(), back.z());
+        let trace = m00 + m11 + m22;
+
+        // divide by the largest component, which keeps the result accurate
+        let q = if trace > 0.0 {
+            let s = 2.0 * f64::sqrt(1.0 + trace);
+            Self {
+                w: s / 4.0,
+                x: (m21 - m12) / s,
+                y: (m02 - m20) / s,
+                z: (m10 - m01) / s,
+            }
+        } else if m00 > m11 && m00 > m22 {
+            let s = 2.0 * f64::sqrt(1.0 + m00 - m11 - m22);
+            Self {
+                w: (m21 - m12) / s,
+                x: s / 4.0,
+                y: (m01 + m10) / s,
+                z: (m02 + m20) / s,
+            }
+        } else if m11 > m22 {
+            let s = 2.0 * f64::sqrt(1.0 + m11 - m00 - m22);
+            Self {
+                w: (m02 - m20) / s,
+                x: (m01 + m10) / s,
+                y: s / 4.0,
+                z: (m12 + m21) / s,
+            }
+        } else {
+            let s = 2.0 * f64::sqrt(1.0 + m22 - m00 - m11);
+            Self {
+                w: (m10 - m01) / s,
+                x: (m02 + m20) / s,
+                y: (m12 + m21) / s,
+                z: s / 4.0,
+            }
+        };
+
+        q.normalize()
+    }
+
+    /// `v` rotated by this unit quaternion
+    pub fn rotate(&self, v: &Vector3) -> Vector3 {
+        // v + 2w(u × v) + 2u × (u × v), with u the vector part
+        let u = Vector3::new(self.x, self.y, self.z);
+        let t = 2.0 * cross(&u, v);
+
+        *v + self.w * t + cross(&u, &t)
+    }
+
+    /// the four dimensional dot product
+    pub fn dot(&self, other: &Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// the inverse rotation of a unit quaternion
+    pub fn conjugate(&self) -> Self {
+        Self {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    /// scaled to unit length
+    pub fn normalize(&self) -> Self {
+        let length = f64::sqrt(self.dot(self));
+
+        Self {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    /// the pure quaternion whose exponential this is: the rotation axis scaled by half the angle
+    fn log(&self) -> Self {
+        let sin = f64::sqrt(self.x * self.x + self.y * self.y + self.z * self.z);
+        let scale = if sin < 1e-12 {
+            1.0
+        } else {
+            f64::atan2(sin, self.w) / sin
+        };
+
+        Self {
+            w: 0.0,
+            x: self.x * scale,
+            y: self.y * scale,
+            z: self.z * scale,
+        }
+    }
+
+    fn exp(&self) -> Self {
+        let angle = f64::sqrt(self.x * self.x + self.y * self.y + self.z * self.z);
+        let scale = if angle < 1e-12 {
+            1.0
+        } else {
+            f64::sin(angle) / angle
+        };
+
+        Self {
+            w: f64::cos(angle),
+            x: self.x * scale,
+            y: self.y * scale,
+            z: self.z * scale,
+        }
+    }
+
+    /// spherical linear interpolation, at constant angular speed along the shorter arc
+    pub fn slerp(a: &Quaternion, b: &Quaternion, t: f64) -> Self {
+        let mut b = *b;
+        let mut cos = a.dot(&b);
+
+        if cos < 0.0 {
+            b = -b;
+            cos = -cos;
+        }
+
+        // nearly parallel: the arc is straight enough
+        let (wa, wb) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let angle = f64::acos(cos);
+            let sin = f64::sin(angle);
+
+            (f64::sin((1.0 - t) * angle) / sin, f64::sin(t * angle) / sin)
+        };
+
+        Self {
+            w: wa * a.w + wb * b.w,
+            x: wa * a.x + wb * b.x,
+            y: wa * a.y + wb * b.y,
+            z: wa * a.z + wb * b.z,
+        }
+        .normalize()
+    }
+
+    /// Spherical cubic interpolation between `q1` and `q2` with `q0` and `q3` as their
+    /// neighbours, the rotational counterpart of a Catmull-Rom spline: it passes through
+    /// every key and turns smoothly across them. The keys should lie in one hemisphere.
+    pub fn squad(
+        q0: &Quaternion,
+        q1: &Quaternion,
+        q2: &Quaternion,
+        q3: &Quaternion,
+        t: f64,
+    ) -> Self {
+        let s1 = Self::control_point(q0, q1, q2);
+        let s2 = Self::control_point(q1, q2, q3);
+
+        Self::slerp_unchecked(
+            &Self::slerp_unchecked(q1, q2, t),
+            &Self::slerp_unchecked(&s1, &s2, t),
+            2.0 * t * (1.0 - t),
+        )
+    }
+
+    /// the inner control point at `current` that makes the curve's tangent there the
+    /// average of the directions to its neighbours
+    fn control_point(previous: &Quaternion, current: &Quaternion, next: &Quaternion) -> Self {
+        let inverse = current.conjugate();
+        let to_next = (inverse * *next).log();
+        let to_previous = (inverse * *previous).log();
+        let tangent = Self {
+            w: 0.0,
+            x: -(to_next.x + to_previous.x) / 4.0,
+            y: -(to_next.y + to_previous.y) / 4.0,
+            z: -(to_next.z + to_previous.z) / 4.0,
+        };
+
+        (*current * tangent.exp()).normalize()
+    }
+
+    /// slerp without taking the shorter arc, which squad needs to stay continuous
+    fn slerp_unchecked(a: &Quaternion, b: &Quaternion, t: f64) -> Self {
+        (*a * (a.conjugate() * *b).power(t)).normalize()
+    }
+
+    fn power(&self, t: f64) -> Self {
+        let log = self.log();
+
+        Self {
+            w: 0.0,
+            x: log.x * t,
+            y: log.y * t,
+            z: log.z * t,
+        }
+        .exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Vector3, b: &Vector3) {
+        assert!((*a - *b).length() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn bases_survive_the_round_trip() {
+        let right = Vector3::new(0.0, 0.0, -1.0);
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let back = Vector3::new(1.0, 0.0, 0.0);
+        let q = Quaternion::from_basis(&right, &up, &back);
+
+        assert_close(&q.rotate(&Vector3::new(1.0, 0.0, 0.0)), &right);
+        assert_close(&q.rotate(&Vector3::new(0.0, 1.0, 0.0)), &up);
+        assert_close(&q.rotate(&Vector3::new(0.0, 0.0, 1.0)), &back);
+
+        // halfway from no rotation to a quarter turn about y is an eighth turn
+        let half = Quaternion::slerp(&Quaternion::identity(), &q, 0.5);
+        let diagonal = f64::sqrt(0.5);
+        assert_close(
+            &half.rotate(&Vector3::new(1.0, 0.0, 0.0)),
+            &Vector3::new(diagonal, 0.0, -diagonal),
+        );
+
+        // squad passes through its keys
+        let keys = [Quaternion::identity(), q, half, q];
+        let start = Quaternion::squad(&keys[0], &keys[1], &keys[2], &keys[3], 0.0);
+        let end = Quaternion::squad(&keys[0], &keys[1], &keys[2], &keys[3], 1.0);
+        assert!(f64::abs(start.dot(&q)) > 1.0 - 1e-9);
+        assert!(f64::abs(end.dot(&half)) > 1.0 - 1e-9);
+    }
+}
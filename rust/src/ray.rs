@@ -1,19 +1,24 @@
+//! Rays.
+
 use crate::point::Point3;
 use crate::vec3::Vector3;
 
+/// A half line: the points `origin + t * direction` for t > 0.
 pub struct Ray {
     orig: Point3,
     dir: Vector3,
 }
 
 impl Ray {
+    /// the ray from `origin` along `direction`, which needn't be a unit vector
     pub fn new(origin: &Point3, direction: &Vector3) -> Self {
         Self {
-            orig: origin.clone(),
-            dir: direction.clone(),
+            orig: *origin,
+            dir: *direction,
         }
     }
 
+    /// the ray from the origin with no direction
     pub fn new_default() -> Self {
         Self {
             orig: Point3::new_default(),
@@ -21,14 +26,17 @@ impl Ray {
         }
     }
 
+    /// where the ray starts
     pub fn origin(&self) -> &Point3 {
         &self.orig
     }
 
+    /// where the ray goes, not necessarily a unit vector
     pub fn direction(&self) -> &Vector3 {
         &self.dir
     }
 
+    /// the point at parameter `t` along the ray
     pub fn at(&self, t: f64) -> Point3 {
         self.orig + t * self.dir
     }
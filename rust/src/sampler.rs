@@ -0,0 +1,432 @@
+//! Samplers, which pick the random numbers of every camera sample.
+
+use std::hash::Hasher;
+use std::sync::{Arc, OnceLock};
+
+use crate::util::{mix_seed, Pcg32, SampleSource};
+
+/// which sample of which pixel is being taken
+#[derive(Clone, Copy)]
+pub struct PixelSample {
+    /// the pixel's column
+    pub i: u32,
+    /// the pixel's row
+    pub j: u32,
+    /// the number of the sample within the pixel, counting over the whole render
+    pub index: u32,
+    /// the samples the pixel gets over the whole render
+    pub count: u32,
+    /// decorrelates renders from each other
+    pub seed: u64,
+}
+
+impl PixelSample {
+    fn hash(&self, dimension: u32, salt: u64) -> u64 {
+        mix_seed(&[
+            self.seed,
+            self.i as u64,
+            self.j as u64,
+            dimension as u64,
+            salt,
+        ])
+    }
+}
+
+/// Supplies the numbers a pixel sample is built from, one dimension after another: the
+/// camera takes the pixel position and the lens position first, then materials and light
+/// sampling draw what they need. Every value is a function of the pixel sample and the
+/// dimension, so samplers can spread the samples of a pixel evenly over each dimension.
+pub trait Sampler: Sync + Send {
+    /// a number in [0, 1)
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64;
+
+    /// a point in [0, 1)², taking up dimensions `dimension` and `dimension + 1`
+    fn get_2d(&self, sample: &PixelSample, dimension: u32) -> (f64, f64) {
+        (
+            self.get_1d(sample, dimension),
+            self.get_1d(sample, dimension + 1),
+        )
+    }
+
+    /// feeds the kind of sampler and any settings it has into `state`, for the camera's
+    /// checkpoint fingerprint; the default only feeds in the type
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        state.write(std::any::type_name::<Self>().as_bytes());
+    }
+}
+
+/// hands the dimensions of one pixel sample to `random_double` in order
+pub struct PixelSampleSource {
+    sampler: Arc<dyn Sampler>,
+    sample: PixelSample,
+    dimension: u32,
+}
+
+impl PixelSampleSource {
+    /// the dimensions of `sample`, drawn from `sampler` starting at the first
+    pub fn new(sampler: Arc<dyn Sampler>, sample: PixelSample) -> Self {
+        Self {
+            sampler,
+            sample,
+            dimension: 0,
+        }
+    }
+}
+
+impl SampleSource for PixelSampleSource {
+    fn next(&mut self) -> f64 {
+        let value = self.sampler.get_1d(&self.sample, self.dimension);
+        self.dimension += 1;
+
+        value
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let value = self.sampler.get_2d(&self.sample, self.dimension);
+        self.dimension += 2;
+
+        value
+    }
+}
+
+/// plain uniform random numbers
+pub struct Independent;
+
+impl Sampler for Independent {
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64 {
+        Pcg32::new(sample.hash(dimension, sample.index as u64), 0).next()
+    }
+}
+
+/// Jittered stratification: the samples of a pixel fall into distinct strata of each
+/// dimension (of each pair of dimensions in 2D), visited in a different random order
+/// per dimension so the dimensions don't correlate.
+pub struct Stratified;
+
+impl Stratified {
+    fn jitter(sample: &PixelSample, dimension: u32) -> f64 {
+        Pcg32::new(sample.hash(dimension, sample.index as u64), 1).next()
+    }
+}
+
+impl Sampler for Stratified {
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64 {
+        let count = u32::max(sample.count, 1);
+        let stratum = permute(
+            sample.index % count,
+            count,
+            sample.hash(dimension, u64::MAX) as u32,
+        );
+
+        (stratum as f64 + Self::jitter(sample, dimension)) / count as f64
+    }
+
+    fn get_2d(&self, sample: &PixelSample, dimension: u32) -> (f64, f64) {
+        // the smallest square grid with a cell for every sample
+        let side = f64::ceil(f64::sqrt(u32::max(sample.count, 1) as f64)) as u32;
+        let cells = side * side;
+        let cell = permute(
+            sample.index % cells,
+            cells,
+            sample.hash(dimension, u64::MAX) as u32,
+        );
+
+        (
+            ((cell % side) as f64 + Self::jitter(sample, dimension)) / side as f64,
+            ((cell / side) as f64 + Self::jitter(sample, dimension + 1)) / side as f64,
+        )
+    }
+}
+
+/// The Halton sequence, dimension d being the radical inverse in the d-th prime base,
+/// with a random toroidal shift (Cranley-Patterson rotation) per pixel and dimension.
+/// Dimensions past the last prime in the table fall back to independent numbers.
+pub struct Halton;
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+impl Sampler for Halton {
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64 {
+        let Some(&base) = PRIMES.get(dimension as usize) else {
+            return Independent.get_1d(sample, dimension);
+        };
+
+        let shift = Pcg32::new(sample.hash(dimension, u64::MAX), 2).next();
+
+        wrap(radical_inverse(base, sample.index) + shift)
+    }
+}
+
+/// Owen-scrambled Sobol points (Burley, "Practical hash-based Owen scrambling"). Every 1D
+/// or 2D request takes the first one or two Sobol dimensions, with the sample order
+/// shuffled and the digits scrambled by hashes of the pixel and dimension, so any number
+/// of dimensions is well stratified without a table of direction numbers.
+pub struct Sobol;
+
+impl Sampler for Sobol {
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64 {
+        let index = nested_uniform_scramble(sample.index, sample.hash(dimension, 0) as u32);
+
+        to_unit(nested_uniform_scramble(
+            sobol(index, 0),
+            sample.hash(dimension, 1) as u32,
+        ))
+    }
+
+    fn get_2d(&self, sample: &PixelSample, dimension: u32) -> (f64, f64) {
+        let index = nested_uniform_scramble(sample.index, sample.hash(dimension, 0) as u32);
+
+        (
+            to_unit(nested_uniform_scramble(
+                sobol(index, 0),
+                sample.hash(dimension, 1) as u32,
+            )),
+            to_unit(nested_uniform_scramble(
+                sobol(index, 1),
+                sample.hash(dimension, 2) as u32,
+            )),
+        )
+    }
+}
+
+/// Sobol points shared by all pixels and shifted per pixel by a blue noise texture (Heitz
+/// and Belcour), so the error left at low sample counts is spread like blue noise rather
+/// than white noise and reads as finer grain. Each dimension looks the texture up at its
+/// own offset.
+pub struct BlueNoise;
+
+const BLUE_NOISE_SIZE: u32 = 64;
+
+impl BlueNoise {
+    fn shift(sample: &PixelSample, dimension: u32) -> f64 {
+        let offset = mix_seed(&[sample.seed, dimension as u64]);
+        let x = sample.i.wrapping_add(offset as u32) % BLUE_NOISE_SIZE;
+        let y = sample.j.wrapping_add((offset >> 32) as u32) % BLUE_NOISE_SIZE;
+
+        blue_noise_texture()[(y * BLUE_NOISE_SIZE + x) as usize]
+    }
+
+    fn index(sample: &PixelSample, dimension: u32) -> u32 {
+        nested_uniform_scramble(
+            sample.index,
+            mix_seed(&[sample.seed, dimension as u64]) as u32,
+        )
+    }
+}
+
+impl Sampler for BlueNoise {
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64 {
+        let index = Self::index(sample, dimension);
+
+        wrap(to_unit(sobol(index, 0)) + Self::shift(sample, dimension))
+    }
+
+    fn get_2d(&self, sample: &PixelSample, dimension: u32) -> (f64, f64) {
+        let index = Self::index(sample, dimension);
+
+        (
+            wrap(to_unit(sobol(index, 0)) + Self::shift(sample, dimension)),
+            wrap(to_unit(sobol(index, 1)) + Self::shift(sample, dimension + 1)),
+        )
+    }
+}
+
+/// A tileable blue noise texture made with a simplified void-and-cluster method (Ulichney):
+/// texels are switched on one at a time, each in the largest void left by the previous
+/// ones, and a texel's value is its rank in that order.
+fn blue_noise_texture() -> &'static [f64] {
+    static TEXTURE: OnceLock<Vec<f64>> = OnceLock::new();
+
+    TEXTURE.get_or_init(|| {
+        let size = BLUE_NOISE_SIZE as usize;
+        let texels = size * size;
+        let sigma = 1.5;
+
+        // energy a texel adds at every toroidal offset
+        let kernel: Vec<f64> = (0..texels)
+            .map(|index| {
+                let wrap_distance = |d: usize| usize::min(d, size - d) as f64;
+                let (dx, dy) = (wrap_distance(index % size), wrap_distance(index / size));
+
+                f64::exp(-(dx * dx + dy * dy) / (2.0 * sigma * sigma))
+            })
+            .collect();
+
+        let mut energy = vec![0.0_f64; texels];
+        let mut rank = vec![usize::MAX; texels];
+
+        for order in 0..texels {
+            let void = (0..texels)
+                .filter(|&index| rank[index] == usize::MAX)
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap();
+
+            rank[void] = order;
+
+            let (vx, vy) = (void % size, void / size);
+
+            for (index, value) in energy.iter_mut().enumerate() {
+                let dx = (index % size + size - vx) % size;
+                let dy = (index / size + size - vy) % size;
+
+                *value += kernel[dy * size + dx];
+            }
+        }
+
+        rank.iter()
+            .map(|&order| (order as f64 + 0.5) / texels as f64)
+            .collect()
+    })
+}
+
+/// the first two dimensions of the Sobol sequence, as 32 bit fractions
+fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+
+    // direction numbers of x + 1: v_0 = 1/2, v_k = v_(k-1) ^ (v_(k-1) >> 1)
+    let mut value = 0;
+    let mut direction = 1u32 << 31;
+    let mut index = index;
+
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= direction;
+        }
+
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    value
+}
+
+/// Laine and Karras' hash, which only lets lower bits affect higher ones
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+
+    x
+}
+
+/// a random permutation of the binary digits that keeps every dyadic interval together
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// where `index` goes under a random permutation of 0..count (Kensler, "Correlated
+/// Multi-Jittered Sampling"), cycle walking past the next power of two
+fn permute(index: u32, count: u32, seed: u32) -> u32 {
+    if count <= 1 {
+        return 0;
+    }
+
+    let mask = u32::MAX >> (count - 1).leading_zeros();
+    let mut i = index;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+
+        if i < count {
+            return (i.wrapping_add(seed)) % count;
+        }
+    }
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut digits = 0.0;
+    let mut scale = 1.0;
+
+    while index > 0 {
+        scale *= inverse_base;
+        digits += (index % base) as f64 * scale;
+        index /= base;
+    }
+
+    digits
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 * (1.0 / (1u64 << 32) as f64)
+}
+
+/// back into [0, 1) after a toroidal shift
+fn wrap(x: f64) -> f64 {
+    let x = x - f64::floor(x);
+
+    if x >= 1.0 {
+        0.0
+    } else {
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every cell of an n x n grid holds exactly one of n² samples
+    fn covers_grid(sampler: &dyn Sampler, n: u32) -> bool {
+        let mut hits = vec![0; (n * n) as usize];
+
+        for index in 0..n * n {
+            let sample = PixelSample {
+                i: 3,
+                j: 5,
+                index,
+                count: n * n,
+                seed: 11,
+            };
+            let (x, y) = sampler.get_2d(&sample, 4);
+            let cell = (y * n as f64) as u32 * n + (x * n as f64) as u32;
+
+            hits[cell as usize] += 1;
+        }
+
+        hits.iter().all(|&count| count == 1)
+    }
+
+    #[test]
+    fn stratified_samplers_fill_every_stratum() {
+        assert!(covers_grid(&Stratified, 8));
+        assert!(covers_grid(&Sobol, 8));
+    }
+
+    #[test]
+    fn kensler_permutation_is_a_permutation() {
+        for count in [1, 2, 7, 64, 100] {
+            let mut seen = vec![false; count as usize];
+
+            for index in 0..count {
+                seen[permute(index, count, 0xdeadbeef) as usize] = true;
+            }
+
+            assert!(seen.iter().all(|&seen| seen));
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A rectangle of pixels rendered as one unit of work, `index` giving its place in
+/// scanline order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Tile {
+    pub index: usize,
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32, // exclusive
+    pub y1: u32, // exclusive
+}
+
+impl Tile {
+    /// covers the image with tiles of `size` x `size` pixels, smaller along the right and
+    /// bottom edges
+    pub(crate) fn split(width: u32, height: u32, size: u32) -> Vec<Tile> {
+        let size = u32::max(size, 1);
+        let mut tiles = vec![];
+
+        for y0 in (0..height).step_by(size as usize) {
+            for x0 in (0..width).step_by(size as usize) {
+                tiles.push(Tile {
+                    index: tiles.len(),
+                    x0,
+                    y0,
+                    x1: u32::min(x0 + size, width),
+                    y1: u32::min(y0 + size, height),
+                });
+            }
+        }
+
+        tiles
+    }
+
+    /// the pixels of the tile, row by row
+    pub(crate) fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.y0..self.y1).flat_map(move |j| (self.x0..self.x1).map(move |i| (i, j)))
+    }
+}
+
+/// Hands out work to a fixed set of workers. Every worker starts with a contiguous share
+/// of the items and takes from its front; a worker that runs out steals from the back of
+/// another's share, so the load evens out while workers mostly stay on neighbouring tiles.
+pub(crate) struct WorkStealingQueue<T> {
+    queues: Vec<Mutex<VecDeque<T>>>,
+}
+
+impl<T> WorkStealingQueue<T> {
+    pub(crate) fn new(items: Vec<T>, workers: usize) -> Self {
+        let workers = usize::max(workers, 1);
+        let per_worker = items.len().div_ceil(workers);
+        let mut queues: Vec<VecDeque<T>> = (0..workers).map(|_| VecDeque::new()).collect();
+
+        for (k, item) in items.into_iter().enumerate() {
+            queues[k / usize::max(per_worker, 1)].push_back(item);
+        }
+
+        Self {
+            queues: queues.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    /// the next item for `worker`, None once every queue is empty
+    pub(crate) fn next(&self, worker: usize) -> Option<T> {
+        let count = self.queues.len();
+
+        if let Some(item) = self.queues[worker % count].lock().unwrap().pop_front() {
+            return Some(item);
+        }
+
+        (1..count).find_map(|offset| {
+            self.queues[(worker + offset) % count]
+                .lock()
+                .unwrap()
+                .pop_back()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread;
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        let tiles = Tile::split(37, 20, 16);
+        let mut covered = vec![0; 37 * 20];
+
+        assert_eq!(tiles.len(), 3 * 2);
+
+        for (k, tile) in tiles.iter().enumerate() {
+            assert_eq!(tile.index, k);
+
+            for (i, j) in tile.pixels() {
+                covered[(j * 37 + i) as usize] += 1;
+            }
+        }
+
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn every_item_is_handed_out_exactly_once() {
+        let queue = WorkStealingQueue::new((0..1000).collect(), 4);
+        let seen: Vec<AtomicU32> = (0..1000).map(|_| AtomicU32::new(0)).collect();
+
+        thread::scope(|scope| {
+            for worker in 0..4 {
+                let (queue, seen) = (&queue, &seen);
+
+                scope.spawn(move || {
+                    // the first worker is slow, so the others have to steal from it
+                    while let Some(item) = queue.next(worker) {
+                        if worker == 0 {
+                            thread::yield_now();
+                        }
+
+                        seen[item as usize].fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert!(seen.iter().all(|count| count.load(Ordering::Relaxed) == 1));
+    }
+}
@@ -0,0 +1,67 @@
+//! Stereoscopic rendering: one image per eye, packed into one.
+
+/// How the images of the two eyes share one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoPacking {
+    /// left eye on the left half, the image twice as wide
+    SideBySide,
+    /// left eye on the top half, the image twice as tall
+    TopBottom,
+}
+
+/// Renders the image once per eye, the eyes `interocular_distance` apart along the
+/// camera's right axis, and packs both into one image. Perspective eyes share the image
+/// plane at `convergence_distance` (off-axis stereo), so objects there show no parallax;
+/// an infinite distance keeps the eyes parallel. With the equirectangular projection
+/// this gives omni-directional stereo (ODS): every longitude gets its own eye positions
+/// on a circle, which ignores the convergence distance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stereo {
+    interocular_distance: f64,
+    convergence_distance: f64,
+    packing: StereoPacking,
+}
+
+impl Stereo {
+    /// eyes `interocular_distance` apart, converging at `convergence_distance` (infinite for
+    /// parallel eyes), packed as `packing` says
+    pub fn new(
+        interocular_distance: f64,
+        convergence_distance: f64,
+        packing: StereoPacking,
+    ) -> Self {
+        Self {
+            interocular_distance,
+            convergence_distance,
+            packing,
+        }
+    }
+
+    /// offsets of the left and right eye along the camera's right axis
+    pub(crate) fn eye_offsets(&self) -> [f64; 2] {
+        let half = self.interocular_distance / 2.0;
+
+        [-half, half]
+    }
+
+    pub(crate) fn convergence_distance(&self) -> f64 {
+        self.convergence_distance
+    }
+
+    /// size of the packed image for eye images of `width` x `height`
+    pub(crate) fn packed_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.packing {
+            StereoPacking::SideBySide => (2 * width, height),
+            StereoPacking::TopBottom => (width, 2 * height),
+        }
+    }
+
+    /// which eye pixel (i, j) of the packed image belongs to (0 for left), and where in
+    /// that eye's image it is
+    pub(crate) fn locate(&self, i: u32, j: u32, width: u32, height: u32) -> (usize, u32, u32) {
+        match self.packing {
+            StereoPacking::SideBySide => ((i / width) as usize, i % width, j),
+            StereoPacking::TopBottom => ((j / height) as usize, i, j % height),
+        }
+    }
+}
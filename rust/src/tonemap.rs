@@ -0,0 +1,316 @@
+//! Tone mapping: bringing the linear radiance of a render into the [0, 1] a display shows,
+//! after scaling it by the exposure. The result is still linear; the sRGB curve is applied
+//! when the image is encoded in 8 bits.
+
+use crate::color::{luminance, Color};
+use crate::image::Image;
+
+/// The curves from scene radiance to display values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    /// no curve: everything past 1 is clipped
+    Clamp,
+    /// L / (1 + L) on the luminance, keeping hues; never quite reaches white
+    Reinhard,
+    /// Reinhard stretched so luminance `white` maps to 1, the brightest in the image if none
+    ExtendedReinhard {
+        /// the luminance shown as white
+        white: Option<f64>,
+    },
+    /// John Hable's filmic curve from Uncharted 2, per channel
+    Hable,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms
+    Aces,
+    /// Troy Sobotka's AgX, in Benjamin Wrensch's polynomial approximation, which desaturates
+    /// bright colors toward white rather than skewing their hues
+    Agx,
+}
+
+impl ToneMapper {
+    /// the names `from_name` knows, in the order of the variants
+    pub const NAMES: [&'static str; 6] = [
+        "clamp",
+        "reinhard",
+        "extended-reinhard",
+        "hable",
+        "aces",
+        "agx",
+    ];
+
+    /// the mapper called `name` on the command line; `extended-reinhard=<white>` sets the
+    /// white point
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.split_once('=') {
+            Some(("extended-reinhard", white)) => match white.parse::<f64>() {
+                Ok(white) if white > 0.0 => Some(Self::ExtendedReinhard { white: Some(white) }),
+                _ => None,
+            },
+            Some(_) => None,
+            None => match name {
+                "clamp" => Some(Self::Clamp),
+                "reinhard" => Some(Self::Reinhard),
+                "extended-reinhard" => Some(Self::ExtendedReinhard { white: None }),
+                "hable" => Some(Self::Hable),
+                "aces" => Some(Self::Aces),
+                "agx" => Some(Self::Agx),
+                _ => None,
+            },
+        }
+    }
+
+    /// the display value for `color`, clamped to [0, 1]; `white` is the luminance extended
+    /// Reinhard maps to 1
+    fn map(&self, color: &Color, white: f64) -> Color {
+        let mapped = match self {
+            Self::Clamp => *color,
+            Self::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            Self::ExtendedReinhard { .. } => {
+                scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            Self::Hable => per_channel(color, |x| hable(2.0 * x) / hable(HABLE_WHITE)),
+            Self::Aces => aces(color),
+            Self::Agx => agx(color),
+        };
+
+        per_channel(&mapped, |x| f64::clamp(x, 0.0, 1.0))
+    }
+}
+
+/// An exposure and the curve to map the exposed image with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    mapper: ToneMapper,
+    exposure: f64,
+}
+
+impl ToneMapping {
+    /// `mapper` at the exposure the image was rendered with
+    pub fn new(mapper: ToneMapper) -> Self {
+        Self {
+            mapper,
+            exposure: 0.0,
+        }
+    }
+
+    /// the curve
+    pub fn mapper(&self) -> ToneMapper {
+        self.mapper
+    }
+
+    /// the exposure in stops: every one doubles the light
+    pub fn exposure(&self) -> f64 {
+        self.exposure
+    }
+
+    /// scales the light by 2^`stops` before the curve
+    pub fn set_exposure(&mut self, stops: f64) -> &mut Self {
+        self.exposure = stops;
+        self
+    }
+
+    /// the exposure as the factor the light is multiplied by, which must be positive
+    pub fn set_exposure_scale(&mut self, scale: f64) -> &mut Self {
+        self.exposure = f64::log2(scale);
+        self
+    }
+
+    /// the image multiplied by the exposure, without a curve, for formats that keep the
+    /// values past 1
+    pub fn expose(&self, image: &Image) -> Image {
+        let scale = f64::exp2(self.exposure);
+        let mut exposed = image.clone();
+
+        for pixel in exposed.pixels_mut() {
+            *pixel *= scale;
+        }
+
+        exposed
+    }
+
+    /// the image exposed and mapped to display values in [0, 1]
+    pub fn apply(&self, image: &Image) -> Image {
+        let mut mapped = self.expose(image);
+        let white = match self.mapper {
+            ToneMapper::ExtendedReinhard { white: Some(white) } => white,
+            _ => mapped
+                .pixels()
+                .iter()
+                .map(luminance)
+                .fold(0.0, f64::max)
+                .max(f64::MIN_POSITIVE),
+        };
+
+        for pixel in mapped.pixels_mut() {
+            *pixel = self.mapper.map(pixel, white);
+        }
+
+        mapped
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self::new(ToneMapper::Clamp)
+    }
+}
+
+fn per_channel(color: &Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(color.x()), f(color.y()), f(color.z()))
+}
+
+/// `color` with its luminance L taken to `curve(L)`
+fn scale_luminance(color: &Color, curve: impl Fn(f64) -> f64) -> Color {
+    let l = luminance(color);
+
+    if l <= 0.0 {
+        return Color::new_default();
+    }
+
+    *color * (curve(l) / l)
+}
+
+fn multiply(matrix: &[[f64; 3]; 3], color: &Color) -> Color {
+    let row = |r: &[f64; 3]| r[0] * color.x() + r[1] * color.y() + r[2] * color.z();
+
+    Color::new(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]))
+}
+
+/// the linear value Hable's curve reaches white at
+const HABLE_WHITE: f64 = 11.2;
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+fn aces(color: &Color) -> Color {
+    // sRGB into the ACES working space, and back after the curve
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    let curve = |v: f64| {
+        let a = v * (v + 0.024_578_6) - 0.000_090_537;
+        let b = v * (0.983_729 * v + 0.432_951) + 0.238_081;
+        a / b
+    };
+
+    multiply(&OUTPUT, &per_channel(&multiply(&INPUT, color), curve))
+}
+
+fn agx(color: &Color) -> Color {
+    // into and out of the AgX working space, which squeezes colors toward gray
+    const INSET: [[f64; 3]; 3] = [
+        [
+            0.842_479_062_253_094,
+            0.078_433_599_999_999_2,
+            0.079_223_745_147_764_3,
+        ],
+        [
+            0.042_328_242_261_012_3,
+            0.878_468_636_469_772,
+            0.079_166_127_460_543_4,
+        ],
+        [0.042_375_654_905_705_1, 0.078_433_6, 0.879_142_973_793_104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [
+            1.196_879_005_120_17,
+            -0.098_020_881_140_136_8,
+            -0.099_029_744_079_720_5,
+        ],
+        [
+            -0.052_896_851_757_456_2,
+            1.151_903_129_904_17,
+            -0.098_961_176_844_843_3,
+        ],
+        [
+            -0.052_971_635_514_443_8,
+            -0.098_043_450_117_124_1,
+            1.151_073_672_641_16,
+        ],
+    ];
+    // the range of stops around middle gray the curve spans
+    const MIN_EV: f64 = -12.473_93;
+    const MAX_EV: f64 = 4.026_069;
+
+    let encoded = per_channel(&multiply(&INSET, color), |x| {
+        let stops = if x > 0.0 { f64::log2(x) } else { MIN_EV };
+        let x = (f64::clamp(stops, MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let (x2, x4) = (x * x, x * x * x * x);
+
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+
+    // the curve's output is meant for a display with a 2.2 gamma
+    per_channel(&multiply(&OUTSET, &encoded), |x| {
+        f64::powf(f64::max(x, 0.0), 2.2)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: f64) -> Color {
+        Color::new(value, value, value)
+    }
+
+    #[test]
+    fn curves_rise_from_black_and_stay_on_the_display() {
+        for name in ToneMapper::NAMES {
+            let mapper = ToneMapper::from_name(name).unwrap();
+            let mut previous = -1.0;
+
+            for stop in -8..12 {
+                let mapped = mapper.map(&gray(f64::exp2(stop as f64)), 16.0);
+
+                assert!((0.0..=1.0).contains(&mapped.y()), "{name}");
+                assert!(mapped.y() >= previous, "{name} falls at {stop} stops");
+                previous = mapped.y();
+            }
+
+            assert!(mapper.map(&gray(0.0), 16.0).y() < 0.01, "{name}");
+        }
+    }
+
+    #[test]
+    fn reinhard_halves_one_and_extended_reinhard_reaches_white() {
+        let image = Image::from_fn(2, 1, |i, _| gray([1.0, 4.0][i as usize]));
+
+        let reinhard = ToneMapping::new(ToneMapper::Reinhard).apply(&image);
+        assert!(f64::abs(reinhard.pixel(0, 0).x() - 0.5) < 1e-12);
+
+        let extended = ToneMapping::new(ToneMapper::ExtendedReinhard { white: None }).apply(&image);
+        assert!(f64::abs(extended.pixel(1, 0).x() - 1.0) < 1e-12);
+
+        // with white held at 4, a stop down no longer reaches it
+        let mut darker = ToneMapping::new(ToneMapper::ExtendedReinhard { white: Some(4.0) });
+        darker.set_exposure(-1.0);
+        assert!(darker.apply(&image).pixel(1, 0).x() < 1.0);
+        assert_eq!(darker.expose(&image).pixel(1, 0), gray(2.0));
+
+        darker.set_exposure_scale(4.0);
+        assert!(f64::abs(darker.exposure() - 2.0) < 1e-12);
+    }
+
+    #[test]
+    fn names_parse() {
+        assert_eq!(
+            ToneMapper::from_name("extended-reinhard=6"),
+            Some(ToneMapper::ExtendedReinhard { white: Some(6.0) })
+        );
+        assert_eq!(ToneMapper::from_name("extended-reinhard=-1"), None);
+        assert_eq!(ToneMapper::from_name("aces=1"), None);
+        assert_eq!(ToneMapper::from_name("filmic"), None);
+    }
+}
@@ -1,55 +1,211 @@
+//! Constants, random numbers and hashing used throughout.
+
 use rand::Rng;
+use std::cell::RefCell;
+use std::hash::Hasher;
+use std::rc::Rc;
 
 // constants
-pub const INFINITY: f64 = std::f64::INFINITY; 
-pub const NEG_INFINITY: f64 = std::f64::NEG_INFINITY;
+/// positive infinity
+pub const INFINITY: f64 = f64::INFINITY; 
+/// negative infinity
+pub const NEG_INFINITY: f64 = f64::NEG_INFINITY;
+/// π
 pub const PI: f64 = std::f64::consts::PI;
 
+/// converts `degrees` to radians
 #[inline]
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+/// Where `random_double` takes its numbers from. Each thread draws from its own
+/// `rand::thread_rng` unless a source is installed with `with_sample_source`, which lets
+/// an integrator record, replay or mutate the numbers a path is built from.
+pub trait SampleSource {
+    /// a number in [0, 1)
+    fn next(&mut self) -> f64;
+
+    /// two numbers meant to be used together, e.g. as a point on the pixel or the lens,
+    /// which lets well stratified sources spread the pairs over the unit square
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next(), self.next())
+    }
+}
+
+thread_local! {
+    static SAMPLE_SOURCE: RefCell<Option<Rc<RefCell<dyn SampleSource>>>> = const { RefCell::new(None) };
+}
+
+/// runs `f` with every `random_double` on this thread drawn from `source`
+pub fn with_sample_source<R>(source: Rc<RefCell<dyn SampleSource>>, f: impl FnOnce() -> R) -> R {
+    let previous = SAMPLE_SOURCE.with(|current| current.replace(Some(source)));
+    let result = f();
+
+    SAMPLE_SOURCE.with(|current| *current.borrow_mut() = previous);
+
+    result
+}
+
+/// a number in [0, 1), from the thread's sample source if one is installed
 #[inline]
 pub fn random_double() -> f64 {
-    rand::thread_rng().gen_range(0.0..1.0)
+    SAMPLE_SOURCE.with(|current| match current.borrow().as_ref() {
+        Some(source) => source.borrow_mut().next(),
+        None => rand::thread_rng().gen_range(0.0..1.0),
+    })
+}
+
+/// two numbers in [0, 1) meant to be used together, see `SampleSource::next_2d`
+#[inline]
+pub fn random_double_2d() -> (f64, f64) {
+    SAMPLE_SOURCE.with(|current| match current.borrow().as_ref() {
+        Some(source) => source.borrow_mut().next_2d(),
+        None => {
+            let mut rng = rand::thread_rng();
+            (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0))
+        }
+    })
 }
 
+/// a number in [min, max), drawn like `random_double`
 #[inline]
 pub fn random_double_in_range(min: f64, max: f64) -> f64 {
-    rand::thread_rng().gen_range(min..max)
+    min + (max - min) * random_double()
+}
+
+/// PCG32 (O'Neill, pcg-random.org): small, fast and seedable, with independent streams
+#[derive(Clone)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    /// the generator seeded with `seed`, on its own `stream` of numbers
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+
+        rng
+    }
+
+    /// the next 32 random bits
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(self.increment);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+
+        xorshifted.rotate_right(rotation)
+    }
+}
+
+impl SampleSource for Pcg32 {
+    fn next(&mut self) -> f64 {
+        // 53 random bits fill the whole mantissa
+        let bits = (((self.next_u32() as u64) << 32) | self.next_u32() as u64) >> 11;
+
+        bits as f64 * (1.0 / (1u64 << 53) as f64)
+    }
 }
 
+/// folds several values into one well-mixed seed (splitmix64 finalizer)
+pub fn mix_seed(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |hash, value| {
+        let mut z = (hash ^ value).wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+
+        z ^ (z >> 31)
+    })
+}
+
+/// FNV-1a, a hash that stays the same from run to run and across compiler versions,
+/// unlike the standard library's
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fnv1a {
+    /// the hash of nothing
+    pub fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// hashes floats by their bits
+pub fn hash_f64s(state: &mut dyn Hasher, values: &[f64]) {
+    for value in values {
+        state.write_u64(value.to_bits());
+    }
+}
+
+/// Intervals of the real line, e.g. of ray parameters.
 pub mod interval {
     use super::{INFINITY, NEG_INFINITY};
 
+    /// The closed interval [min, max], empty if min > max.
     #[derive(Clone, Copy)]
     pub struct Interval {
+        /// the lower bound
         pub min: f64, 
+        /// the upper bound
         pub max: f64
     }
 
     impl Interval {
+        /// the whole real line
         pub const fn new_default() -> Self {
             Self { min: NEG_INFINITY, max: INFINITY }
         } 
 
+        /// the interval [min, max]
         pub const fn new(min: f64, max: f64) -> Self {
             Self { min, max }
         }
 
+        /// max - min, negative when empty
         pub fn size(&self) -> f64 {
             self.max - self.min
         }
 
+        /// whether `x` is in the interval, bounds included
         pub fn contains(&self, x: f64) -> bool {
             self.min <= x && x <= self.max
         }
 
+        /// whether `x` is in the interval, bounds excluded
         pub fn surrounds(&self, x: f64) -> bool {
             self.min < x && x < self.max
         }
 
+        /// `x` moved into the interval
         pub fn clamps(&self, x: f64) -> f64 {
             if x < self.min {
                 self.min
@@ -61,6 +217,40 @@ pub mod interval {
         }
     }
 
+    /// the interval with nothing in it
     pub static EMPTY: Interval = Interval::new(INFINITY, -INFINITY);
+    /// the whole real line
     pub static UNIVERSE: Interval = Interval::new(-INFINITY, INFINITY);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::random_unit_vector;
+
+    #[test]
+    fn seeded_sources_replay_the_same_numbers() {
+        let draw = |seed: u64| {
+            let source: Rc<RefCell<dyn SampleSource>> = Rc::new(RefCell::new(Pcg32::new(seed, 3)));
+
+            with_sample_source(source, || {
+                (0..8)
+                    .map(|_| (random_double(), random_unit_vector()))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+    }
+
+    #[test]
+    fn pcg32_stays_in_unit_interval() {
+        let mut rng = Pcg32::new(mix_seed(&[1, 2, 3]), 0);
+
+        for _ in 0..10_000 {
+            let x = rng.next();
+            assert!((0.0..1.0).contains(&x));
+        }
+    }
+}
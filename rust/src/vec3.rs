@@ -1,8 +1,11 @@
+//! Vectors in 3D space and the random directions sampling draws.
+
 use std::fmt::Display;
 use std::ops::{Add, AddAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-use crate::util::{self, random_double, random_double_in_range};
+use crate::util;
 
+/// A vector in 3D space, also serving as a point and an RGB color.
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub struct Vector3(f64, f64, f64);
 
@@ -85,34 +88,42 @@ impl Display for Vector3 {
 }
 
 impl Vector3 {
+    /// the vector (x, y, z)
     pub fn new(x: f64, y: f64, z: f64) -> Self {
         Self(x, y, z)
     }
 
+    /// the zero vector
     pub fn new_default() -> Self {
         Self::new(0.0, 0.0, 0.0)
     }
 
+    /// the first component
     pub fn x(&self) -> f64 {
         self.0
     }
 
+    /// the second component
     pub fn y(&self) -> f64 {
         self.1
     }
 
+    /// the third component
     pub fn z(&self) -> f64 {
         self.2
     }
 
+    /// the Euclidean length
     pub fn length(&self) -> f64 {
         f64::sqrt(self.length_squared())
     }
 
+    /// the length squared, cheaper than `length`
     pub fn length_squared(&self) -> f64 {
         self.0 * self.0 + self.1 * self.1 + self.2 * self.2
     }
 
+    /// the vector scaled to unit length; fails for vectors too short to scale
     pub fn normalize(&self) -> Result<Self, &'static str> {
         let length = self.length_squared();
 
@@ -120,13 +131,14 @@ impl Vector3 {
             return Err("The vector is not normalizable: the length is too short");
         }
 
-        let mut clone = self.clone();
+        let mut clone = *self;
 
         clone *= 1.0 / clone.length();
 
         Ok(clone)
     }
 
+    /// whether every component is close to 0
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
 
@@ -134,11 +146,13 @@ impl Vector3 {
     }
 }
 
+/// the dot product
 #[inline]
 pub fn dot(v1: &Vector3, v2: &Vector3) -> f64 {
     v1.0 * v2.0 + v1.1 * v2.1 + v1.2 * v2.2
 }
 
+/// the cross product, following the right hand rule
 #[inline]
 pub fn cross(v1: &Vector3, v2: &Vector3) -> Vector3 {
     Vector3(
@@ -148,6 +162,7 @@ pub fn cross(v1: &Vector3, v2: &Vector3) -> Vector3 {
     )
 }
 
+/// components uniform in [0, 1)
 #[inline]
 pub fn random() -> Vector3 {
     Vector3::new(
@@ -157,6 +172,7 @@ pub fn random() -> Vector3 {
     )
 }
 
+/// components uniform in [min, max)
 #[inline]
 pub fn random_in_range(min: f64, max: f64) -> Vector3 {
     Vector3::new(
@@ -166,23 +182,20 @@ pub fn random_in_range(min: f64, max: f64) -> Vector3 {
     )
 } 
 
+/// uniform on the unit sphere
 #[inline]
-fn random_unit_vector() -> Vector3 {
-    let mut p: Vector3;
-
-    loop {
-        p = random_in_range(-1.0, 1.0);
-
-        if p.length_squared() < 1e-160 {
-            continue
-        }
-
-        break
-    }
-
-    p.normalize().unwrap()
+pub fn random_unit_vector() -> Vector3 {
+    // uniform height and angle around the axis cover the sphere uniformly (Archimedes),
+    // and use exactly two numbers so stratified samplers stay stratified
+    let (u, v) = util::random_double_2d();
+    let z = 1.0 - 2.0 * u;
+    let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+    let phi = 2.0 * util::PI * v;
+
+    Vector3::new(r * f64::cos(phi), r * f64::sin(phi), z)
 }
 
+/// uniform on the unit hemisphere around `normal`
 #[inline]
 pub fn random_on_hemisphere(normal: &Vector3) -> Vector3 {
     let random_vec = random_unit_vector();
@@ -194,36 +207,44 @@ pub fn random_on_hemisphere(normal: &Vector3) -> Vector3 {
     }
 }
 
+/// `v` mirrored about the unit normal `n`
 #[inline]
 pub fn reflect(v: &Vector3, n: &Vector3) -> Vector3 {
-    v.clone() - 2.0 * dot(v, n) * n.clone()
+    *v - 2.0 * dot(v, n) * *n
 }
 
+/// the unit vector `uv` bent through a surface with unit normal `n` by Snell's law
 #[inline]
 pub fn refract(uv: &Vector3, n: &Vector3, etai_over_etat: f64) -> Vector3 {
-    let uv_outward = -uv.clone();
+    let uv_outward = -*uv;
     let cos_theta = f64::min(dot(&uv_outward, n), 1.0);
 
-    let r_out_perp = etai_over_etat * (uv.clone() + cos_theta * n.clone());
-    let r_out_parallel = -f64::sqrt(f64::abs(1.0 - r_out_perp.length_squared())) * n.clone();
+    let r_out_perp = etai_over_etat * (*uv + cos_theta * *n);
+    let r_out_parallel = -f64::sqrt(f64::abs(1.0 - r_out_perp.length_squared())) * *n;
 
     r_out_parallel + r_out_perp
 }
 
-
+/// uniform on the unit disk in the xy plane
 #[inline]
 pub fn random_in_unit_disk() -> Vector3 {
-    loop {
-        let x = random_double_in_range(-1.0, 1.0);
-        let y = random_double_in_range(-1.0, 1.0);
-        let p = Vector3::new(x, y, 0.0);
+    // concentric mapping (Shirley and Chiu) of a point in the square onto the disk,
+    // which keeps neighbouring samples close together
+    let (u, v) = util::random_double_2d();
+    let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
 
-        if p.length_squared() < 1.0 {
-            return p
-        }
+    if x == 0.0 && y == 0.0 {
+        return Vector3::new(0.0, 0.0, 0.0);
     }
-}
 
+    let (r, theta) = if f64::abs(x) > f64::abs(y) {
+        (x, util::PI / 4.0 * (y / x))
+    } else {
+        (y, util::PI / 2.0 - util::PI / 4.0 * (x / y))
+    };
+
+    Vector3::new(r * f64::cos(theta), r * f64::sin(theta), 0.0)
+}
 
 #[cfg(test)]
 mod tests {
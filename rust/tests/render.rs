@@ -0,0 +1,286 @@
+use std::hash::Hasher;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust::aov::Aov;
+use rust::camera::{Builder, Camera};
+use rust::checkpoint::Checkpoint;
+use rust::color::Color;
+use rust::compare::compare;
+use rust::denoise::Denoiser;
+use rust::firefly::{Limit, RadianceClamp};
+use rust::geometry::hittable::{HitRecord, Hittable, HittableList};
+use rust::geometry::sphere::Sphere;
+use rust::image::{ppm, Image};
+use rust::material::{DiffuseLight, Lambertian};
+use rust::point::Point3;
+use rust::ray::Ray;
+use rust::util::interval::Interval;
+use rust::vec3::Vector3;
+
+fn scene() -> Arc<HittableList> {
+    let mut world = HittableList::new();
+    let gray = Arc::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+    let light = Arc::new(DiffuseLight::new(&Color::new(4.0, 4.0, 4.0)));
+
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, -100.5, -1.0),
+        100.0,
+        gray.clone(),
+    )));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, 0.0, -1.0),
+        0.5,
+        gray,
+    )));
+    world.add(Arc::new(Sphere::new(
+        &Point3::new(0.0, 2.0, -1.0),
+        0.5,
+        light,
+    )));
+
+    Arc::new(world)
+}
+
+fn builder(seed: u64) -> Builder {
+    let mut builder = Builder::new();
+    builder
+        .set_image_width(24)
+        .set_image_aspect_ratio(3.0 / 2.0)
+        .set_samples_per_pixel(4)
+        .set_max_depth(8)
+        .set_vfov(60.0)
+        .set_lookfrom(&Point3::new(0.0, 0.5, 1.0))
+        .set_lookat(&Point3::new(0.0, 0.0, -1.0))
+        .set_vup(&Vector3::new(0.0, 1.0, 0.0))
+        .set_focus_dist(2.0)
+        .set_seed(seed)
+        .set_threads(2);
+
+    builder
+}
+
+fn camera(seed: u64) -> Camera {
+    builder(seed).build()
+}
+
+#[test]
+fn renders_linear_radiance_of_the_requested_size() {
+    let image = camera(1).render(scene()).unwrap();
+
+    assert_eq!((image.width(), image.height()), (24, 16));
+    assert_eq!(image.pixels().len(), 24 * 16);
+    assert!(image.pixels().iter().all(|pixel| {
+        [pixel.x(), pixel.y(), pixel.z()]
+            .iter()
+            .all(|value| value.is_finite() && *value >= 0.0)
+    }));
+    // the light is far brighter than a byte can hold, which the image keeps
+    assert!(image.pixels().iter().any(|pixel| pixel.x() > 1.0));
+}
+
+#[test]
+fn images_encode_as_plain_ppm() {
+    let image = camera(1).render(scene()).unwrap();
+    let mut output = vec![];
+
+    ppm::write_plain(&image, &mut output).unwrap();
+
+    let text = String::from_utf8(output).unwrap();
+    let mut lines = text.lines();
+
+    assert_eq!(lines.next(), Some("P3"));
+    assert_eq!(lines.next(), Some("24 16"));
+    assert_eq!(lines.next(), Some("255"));
+
+    let pixels: Vec<&str> = lines.collect();
+    assert_eq!(pixels.len(), 24 * 16);
+    assert!(pixels
+        .iter()
+        .all(|pixel| pixel.split(' ').all(|value| value.parse::<u8>().is_ok())));
+    assert!(pixels.iter().any(|&pixel| pixel != "0 0 0"));
+}
+
+#[test]
+fn seeded_renders_repeat() {
+    let render = |seed| camera(seed).render(scene()).unwrap();
+
+    assert_eq!(render(7), render(7));
+    assert_ne!(render(7), render(8));
+}
+
+#[test]
+fn aovs_describe_the_first_hit() {
+    let layers = builder(1)
+        .set_aovs(&Aov::ALL)
+        .build()
+        .render_layers(scene())
+        .unwrap();
+    let center = |aov| layers.aov(aov).unwrap().pixel(12, 8);
+    let corner = |aov| layers.aov(aov).unwrap().pixel(0, 0);
+
+    assert_eq!(layers.aovs.len(), Aov::ALL.len());
+    assert_eq!(layers.beauty, camera(1).render(scene()).unwrap());
+
+    // the gray sphere in the middle is the second object, of the first material
+    assert_eq!(center(Aov::ObjectId).x(), 2.0);
+    assert_eq!(center(Aov::MaterialId).x(), 1.0);
+    assert_eq!(center(Aov::Alpha).x(), 1.0);
+    assert!((center(Aov::Albedo).x() - 0.5).abs() < 1e-9);
+    assert!(center(Aov::Normal).z() > 0.5);
+    assert!((center(Aov::Depth).x() - 1.56).abs() < 0.1);
+    assert!((center(Aov::Position).z() + 0.5).abs() < 0.1);
+
+    // the sky
+    assert_eq!(corner(Aov::ObjectId).x(), 0.0);
+    assert_eq!(corner(Aov::Alpha).x(), 0.0);
+}
+
+#[test]
+fn denoising_brings_a_noisy_render_closer_to_a_converged_one() {
+    let layers = builder(1)
+        .set_aovs(&[Aov::Albedo, Aov::Normal])
+        .build()
+        .render_layers(scene())
+        .unwrap();
+    let reference = builder(2)
+        .set_samples_per_pixel(1024)
+        .build()
+        .render(scene())
+        .unwrap();
+    let mse = |image: &Image| {
+        image
+            .pixels()
+            .iter()
+            .zip(reference.pixels())
+            .map(|(a, b)| (*a - *b).length_squared())
+            .sum::<f64>()
+            / image.pixels().len() as f64
+    };
+
+    let denoised = Denoiser::new().denoise_layers(&layers).unwrap();
+
+    assert!(mse(&denoised) < mse(&layers.beauty) / 2.0);
+    assert!(Denoiser::new()
+        .denoise_layers(&camera(1).render_layers(scene()).unwrap())
+        .is_err());
+}
+
+#[test]
+fn clamping_only_takes_light_away() {
+    let mut clamp = RadianceClamp::new();
+    clamp
+        .set_direct(Limit::MaxComponent(0.5))
+        .set_indirect(Limit::Luminance(0.1));
+    let clamped = builder(3)
+        .set_radiance_clamp(clamp)
+        .build()
+        .render(scene())
+        .unwrap();
+    let unclamped = camera(3).render(scene()).unwrap();
+    let pairs = || clamped.pixels().iter().zip(unclamped.pixels());
+
+    // the same samples are taken, just with their brightest parts cut back
+    assert!(pairs().all(|(a, b)| a.x() <= b.x() && a.y() <= b.y() && a.z() <= b.z()));
+    assert!(pairs().any(|(a, b)| a.x() < b.x()));
+}
+
+#[test]
+fn saved_renders_load_back_and_compare() {
+    let image = camera(1).render(scene()).unwrap();
+    let directory = std::env::temp_dir().join(format!("render-compare-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    for extension in ["ppm", "png", "pfm", "exr"] {
+        let path = directory.join(format!("image.{extension}"));
+        image.save(&path).unwrap();
+
+        let loaded = Image::load(&path).unwrap();
+        let comparison = compare(&image, &loaded).unwrap();
+
+        assert_eq!((loaded.width(), loaded.height()), (24, 16));
+        // 8-bit formats clip the light and round, which leaves an error but hardly a visible one
+        assert!(comparison.flip < 0.05, "{extension}: {comparison}");
+        assert!(comparison.ssim > 0.99, "{extension}: {comparison}");
+    }
+
+    assert!(Image::load(&directory.join("image.bmp")).is_err());
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let noisier = builder(2)
+        .set_samples_per_pixel(1)
+        .build()
+        .render(scene())
+        .unwrap();
+    assert!(compare(&image, &noisier).unwrap().flip > 0.1);
+}
+
+/// The scene, for a render that is killed after so many rays: hitting it panics once
+/// `budget` runs out.
+struct Interrupting {
+    scene: Arc<HittableList>,
+    hits: AtomicUsize,
+    budget: usize,
+}
+
+impl Interrupting {
+    fn world(budget: usize) -> (Arc<HittableList>, Arc<Interrupting>) {
+        let interrupting = Arc::new(Interrupting {
+            scene: scene(),
+            hits: AtomicUsize::new(0),
+            budget,
+        });
+        let mut world = HittableList::new();
+        world.add(interrupting.clone());
+
+        (Arc::new(world), interrupting)
+    }
+}
+
+impl Hittable for Interrupting {
+    fn hit(&self, ray: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if self.hits.fetch_add(1, Ordering::Relaxed) >= self.budget {
+            panic!("render interrupted");
+        }
+
+        self.scene.hit(ray, ray_t, rec)
+    }
+
+    fn fingerprint(&self, state: &mut dyn Hasher) {
+        self.scene.fingerprint(state);
+    }
+}
+
+#[test]
+fn interrupted_renders_resume_from_the_last_batch_of_tiles() {
+    let path = std::env::temp_dir().join(format!("render-resume-{}.ckpt", std::process::id()));
+    let camera = |resume: bool| {
+        let mut checkpoint = Checkpoint::new(path.to_str().unwrap());
+        checkpoint.set_interval(Duration::ZERO).set_resume(resume);
+        builder(1)
+            .set_tile_size(4)
+            .set_checkpoint(checkpoint)
+            .build()
+    };
+
+    let (world, uninterrupted) = Interrupting::world(usize::MAX);
+    let expected = camera(false).render(world).unwrap();
+    let hits = uninterrupted.hits.load(Ordering::Relaxed);
+    std::fs::remove_file(&path).unwrap();
+
+    // a single pass, killed halfway through its tiles
+    let (world, _) = Interrupting::world(hits / 2);
+    let interrupted = panic::catch_unwind(AssertUnwindSafe(|| camera(false).render(world)));
+    assert!(interrupted.is_err());
+    assert!(path.exists());
+
+    let (world, resumed) = Interrupting::world(usize::MAX);
+    let image = camera(true).render(world).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(image.pixels(), expected.pixels());
+    // the tiles saved before the interruption aren't rendered again
+    assert!(resumed.hits.load(Ordering::Relaxed) < hits);
+}